@@ -34,9 +34,9 @@ impl<In> Leaf<In> {
 
             Leaf::Group(ref group) => Leaf::Group(group.map(f)?),
 
-            Leaf::Repeat { ref prefix, times } => {
+            Leaf::Repeat { ref prefix, times, lazy } => {
                 let prefix = Box::new(prefix.map(f)?);
-                Leaf::Repeat { prefix, times }
+                Leaf::Repeat { prefix, times, lazy }
             },
 
             Leaf::Raw(ref string) => Leaf::Raw(string.clone()),
@@ -211,43 +211,65 @@ impl Compiler {
                 }
             },
 
-            Leaf::Repeat { ref prefix, times, .. } => {
-                self.tr_repeat(prefix, times)
+            Leaf::Repeat { ref prefix, times, lazy } => {
+                self.tr_repeat(prefix, times, lazy)
             },
         }
     }
 
-    fn tr_repeat(&mut self, prefix: &Leaf<String>, times: Repeat) {
+    fn tr_repeat(&mut self, prefix: &Leaf<String>, times: Repeat, lazy: bool) {
         let (min, max) = match times {
             Repeat::OneOrZero => (0, Some(1)),
             Repeat::ZeroOrMore => (0, None),
             Repeat::OneOrMore => (1, None),
             Repeat::Count(u) => (u, Some(u)),
+            Repeat::Between(min, max) => (min, max),
         };
 
-        let max = max.unwrap_or(usize::max_value());
+        // Each loop body runs at least once, so skip empty loops entirely
+        let extra = max.map_or(usize::MAX, |max| max - min);
 
         let sp = self.next_sp;
         self.next_sp += 1;
 
-        let loop1 = self.gensym();
-        self.emit(Op::POINT { sp });
-        self.emit(Op::MOV { ix: min });
-        self.label(loop1);
-        self.tr_leaf(prefix);
-        self.emit(Op::POINT { sp });
-        self.emit(Op::LOOP { label: loop1 });
-
-        let loop2 = self.gensym();
-        let exit = self.gensym();
-        self.emit(Op::MOV { ix: max });
-        self.label(loop2);
-        self.emit(Op::MARK { label: exit });
-        self.tr_leaf(prefix);
-        self.emit(Op::POINT { sp });
-        self.emit(Op::LOOP { label: loop2 });
-
-        self.label(exit);
+        if min > 0 {
+            let loop1 = self.gensym();
+            self.emit(Op::POINT { sp });
+            self.emit(Op::MOV { ix: min });
+            self.label(loop1);
+            self.tr_leaf(prefix);
+            self.emit(Op::POINT { sp });
+            self.emit(Op::LOOP { label: loop1 });
+        }
+
+        if extra > 0 && lazy {
+            // Leave the loop first, and only come back for another round
+            // if the rest of the pattern fails
+            let loop2 = self.gensym();
+            let body = self.gensym();
+            let exit = self.gensym();
+            self.emit(Op::POINT { sp });
+            self.emit(Op::MOV { ix: extra });
+            self.label(loop2);
+            self.emit(Op::MARK { label: body });
+            self.emit(Op::JUMP { label: exit });
+            self.label(body);
+            self.tr_leaf(prefix);
+            self.emit(Op::POINT { sp });
+            self.emit(Op::LOOP { label: loop2 });
+            self.label(exit);
+        } else if extra > 0 {
+            let loop2 = self.gensym();
+            let exit = self.gensym();
+            self.emit(Op::POINT { sp });
+            self.emit(Op::MOV { ix: extra });
+            self.label(loop2);
+            self.emit(Op::MARK { label: exit });
+            self.tr_leaf(prefix);
+            self.emit(Op::POINT { sp });
+            self.emit(Op::LOOP { label: loop2 });
+            self.label(exit);
+        }
     }
 
     fn tr_string(&mut self, string: &str) {
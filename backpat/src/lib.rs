@@ -95,3 +95,46 @@ fn backtracking() {
     assert_match!("/CASE/i", "case", "case");
     assert_match!("/.+b/", "aaabc", "aaab");
 }
+
+#[test]
+fn unanchored_offsets() {
+    assert_match!("/b(c)/", "abcd", "bc", "c");
+    assert_match!("/\\d+/", "abc 123", "123");
+}
+
+#[test]
+fn repeat_last_char() {
+    assert_match!("/ab+/", "abbb", "abbb");
+    assert_match!("/, */", "x,y", ",");
+    assert_match!("/(ab)+/", "ababc", "abab");
+}
+
+#[test]
+fn optional_repeats() {
+    assert_match!("/ab*c/", "ac", "ac");
+    assert_match!("/ab?c/", "ac", "ac");
+    assert_match!("/ab?c/", "abc", "abc");
+    assert_match!("/a{2}/", "aaaa", "aa");
+}
+
+#[test]
+fn lazy_repeats() {
+    assert_match!("/<(.+?)>/", "<a><b>", "<a>", "a");
+    assert_match!("/<(.+)>/", "<a><b>", "<a><b>", "a><b");
+    assert_match!("/a*?b/", "aab", "aab");
+    assert_match!("/ab+?/", "abbb", "ab");
+    assert_match!("/ab??/", "abc", "a");
+}
+
+#[test]
+fn bounded_repeats() {
+    assert_match!("/a{2,3}/", "aaaa", "aaa");
+    assert_match!("/a{2,}/", "aaaaa", "aaaaa");
+    assert_match!("/a{0,2}b/", "aaab", "aab");
+    assert_match!("/a{1,3}?/", "aaa", "a");
+    assert_match!("/x{0,}/", "y", "");
+
+    for bad in &["/a{3,2}/", "/a{,2}/", "/a{2,x}/"] {
+        assert!(parse::Ast::<String>::parse(&mut bad.chars().peekable()).is_err());
+    }
+}
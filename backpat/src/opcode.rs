@@ -70,13 +70,13 @@ impl<'a> Eval<'a> {
     pub fn eval(mut self) -> Option<Captures> {
         let haystack = self.haystack;
 
-        for (left, _) in haystack.char_indices() {
+        for (offset, _) in haystack.char_indices() {
             self.marks.clear();
             self.captures.clear();
             self.pc = 0;
             self.sp = 0;
             self.right = 0;
-            self.haystack = &haystack[left ..];
+            self.haystack = &haystack[offset ..];
 
             if self.eval_once() {
                 let mut captures = Captures::new();
@@ -87,8 +87,9 @@ impl<'a> Eval<'a> {
                         Delim::Left(group) => stack.push((group, index)),
 
                         Delim::Right => {
+                            // Spans are relative to the whole haystack
                             let (group, left) = stack.pop().unwrap();
-                            let span = (left, index);
+                            let span = (offset + left, offset + index);
 
                             if captures.contains_key(&group) {
                                 continue;
@@ -243,7 +244,7 @@ impl<'a> Eval<'a> {
             },
 
             Op::DIGIT => {
-                self.bump() && self.ch.is_digit(10)
+                self.bump() && self.ch.is_ascii_digit()
             },
 
             Op::SPACE => {
@@ -27,6 +27,8 @@ pub enum Leaf<Payload> {
     Repeat {
         prefix: Box<Leaf<Payload>>,
         times: Repeat,
+        /// Try as few repetitions as possible first
+        lazy: bool,
     },
     Payload(Payload),
 }
@@ -34,7 +36,7 @@ pub enum Leaf<Payload> {
 pub trait TokenStream<Payload> {
     fn lookahead(&mut self) -> Option<char>;
     fn getc(&mut self) -> Option<char>;
-    fn parse_payload(&mut self, char) -> Result<Payload>;
+    fn parse_payload(&mut self, sigil: char) -> Result<Payload>;
 }
 
 #[derive(Debug)]
@@ -62,6 +64,7 @@ pub enum Repeat {
     ZeroOrMore,
     OneOrMore,
     Count(usize),
+    Between(usize, Option<usize>),
 }
 
 struct Tree<Payload> {
@@ -155,25 +158,30 @@ impl<'a, P, T: TokenStream<P>> Parser<'a, P, T> {
                 },
 
                 '{' => {
-                    let mut digits = String::new();
+                    let min = self.parse_count()?.ok_or(Error::Bad)?;
 
-                    loop {
-                        let d = self.consume()?;
+                    let times = match self.consume()? {
+                        '}' => Repeat::Count(min),
 
-                        if d.is_digit(10) {
-                            digits.push(d);
-                        } else if d == '}' {
-                            break;
-                        } else {
-                            return Err(Error::Bad);
-                        }
-                    }
+                        ',' => {
+                            let max = self.parse_count()?;
+                            if self.consume()? != '}' {
+                                return Err(Error::Bad);
+                            }
 
-                    let count = digits.parse::<usize>().map_err(|_| {
-                        Error::Bad
-                    })?;
+                            match max {
+                                Some(max) if max < min => {
+                                    return Err(Error::Bad);
+                                },
+                                max => Repeat::Between(min, max),
+                            }
+                        },
+
+                        _ => return Err(Error::Bad),
+                    };
 
-                    tree.repeat(Repeat::Count(count))?;
+                    let lazy = self.lazy()?;
+                    tree.repeat(times, lazy)?;
                 },
 
                 '}' | ']' | ')' => {
@@ -208,15 +216,18 @@ impl<'a, P, T: TokenStream<P>> Parser<'a, P, T> {
                 },
 
                 '+' => {
-                    tree.repeat(Repeat::OneOrMore)?;
+                    let lazy = self.lazy()?;
+                    tree.repeat(Repeat::OneOrMore, lazy)?;
                 },
 
                 '*' => {
-                    tree.repeat(Repeat::ZeroOrMore)?;
+                    let lazy = self.lazy()?;
+                    tree.repeat(Repeat::ZeroOrMore, lazy)?;
                 },
 
                 '?' => {
-                    tree.repeat(Repeat::OneOrZero)?;
+                    let lazy = self.lazy()?;
+                    tree.repeat(Repeat::OneOrZero, lazy)?;
                 },
 
                 '\\' => {
@@ -245,6 +256,31 @@ impl<'a, P, T: TokenStream<P>> Parser<'a, P, T> {
         }
     }
 
+    /// Read the digits of a `{}` repeat, if there are any.
+    fn parse_count(&mut self) -> Result<Option<usize>> {
+        let mut digits = String::new();
+
+        while self.lookahead()?.is_ascii_digit() {
+            digits.push(self.consume()?);
+        }
+
+        if digits.is_empty() {
+            return Ok(None);
+        }
+
+        digits.parse::<usize>().map(Some).map_err(|_| Error::Bad)
+    }
+
+    /// Consume the `?` that makes the quantifier before it non-greedy.
+    fn lazy(&mut self) -> Result<bool> {
+        if self.lookahead()? == '?' {
+            self.consume()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     fn parse_class(&mut self) -> Result<Class> {
         let mut prev = None;
         let mut invert = false;
@@ -329,11 +365,25 @@ impl<Payload> Tree<Payload> {
         })
     }
 
-    fn repeat(&mut self, times: Repeat) -> Result<()> {
+    fn repeat(&mut self, times: Repeat, lazy: bool) -> Result<()> {
+        // A quantifier only applies to the last character of a literal
+        let split = self.last_mut().and_then(|string| {
+            if string.chars().count() > 1 {
+                string.pop()
+            } else {
+                None
+            }
+        });
+
+        if let Some(ch) = split {
+            self.push(Leaf::Raw(ch.to_string()));
+        }
+
         if let Some(leaf) = self.items.pop() {
             self.items.push(Leaf::Repeat {
                 prefix: Box::new(leaf),
                 times,
+                lazy,
             });
             Ok(())
         } else {
@@ -401,13 +451,25 @@ mod display {
 
                 Leaf::Class(ref class) => class.fmt(f),
 
-                Leaf::Repeat { ref prefix, times } => {
-                    write!(f, "{}{}", prefix, match times {
-                        Repeat::OneOrZero => "?",
-                        Repeat::OneOrMore => "+",
-                        Repeat::ZeroOrMore => "*",
-                        Repeat::Count(_) => "{...}",
-                    })
+                Leaf::Repeat { ref prefix, times, lazy } => {
+                    write!(f, "{}", prefix)?;
+
+                    match times {
+                        Repeat::OneOrZero => write!(f, "?")?,
+                        Repeat::OneOrMore => write!(f, "+")?,
+                        Repeat::ZeroOrMore => write!(f, "*")?,
+                        Repeat::Count(n) => write!(f, "{{{}}}", n)?,
+                        Repeat::Between(min, None) => {
+                            write!(f, "{{{},}}", min)?
+                        },
+                        Repeat::Between(min, Some(max)) => {
+                            write!(f, "{{{},{}}}", min, max)?
+                        },
+                    }
+
+                    if lazy { write!(f, "?")?; }
+
+                    Ok(())
                 },
 
                 Leaf::Payload(ref p) => {
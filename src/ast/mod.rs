@@ -1,20 +1,89 @@
+use lalrpop_util::ErrorRecovery;
+
 use super::*;
 use value::*;
 
+#[allow(clippy::all, unused_parens)]
 mod grammar {
     include!(concat!(env!("OUT_DIR"), "/ast/grammar.rs"));
 }
 
-pub use self::grammar::{parse_def, parse_block_body, parse_module};
+mod resolve;
+
+pub type ParseError = lalrpop_util::ParseError<usize, Token, Error>;
+
+type Recovered = Vec<ErrorRecovery<usize, Token, Error>>;
+
+/// Fail with the first syntax error, even one the parser recovered from.
+fn strict<T, F>(parse: F) -> Result<T, ParseError>
+    where F: FnOnce(&mut Recovered) -> Result<T, ParseError>
+{
+    let mut errors = vec![];
+    let result = parse(&mut errors);
+
+    match errors.into_iter().next() {
+        Some(recovered) => Err(recovered.error),
+        None => result,
+    }
+}
+
+pub fn parse_module<I>(tokens: I) -> Result<Module, ParseError>
+    where I: IntoIterator<Item=Result<(usize, Token, usize)>>
+{
+    strict(|errors| grammar::parse_module(errors, tokens))
+}
+
+pub fn parse_def<I>(tokens: I) -> Result<Def, ParseError>
+    where I: IntoIterator<Item=Result<(usize, Token, usize)>>
+{
+    strict(|errors| grammar::parse_def(errors, tokens))
+}
+
+pub fn parse_block_body<I>(tokens: I) -> Result<Vec<Stmt>, ParseError>
+    where I: IntoIterator<Item=Result<(usize, Token, usize)>>
+{
+    strict(|errors| grammar::parse_block_body(errors, tokens))
+}
+
+/// Parse as much of a module as possible, skipping bad statements, and
+/// return every syntax error found along the way.
+pub fn parse_module_recovering<I>(tokens: I) -> (Option<Module>, Vec<ParseError>)
+    where I: IntoIterator<Item=Result<(usize, Token, usize)>>
+{
+    let mut recovered = vec![];
+    let result = grammar::parse_module(&mut recovered, tokens);
+
+    let mut errors: Vec<_> = recovered.into_iter()
+        .map(|recovered| recovered.error)
+        .collect();
+
+    match result {
+        Ok(module) => (Some(module), errors),
+
+        Err(err) => {
+            errors.push(err);
+            (None, errors)
+        },
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Module {
+    /// Set by `package name;`, which qualifies every sub in the file
+    pub package: Option<Ident>,
+    /// Packages named by `import name;`, which the compiler loads from
+    /// `name.cy` beside this file
+    pub imports: Vec<Ident>,
     pub begin: Vec<Stmt>,
     pub defs: Vec<Def>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Def {
+    /// Byte offset of the `sub` keyword
+    pub pos: usize,
+    /// The `##` comment lines just before the sub, joined by newlines
+    pub doc: Option<Str>,
     pub name: Ident,
     pub args: Args,
     pub body: Vec<Stmt>,
@@ -23,7 +92,7 @@ pub struct Def {
 #[derive(Clone, Debug)]
 pub enum Stmt {
     My {
-        lhs: Ident,
+        lhs: Binding,
         rhs: Option<Expr>,
     },
 
@@ -32,12 +101,20 @@ pub enum Stmt {
         rhs: Expr,
     },
 
+    /// Compound assignment such as `lhs += rhs`
+    Update {
+        lhs: Expr,
+        op: Binop,
+        rhs: Expr,
+    },
+
     Return {
         rhs: Option<Expr>,
     },
 
     Assert {
         rhs: Expr,
+        message: Option<Expr>,
     },
 
     If {
@@ -45,16 +122,87 @@ pub enum Stmt {
         last: Vec<Stmt>,
     },
 
+    /// `if not test`, with an optional else block
+    Unless {
+        test: Expr,
+        body: Vec<Stmt>,
+        last: Vec<Stmt>,
+    },
+
     While {
         test: Expr,
         body: Vec<Stmt>,
     },
 
+    /// `while not test`
+    Until {
+        test: Expr,
+        body: Vec<Stmt>,
+    },
+
+    /// Runs the body of the first arm with a case that fits `subject`, or
+    /// else `last`
+    Given {
+        subject: Expr,
+        arms: Vec<(Vec<Case>, Vec<Stmt>)>,
+        last: Vec<Stmt>,
+    },
+
+    /// Counts from `lo` to `hi` inclusive
+    For {
+        var: Ident,
+        lo: Expr,
+        hi: Expr,
+        body: Vec<Stmt>,
+    },
+
+    Foreach {
+        var: Ident,
+        list: Expr,
+        body: Vec<Stmt>,
+    },
+
+    Try {
+        body: Vec<Stmt>,
+        var: Ident,
+        handler: Vec<Stmt>,
+    },
+
+    Throw {
+        rhs: Expr,
+    },
+
     Bare {
         rhs: Expr,
     },
 
     Nop,
+
+    /// A statement that begins at byte offset `pos` in the source
+    At {
+        pos: usize,
+        stmt: Box<Stmt>,
+    },
+}
+
+/// One way for a `when` arm to fit the subject of `given`
+#[derive(Clone, Debug)]
+pub enum Case {
+    /// Equal to the value
+    Value(Expr),
+    /// Of the named type, like `Int`
+    Type(Ident),
+    /// A string matching the pattern; the arm sees its groups
+    Match(Expr),
+}
+
+impl Case {
+    pub fn new(expr: Expr) -> Self {
+        match expr {
+            Expr::Literal(Literal::Pattern(_)) => Case::Match(expr),
+            expr => Case::Value(expr),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -72,13 +220,20 @@ pub enum Expr {
         args: Vec<Expr>,
     },
 
+    /// `$recv.name(args)`, handled by the receiver rather than a sub
+    Method {
+        recv: Box<Expr>,
+        name: Ident,
+        args: Vec<Expr>,
+    },
+
     Literal(Literal),
 
     Str(Vec<Expr>),
 
     List(Vec<Expr>),
 
-    Record(Vec<(Ident, Expr)>),
+    Record(Vec<(Expr, Expr)>),
 
     Binop {
         lhs: Box<Expr>,
@@ -96,11 +251,41 @@ pub enum Expr {
         rhs: Box<Expr>,
     },
 
+    /// `lhs[lo .. hi]`
+    Slice {
+        lhs: Box<Expr>,
+        lo: Box<Expr>,
+        hi: Box<Expr>,
+    },
+
+    /// `lhs // rhs`: rhs only when lhs is Nil
+    Coalesce {
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+
     Not(Box<Expr>),
 }
 
+/// The left side of `my`, which may take a list or record apart
+#[derive(Clone, Debug)]
+pub enum Binding {
+    Var(Ident),
+    List(Vec<Binding>),
+    Record(Vec<(Expr, Binding)>),
+}
+
 #[derive(Clone, Debug)]
-pub struct Args(pub Vec<Ident>);
+pub struct Args(pub Vec<Param>);
+
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub name: Ident,
+    /// Evaluated when the caller leaves this argument out
+    pub default: Option<Expr>,
+    /// Declared as `@name`; collects surplus arguments into `$name`
+    pub rest: bool,
+}
 
 #[derive(Copy, Clone, Debug)]
 pub enum Binop {
@@ -108,27 +293,71 @@ pub enum Binop {
     Sub,
     Div,
     Mul,
+    Mod,
     Idx,
     Match,
     Equal,
     NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    StrLess,
+    StrGreater,
+    StrLessEqual,
+    StrGreaterEqual,
+    Compare,
 }
 
 #[derive(Clone, Debug)]
 pub enum Literal {
+    Bool(Bool),
     Int(Int),
+    Float(Float),
     Str(Str),
     Ident(Ident),
     Pattern(pattern::Ast),
     Nil,
 }
 
+impl Stmt {
+    pub fn at(self, pos: usize) -> Stmt {
+        Stmt::At { pos, stmt: Box::new(self) }
+    }
+}
+
 impl Binop {
     pub fn apply(self, lhs: Expr, rhs: Expr) -> Expr {
         Expr::Binop { lhs: Box::new(lhs), op: self, rhs: Box::new(rhs) }
     }
 }
 
+impl Expr {
+    /// `-self`, folded into number literals.
+    pub fn negate(self) -> Expr {
+        match self {
+            Expr::Literal(Literal::Int(n)) => Expr::Literal(Literal::Int(-n)),
+            Expr::Literal(Literal::Float(x)) => Expr::Literal(Literal::Float(-x)),
+            expr => Binop::Sub.apply(Expr::Literal(Literal::Int(0)), expr),
+        }
+    }
+
+    /// Follow the indexes and fields after a variable in a string.
+    pub fn access(self, path: Vec<token::Access>) -> Expr {
+        use token::Access;
+
+        path.into_iter().fold(self, |lhs, step| {
+            let rhs = match step {
+                Access::Index(int) => Expr::Literal(Literal::Int(int)),
+                Access::Local(var) => Expr::Local(var),
+                Access::Field(name) => Expr::Literal(Literal::Ident(name)),
+            };
+
+            Binop::Idx.apply(lhs, rhs)
+        })
+    }
+}
+
 mod display {
     use std::fmt::{Display, Formatter, Result};
 
@@ -160,19 +389,30 @@ mod display {
                     write!(f, "{} or {}", lhs, rhs)
                 },
 
+                Expr::Slice { ref lhs, ref lo, ref hi } => {
+                    write!(f, "{}[{} .. {}]", lhs, lo, hi)
+                },
+
+                Expr::Coalesce { ref lhs, ref rhs } => {
+                    write!(f, "{} // {}", lhs, rhs)
+                },
+
                 Expr::Not(ref rhs) => {
                     write!(f, "not {}", rhs)
                 },
 
-                Expr::Binop { ref lhs, op, ref rhs } => match op {
-                    Binop::Add => write!(f, "{} + {}", lhs, rhs),
-                    Binop::Sub => write!(f, "{} - {}", lhs, rhs),
-                    Binop::Div => write!(f, "{} / {}", lhs, rhs),
-                    Binop::Mul => write!(f, "{} * {}", lhs, rhs),
-                    Binop::Idx => write!(f, "{}[{}]", lhs, rhs),
-                    Binop::Match => write!(f, "{} =~ {}", lhs, rhs),
-                    Binop::Equal => write!(f, "{} eq {}", lhs, rhs),
-                    Binop::NotEqual => write!(f, "{} ne {}", lhs, rhs),
+                Expr::Binop { ref lhs, op: Binop::Idx, ref rhs } => {
+                    match **rhs {
+                        Expr::Literal(Literal::Ident(ref field)) => {
+                            write!(f, "{}.{}", lhs, field)
+                        },
+
+                        _ => write!(f, "{}[{}]", lhs, rhs),
+                    }
+                },
+
+                Expr::Binop { ref lhs, op, ref rhs } => {
+                    write!(f, "{} {} {}", lhs, symbol(op), rhs)
                 },
 
                 Expr::Local(ref id) => {
@@ -183,9 +423,46 @@ mod display {
                     write!(f, "%{}", id)
                 },
 
-                Expr::Str(ref _items) => {
-                    // FIXME
-                    write!(f, "{{interpolated string}}")
+                Expr::Str(ref items) => {
+                    write!(f, "\"")?;
+
+                    let mut after_var = false;
+
+                    for item in items.iter() {
+                        match *item {
+                            Expr::Literal(Literal::Str(ref s)) => {
+                                // Would be read as an index or field
+                                if after_var && s.starts_with(&['.', '['][..]) {
+                                    write!(f, "\\")?;
+                                }
+
+                                for c in s.chars() {
+                                    match c {
+                                        '\n' => write!(f, "\\n")?,
+                                        '\r' => write!(f, "\\r")?,
+                                        '\t' => write!(f, "\\t")?,
+                                        '\0' => write!(f, "\\0")?,
+                                        c if c.is_control() => {
+                                            write!(f, "\\u{{{:x}}}", c as u32)?
+                                        },
+                                        '\\' | '"' | '$' | '%' => {
+                                            write!(f, "\\{}", c)?
+                                        },
+                                        c => write!(f, "{}", c)?,
+                                    }
+                                }
+
+                                after_var = false;
+                            },
+
+                            ref other => {
+                                write!(f, "{}", other)?;
+                                after_var = true;
+                            },
+                        }
+                    }
+
+                    write!(f, "\"")
                 },
 
                 Expr::Group(num) => {
@@ -196,22 +473,294 @@ mod display {
                     write!(f, "[{}]", uncomma(items))
                 },
 
-                Expr::Record(_) => {
-                    write!(f, "{{record}}")
+                Expr::Record(ref pairs) => {
+                    let pairs = pairs.iter().map(|(key, val)| {
+                        format!("{}: {}", RecordKey(key), val)
+                    }).collect::<Vec<_>>();
+
+                    write!(f, "{{{}}}", pairs.join(", "))
                 },
 
                 Expr::Call { ref name, ref args } => {
                     write!(f, "{}({})", name, uncomma(args))
                 },
+
+                Expr::Method { ref recv, ref name, ref args } => {
+                    write!(f, "{}.{}({})", recv, name, uncomma(args))
+                },
+            }
+        }
+    }
+
+    fn symbol(op: Binop) -> &'static str {
+        match op {
+            Binop::Add => "+",
+            Binop::Sub => "-",
+            Binop::Div => "/",
+            Binop::Mul => "*",
+            Binop::Mod => "%",
+            Binop::Idx => "[]",
+            Binop::Match => "=~",
+            Binop::Equal => "eq",
+            Binop::NotEqual => "ne",
+            Binop::Less => "<",
+            Binop::Greater => ">",
+            Binop::LessEqual => "<=",
+            Binop::GreaterEqual => ">=",
+            Binop::StrLess => "lt",
+            Binop::StrGreater => "gt",
+            Binop::StrLessEqual => "le",
+            Binop::StrGreaterEqual => "ge",
+            Binop::Compare => "cmp",
+        }
+    }
+
+    /// Computed record keys need brackets; literal ones print as strings
+    struct RecordKey<'a>(&'a Expr);
+
+    impl<'a> Display for RecordKey<'a> {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            match *self.0 {
+                Expr::Literal(Literal::Str(_)) | Expr::Str(_) => {
+                    write!(f, "{}", self.0)
+                },
+
+                ref key => write!(f, "[{}]", key),
             }
         }
     }
 
+    fn indent(f: &mut Formatter, depth: usize) -> Result {
+        write!(f, "{:1$}", "", depth * 4)
+    }
+
+    fn block(f: &mut Formatter, body: &[Stmt], depth: usize) -> Result {
+        writeln!(f, "{{")?;
+
+        for stmt in body.iter() {
+            statement(f, stmt, depth + 1)?;
+        }
+
+        indent(f, depth)?;
+        write!(f, "}}")
+    }
+
+    fn statement(f: &mut Formatter, stmt: &Stmt, depth: usize) -> Result {
+        if let Stmt::At { ref stmt, .. } = *stmt {
+            return statement(f, stmt, depth);
+        }
+
+        indent(f, depth)?;
+
+        match *stmt {
+            Stmt::My { ref lhs, rhs: None } => write!(f, "my {};", lhs)?,
+
+            Stmt::My { ref lhs, rhs: Some(ref rhs) } => {
+                write!(f, "my {} = {};", lhs, rhs)?
+            },
+
+            Stmt::Assign { ref lhs, ref rhs } => write!(f, "{} = {};", lhs, rhs)?,
+
+            Stmt::Update { ref lhs, op, ref rhs } => {
+                write!(f, "{} {}= {};", lhs, symbol(op), rhs)?
+            },
+
+            Stmt::Return { rhs: None } => write!(f, "return;")?,
+
+            Stmt::Return { rhs: Some(ref rhs) } => write!(f, "return {};", rhs)?,
+
+            Stmt::Assert { ref rhs, message: None } => {
+                write!(f, "assert {};", rhs)?;
+            },
+
+            Stmt::Assert { ref rhs, message: Some(ref message) } => {
+                write!(f, "assert {}, {};", rhs, message)?;
+            },
+
+            Stmt::Throw { ref rhs } => write!(f, "throw {};", rhs)?,
+
+            Stmt::Bare { ref rhs } => write!(f, "{};", rhs)?,
+
+            Stmt::Nop => write!(f, ";")?,
+
+            Stmt::If { ref clauses, ref last } => {
+                for (i, (test, body)) in clauses.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " else ")?;
+                    }
+
+                    write!(f, "if {} ", test)?;
+                    block(f, body, depth)?;
+                }
+
+                if !last.is_empty() {
+                    write!(f, " else ")?;
+                    block(f, last, depth)?;
+                }
+            },
+
+            Stmt::Unless { ref test, ref body, ref last } => {
+                write!(f, "unless {} ", test)?;
+                block(f, body, depth)?;
+
+                if !last.is_empty() {
+                    write!(f, " else ")?;
+                    block(f, last, depth)?;
+                }
+            },
+
+            Stmt::While { ref test, ref body } => {
+                write!(f, "while {} ", test)?;
+                block(f, body, depth)?;
+            },
+
+            Stmt::Until { ref test, ref body } => {
+                write!(f, "until {} ", test)?;
+                block(f, body, depth)?;
+            },
+
+            Stmt::Given { ref subject, ref arms, ref last } => {
+                writeln!(f, "given {} {{", subject)?;
+
+                for (cases, body) in arms.iter() {
+                    indent(f, depth + 1)?;
+                    write!(f, "when {} ", uncomma(cases))?;
+                    block(f, body, depth + 1)?;
+                    writeln!(f)?;
+                }
+
+                if !last.is_empty() {
+                    indent(f, depth + 1)?;
+                    write!(f, "else ")?;
+                    block(f, last, depth + 1)?;
+                    writeln!(f)?;
+                }
+
+                indent(f, depth)?;
+                write!(f, "}}")?;
+            },
+
+            Stmt::For { ref var, ref lo, ref hi, ref body } => {
+                write!(f, "for ${} in {}..{} ", var, lo, hi)?;
+                block(f, body, depth)?;
+            },
+
+            Stmt::Foreach { ref var, ref list, ref body } => {
+                write!(f, "foreach ${} in {} ", var, list)?;
+                block(f, body, depth)?;
+            },
+
+            Stmt::Try { ref body, ref var, ref handler } => {
+                write!(f, "try ")?;
+                block(f, body, depth)?;
+                write!(f, " catch ${} ", var)?;
+                block(f, handler, depth)?;
+            },
+
+            Stmt::At { .. } => unreachable!(),
+        }
+
+        writeln!(f)
+    }
+
+    impl Display for Stmt {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            statement(f, self, 0)
+        }
+    }
+
+    impl Display for Binding {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            match *self {
+                Binding::Var(ref name) => write!(f, "${}", name),
+
+                Binding::List(ref items) => write!(f, "[{}]", uncomma(items)),
+
+                Binding::Record(ref pairs) => {
+                    let pairs = pairs.iter().map(|(key, val)| {
+                        format!("{}: {}", RecordKey(key), val)
+                    }).collect::<Vec<_>>();
+
+                    write!(f, "{{{}}}", pairs.join(", "))
+                },
+            }
+        }
+    }
+
+    impl Display for Case {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            match *self {
+                Case::Value(ref expr) | Case::Match(ref expr) => {
+                    write!(f, "{}", expr)
+                },
+
+                Case::Type(ref name) => write!(f, "{}", name),
+            }
+        }
+    }
+
+    impl Display for Param {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            match self.default {
+                _ if self.rest => write!(f, "@{}", self.name),
+                Some(ref default) => write!(f, "${} = {}", self.name, default),
+                None => write!(f, "${}", self.name),
+            }
+        }
+    }
+
+    impl Display for Def {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            if let Some(ref doc) = self.doc {
+                for line in doc.lines() {
+                    writeln!(f, "## {}", line)?;
+                }
+            }
+
+            write!(f, "sub {}({}) ", self.name, uncomma(&self.args.0))?;
+            block(f, &self.body, 0)?;
+            writeln!(f)
+        }
+    }
+
+    impl Display for Module {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            if let Some(ref package) = self.package {
+                writeln!(f, "package {};", package)?;
+                writeln!(f)?;
+            }
+
+            for import in self.imports.iter() {
+                writeln!(f, "import {};", import)?;
+            }
+
+            if !self.imports.is_empty() {
+                writeln!(f)?;
+            }
+
+            for stmt in self.begin.iter() {
+                write!(f, "{}", stmt)?;
+            }
+
+            for (i, def) in self.defs.iter().enumerate() {
+                if i > 0 || !self.begin.is_empty() {
+                    writeln!(f)?;
+                }
+
+                write!(f, "{}", def)?;
+            }
+
+            Ok(())
+        }
+    }
+
     impl Display for Literal {
         fn fmt(&self, f: &mut Formatter) -> Result {
             match *self {
                 Literal::Nil => write!(f, "()"),
+                Literal::Bool(b) => write!(f, "{}", b),
                 Literal::Int(i) => write!(f, "{}", i),
+                Literal::Float(x) => write!(f, "{:?}", x),
                 Literal::Str(ref s) => write!(f, "{:?}", s),
                 Literal::Ident(ref n) => write!(f, ":{}", n),
                 Literal::Pattern(ref p) => p.fmt(f),
@@ -222,17 +771,19 @@ mod display {
 
 #[test]
 fn translation() {
-    let mut strings = Strings::new();
+    let strings = Strings::new();
     let x: Ident = strings.intern("x").unwrap();
     let hello = strings.intern("hello").unwrap();
     let world = strings.intern("world").unwrap();
     let print = strings.intern("print").unwrap();
 
     let src = Def {
+        pos: 0,
+        doc: None,
         name: hello,
         args: Args(vec![]),
         body: vec!{
-            Stmt::My { lhs: x.clone(), rhs: None, },
+            Stmt::My { lhs: Binding::Var(x.clone()), rhs: None, },
             Stmt::Assign {
                 lhs: Expr::Local(x.clone()),
                 rhs: Expr::Literal(Literal::Str(world)),
@@ -247,6 +798,8 @@ fn translation() {
     };
 
     let module = Module {
+        package: None,
+        imports: vec![],
         begin: vec![],
         defs: vec![src],
     };
@@ -256,16 +809,27 @@ fn translation() {
 
 #[test]
 fn syntax() {
-    use ast::grammar::parse_def;
     use token::Tokenizer;
 
     let src = &[
         "sub assign() { $x = $y; }",
+        "sub update() { $x += 1; $x[0] -= 2; %X *= 3; $x.y /= 4; $z %= 5; }",
         "sub simple_if() { if 0 { } }",
         "sub if_else() { if 1 { 1; } else if 2 { 2; } else { 3; } }",
         "sub while_loop() { while 1 { } }",
+        "sub for_loop() { for $i in 1..10 { print $i; } }",
+        "sub try_catch() { try { throw :oops; } catch $e { print $e; } }",
+        "sub foreach_loop() { foreach $x in [1, 2] { print $x; } }",
         "sub globals() { %X = %Y; }",
+        "sub modulo() { %X = %Y % 2 + $z %$w; }",
         "sub symbols() { my $a = :b; :c + :d; }",
+        "sub compare() { assert 1 < 2 and 2 >= 2 and 3 > 2 and 2 <= 3; }",
+        r#"sub records() { my $r = { a: 1, "b c": [], [$k]: {} }; }"#,
+        r#"sub defaults($a, $b = 1, $c = "$a $b") { }"#,
+        "sub qualified() { %pkg::x = pkg::f(:pkg::g); }",
+        "sub variadic($a, $b = 2, @rest) { return $rest; }",
+        "sub pair() { return 1, 2; my ($a, $b) = pair(); }",
+        r#"sub unpack() { my [$a, [$b], {c: $c, "d": {}}] = $x; my {} = {}; }"#,
     ];
 
     for src in src {
@@ -281,3 +845,34 @@ fn syntax() {
         });
     }
 }
+
+#[test]
+fn pretty_print() {
+    use token::Tokenizer;
+
+    let src = &[
+        "my $x = 1; sub f($a, $b = [1], @c) { if $a { $b[0] -= 2; } else { return; } }",
+        r#"my {a: $a, [$k]: [$b]} = {"x y": "$a\$ %G\n", [$k]: 1 + 2 * 3};"#,
+        "sub loops() { for $i in 1..2 { foreach $j in [] { while 0 { } } } }",
+        "package p; sub t() { try { throw :x; } catch $e { print $e.error; } }",
+        "package p; import q; import r::s; print q::f(:t); sub t() { }",
+        r#"my $s = "$a[0].b $l[$i]. $u\.x %g.h[1] $1.";"#,
+        r#"my $e = "\x41\0\x1b[0m \u{1F600}\u{7f}";"#,
+        "sub m($x) { return 1 if $x; $x += 1 until $x > 9; print $x unless 0; }",
+        "sub c($x) { unless $x { } else { until $x { } } if $x { } elsif 1 { } }",
+        "sub g($x) { given $x { when 1, \"a\" { } when Str, re/b/ { } else { } } }",
+        r#"sub s($a) { return $a lt "b" and $a ge "a" or $a cmp "c"; }"#,
+        "sub d($a, $b) { return $a // $b // 1 and defined($a); }",
+        "sub s($l) { return $l[1 .. len($l)][0..$l[0]]; }",
+        "my $n = -1 * -$x[-2] - -0.5;",
+        r#"sub a($x) { assert $x > 0, "bad $x"; assert $x; }"#,
+        "## Does nothing.\n##\n##   ## at all\nsub f() { } #[[ x ]] ## y\nsub g() { }",
+    ];
+
+    let parse = |src: &str| parse_module(Tokenizer::new(src).spanned()).unwrap();
+
+    for src in src {
+        let printed = parse(src).to_string();
+        assert_eq!(parse(&printed).to_string(), printed, "reprinting {}", src);
+    }
+}
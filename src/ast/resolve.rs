@@ -0,0 +1,249 @@
+use std::collections::HashSet;
+
+use super::*;
+
+impl Module {
+    /// Qualify the subs of a `package` and point unqualified calls at them,
+    /// along with symbol arguments naming them, so `map(:double, ...)`
+    /// finds the package's `double`. Calls that name no sub in the package are left
+    /// for the stdlib and host functions.
+    pub fn resolve(&mut self, strings: &Strings) -> Result<()> {
+        let package = match self.package {
+            Some(ref package) => package.clone(),
+            None => return Ok(()),
+        };
+
+        let mut local = HashSet::new();
+        for def in self.defs.iter_mut() {
+            if !def.name.is_qualified() {
+                local.insert(def.name.clone());
+                def.name = strings.intern(format!("{}::{}", package, def.name))?;
+            }
+        }
+
+        let mut qualify = |name: &mut Ident| -> Result<()> {
+            if local.contains(name) {
+                *name = strings.intern(format!("{}::{}", package, name))?;
+            }
+
+            Ok(())
+        };
+
+        for stmt in self.begin.iter_mut() {
+            stmt.each_call(&mut qualify)?;
+        }
+
+        for def in self.defs.iter_mut() {
+            for param in def.args.0.iter_mut() {
+                if let Some(ref mut default) = param.default {
+                    default.each_call(&mut qualify)?;
+                }
+            }
+
+            for stmt in def.body.iter_mut() {
+                stmt.each_call(&mut qualify)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+type Visit<'a> = dyn FnMut(&mut Ident) -> Result<()> + 'a;
+
+fn each_stmt(body: &mut [Stmt], f: &mut Visit) -> Result<()> {
+    for stmt in body.iter_mut() {
+        stmt.each_call(f)?;
+    }
+
+    Ok(())
+}
+
+/// A symbol passed straight to a call may be called through later, as
+/// `map(:double, ...)` and `on(:tick, :handler)` do. Symbols anywhere else
+/// are keys or names of their own.
+fn each_arg(args: &mut [Expr], f: &mut Visit) -> Result<()> {
+    for arg in args.iter_mut() {
+        match *arg {
+            Expr::Literal(Literal::Ident(ref mut name)) => f(name)?,
+            ref mut arg => arg.each_call(f)?,
+        }
+    }
+
+    Ok(())
+}
+
+impl Stmt {
+    fn each_call(&mut self, f: &mut Visit) -> Result<()> {
+        match *self {
+            Stmt::My { ref mut lhs, ref mut rhs } => {
+                lhs.each_call(f)?;
+                if let Some(ref mut rhs) = *rhs {
+                    rhs.each_call(f)?;
+                }
+            },
+
+            Stmt::Assign { ref mut lhs, ref mut rhs } |
+            Stmt::Update { ref mut lhs, ref mut rhs, .. } => {
+                lhs.each_call(f)?;
+                rhs.each_call(f)?;
+            },
+
+            Stmt::Return { ref mut rhs } => {
+                if let Some(ref mut rhs) = *rhs {
+                    rhs.each_call(f)?;
+                }
+            },
+
+            Stmt::Assert { ref mut rhs, ref mut message } => {
+                rhs.each_call(f)?;
+
+                if let Some(ref mut message) = *message {
+                    message.each_call(f)?;
+                }
+            },
+
+            Stmt::Throw { ref mut rhs } |
+            Stmt::Bare { ref mut rhs } => rhs.each_call(f)?,
+
+            Stmt::If { ref mut clauses, ref mut last } => {
+                for &mut (ref mut test, ref mut body) in clauses.iter_mut() {
+                    test.each_call(f)?;
+                    each_stmt(body, f)?;
+                }
+
+                each_stmt(last, f)?;
+            },
+
+            Stmt::Unless { ref mut test, ref mut body, ref mut last } => {
+                test.each_call(f)?;
+                each_stmt(body, f)?;
+                each_stmt(last, f)?;
+            },
+
+            Stmt::Given { ref mut subject, ref mut arms, ref mut last } => {
+                subject.each_call(f)?;
+
+                for &mut (ref mut cases, ref mut body) in arms.iter_mut() {
+                    for case in cases.iter_mut() {
+                        match *case {
+                            Case::Value(ref mut expr) |
+                            Case::Match(ref mut expr) => expr.each_call(f)?,
+                            Case::Type(_) => (),
+                        }
+                    }
+
+                    each_stmt(body, f)?;
+                }
+
+                each_stmt(last, f)?;
+            },
+
+            Stmt::While { ref mut test, ref mut body } |
+            Stmt::Until { ref mut test, ref mut body } => {
+                test.each_call(f)?;
+                each_stmt(body, f)?;
+            },
+
+            Stmt::For { ref mut lo, ref mut hi, ref mut body, .. } => {
+                lo.each_call(f)?;
+                hi.each_call(f)?;
+                each_stmt(body, f)?;
+            },
+
+            Stmt::Foreach { ref mut list, ref mut body, .. } => {
+                list.each_call(f)?;
+                each_stmt(body, f)?;
+            },
+
+            Stmt::Try { ref mut body, ref mut handler, .. } => {
+                each_stmt(body, f)?;
+                each_stmt(handler, f)?;
+            },
+
+            Stmt::Nop => (),
+
+            Stmt::At { ref mut stmt, .. } => stmt.each_call(f)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl Binding {
+    fn each_call(&mut self, f: &mut Visit) -> Result<()> {
+        match *self {
+            Binding::Var(_) => (),
+
+            Binding::List(ref mut items) => {
+                for item in items.iter_mut() {
+                    item.each_call(f)?;
+                }
+            },
+
+            Binding::Record(ref mut pairs) => {
+                for &mut (ref mut key, ref mut item) in pairs.iter_mut() {
+                    key.each_call(f)?;
+                    item.each_call(f)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+impl Expr {
+    fn each_call(&mut self, f: &mut Visit) -> Result<()> {
+        match *self {
+            Expr::Call { ref mut name, ref mut args } => {
+                f(name)?;
+                each_arg(args, f)?;
+            },
+
+            // The receiver decides what the name means
+            Expr::Method { ref mut recv, ref mut args, .. } => {
+                recv.each_call(f)?;
+                each_arg(args, f)?;
+            },
+
+            Expr::Parens(ref mut inner) |
+            Expr::Not(ref mut inner) => inner.each_call(f)?,
+
+            Expr::Str(ref mut items) |
+            Expr::List(ref mut items) => {
+                for item in items.iter_mut() {
+                    item.each_call(f)?;
+                }
+            },
+
+            Expr::Record(ref mut pairs) => {
+                for &mut (ref mut key, ref mut val) in pairs.iter_mut() {
+                    key.each_call(f)?;
+                    val.each_call(f)?;
+                }
+            },
+
+            Expr::Slice { ref mut lhs, ref mut lo, ref mut hi } => {
+                lhs.each_call(f)?;
+                lo.each_call(f)?;
+                hi.each_call(f)?;
+            },
+
+            Expr::Binop { ref mut lhs, ref mut rhs, .. } |
+            Expr::And { ref mut lhs, ref mut rhs } |
+            Expr::Or { ref mut lhs, ref mut rhs } |
+            Expr::Coalesce { ref mut lhs, ref mut rhs } => {
+                lhs.each_call(f)?;
+                rhs.each_call(f)?;
+            },
+
+            Expr::Local(_) |
+            Expr::Global(_) |
+            Expr::Group(_) |
+            Expr::Literal(_) => (),
+        }
+
+        Ok(())
+    }
+}
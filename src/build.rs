@@ -1,17 +1,44 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::process::Command;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use super::*;
 use ident::*;
 use value::*;
 use opcode::*;
+use eval::Interpreter;
+use logger::Level;
+use pattern::Pattern;
 
 pub struct Assembler<'a> {
     code: Vec<Op<Sym>>,
-    strings: &'a mut Strings,
+    strings: &'a Strings,
+    constants: &'a mut Constants,
     labels: HashMap<Sym, usize>,
-    scopes: Vec<HashMap<Ident, usize>>,
+    scopes: Vec<Scope>,
     next_gensym: usize,
+    // Source offset of each statement, keyed by its first instruction
+    lines: Vec<(usize, usize)>,
+    pos: Option<usize>,
+    // Names from scopes already left, for debuggers
+    vars: Vec<LocalVar>,
+    optimize: bool,
+}
+
+#[derive(Default)]
+struct Scope {
+    // Where the scope begins
+    start: usize,
+    names: HashMap<Ident, usize>,
+    // Slots reserved by the compiler that have no name in the source
+    temps: usize,
 }
 
 #[derive(Copy, Clone, Eq, Hash, PartialEq)]
@@ -19,21 +46,70 @@ struct Sym(usize);
 
 impl Module {
     pub fn def(&mut self, def: ast::Def) -> Result<()> {
+        let mut errors = vec![];
+        self.def_into(def, &mut errors)?;
+
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `def`, but carry on past statements that fail to translate,
+    /// adding their errors to `errors`. The sub is only defined if all of
+    /// them translate.
+    fn def_into(&mut self, def: ast::Def, errors: &mut Vec<Error>) -> Result<()> {
         use ast::Def;
 
-        let Def { name, args, body } = def;
-        let args = args.0;
-        let argc = Argc::Exactly(args.len());
+        let Def { pos, name, args, body, .. } = def;
+        let mut params = args.0;
 
-        let mut asm = Assembler::new(&mut self.strings, args);
+        let context = |cause: Error| Error::WithContext {
+            cause: cause.at_offset(pos).into(),
+            context: format!("sub {}", &name),
+        };
 
-        for stmt in body.into_iter() {
-            asm.tr_stmt(stmt).map_err(|cause| {
-                Error::WithContext {
-                    cause: cause.into(),
-                    context: format!("sub {}", &name),
-                }
-            })?;
+        let rest = match params.iter().position(|param| param.rest) {
+            Some(i) if i + 1 < params.len() => {
+                return Err(context(Error::MisplacedRest));
+            },
+
+            Some(_) => params.pop().map(|param| param.name),
+            None => None,
+        };
+
+        let total = params.len();
+        let required = params.iter()
+            .take_while(|param| param.default.is_none())
+            .count();
+
+        if params[required ..].iter().any(|param| param.default.is_none()) {
+            return Err(context(Error::MisplacedDefault));
+        }
+
+        let argc = if rest.is_some() {
+            Argc::AtLeast(required)
+        } else if required == total {
+            Argc::Exactly(total)
+        } else {
+            Argc::Between(required, total)
+        };
+
+        let optional = params.split_off(required);
+        let required = params.into_iter().map(|param| param.name).collect();
+        let mut asm = Assembler::new(self, required);
+
+        for param in optional.into_iter() {
+            let default = param.default.ok_or(Error::InternalCompilerErr)?;
+            asm.param(param.name, default).map_err(context)?;
+        }
+
+        if let Some(rest) = rest {
+            asm.rest(rest).map_err(context)?;
+        }
+
+        if !asm.tr_each(body, errors, context) {
+            return Ok(());
         }
 
         // Implicit return
@@ -47,9 +123,80 @@ impl Module {
         Ok(())
     }
 
+    /// Compile top-level statements for a frame whose slots are already
+    /// named by `slots` (`None` for slots that can no longer be named).
+    /// Returns the code, the slot names afterwards, and whether a final
+    /// bare expression leaves its value on the stack.
+    pub(crate) fn snippet(&mut self, slots: &[Option<Ident>], mut stmts: Vec<ast::Stmt>)
+        -> Result<(InterpretedFn, Vec<Option<Ident>>, bool)>
+    {
+        use ast::Stmt;
+
+        let mut asm = Assembler::new(self, vec![]);
+
+        for (i, slot) in slots.iter().enumerate() {
+            match *slot {
+                Some(ref name) => { asm.scopes[0].names.insert(name.clone(), i); },
+                None => asm.scopes[0].temps += 1,
+            }
+        }
+
+        // Names declared here may shadow earlier ones
+        asm.enter();
+
+        let last = stmts.pop();
+
+        for stmt in stmts.into_iter() {
+            asm.tr_stmt(stmt)?;
+        }
+
+        let value = match last {
+            Some(Stmt::At { pos, stmt }) => match *stmt {
+                Stmt::Bare { rhs } => {
+                    asm.lines.push((asm.code.len(), pos));
+                    asm.tr_expr(rhs).map_err(|err| err.at_offset(pos))?;
+                    true
+                },
+
+                stmt => {
+                    asm.tr_stmt(stmt.at(pos))?;
+                    false
+                },
+            },
+
+            Some(stmt) => {
+                asm.tr_stmt(stmt)?;
+                false
+            },
+
+            None => false,
+        };
+
+        let mut slots = slots.to_vec();
+        slots.resize(asm.depth(), None);
+
+        let declared = asm.scopes.last()
+            .ok_or(Error::InternalCompilerErr)?
+            .names.clone();
+
+        for (name, index) in declared {
+            for slot in slots.iter_mut() {
+                if slot.as_ref() == Some(&name) {
+                    *slot = None;
+                }
+            }
+
+            slots[index] = Some(name);
+        }
+
+        asm.leave()?;
+
+        Ok((asm.build()?, slots, value))
+    }
+
     pub fn def_native<F, V>(&mut self, name: &str, argc: Argc, body: F)
         -> Result<()>
-        where F: 'static + Fn(Vec<Value>) -> Result<V>,
+        where F: 'static + Send + Sync + Fn(Vec<Value>) -> Result<V>,
               V: Into<Value>
     {
         let name = self.strings.intern(name)?;
@@ -60,99 +207,915 @@ impl Module {
 
         self.functions.insert(name, (argc, body));
 
-        Ok(())
+        Ok(())
+    }
+
+    /// Define a native from a closure with typed parameters, such as
+    /// `|text: Str, count: Int| ...`. Arguments are checked against the
+    /// parameter count and converted with `Extract` before the call.
+    pub fn def_fn<F, Args>(&mut self, name: &str, body: F) -> Result<()>
+        where F: NativeFunction<Args>, Args: FromValues
+    {
+        let func = Str::from(name);
+
+        self.def_native(name, Argc::Exactly(Args::ARGC), move |args| {
+            body.call_with(Args::from_values(args, &func)?)
+        })
+    }
+
+    /// Like `def_native`, but the body can call back into the interpreter.
+    pub fn def_native_ctx<F, V>(&mut self, name: &str, argc: Argc, body: F)
+        -> Result<()>
+        where F: 'static + Send + Sync + Fn(&mut Interpreter, Vec<Value>) -> Result<V>,
+              V: Into<Value>
+    {
+        let name = self.strings.intern(name)?;
+        let body = Func::Context(Arc::new(move |interp, args| {
+            let result = body(interp, args)?;
+            Ok(result.into())
+        }));
+
+        self.functions.insert(name, (argc, body));
+
+        Ok(())
+    }
+
+    /// Define `run(cmd, args...)`, which spawns a child process and
+    /// returns `{ stdout: "...", stderr: "...", status: 0 }`. Not part of
+    /// the stdlib, so embedders must opt in.
+    pub fn enable_processes(&mut self) -> Result<()> {
+        self.def_native("run", Argc::AtLeast(1), |args| {
+            let mut args = args.into_iter().map(|arg| arg.to_string());
+            let cmd = args.next().unwrap();
+
+            let output = Command::new(cmd).args(args).output()?;
+
+            let text = |bytes: Vec<u8>| {
+                Str::from(String::from_utf8_lossy(&bytes).as_ref())
+            };
+
+            let status = match output.status.code() {
+                Some(code) => Value::Int(code.into()),
+                None => Value::Nil(()),
+            };
+
+            let record = Record::default();
+            {
+                let mut record = record.borrow_mut();
+                record.insert(Str::from("stdout"), text(output.stdout).into());
+                record.insert(Str::from("stderr"), text(output.stderr).into());
+                record.insert(Str::from("status"), status);
+            }
+
+            Ok(record)
+        })
+    }
+
+    /// Remove the natives `profile` leaves out. Subs defined in script are
+    /// kept, even if they share a name with one.
+    pub fn restrict(&mut self, profile: &Profile) {
+        let allowed = [
+            (profile.stdio, STDIO_NATIVES),
+            (profile.time, TIME_NATIVES),
+            (profile.env, ENV_NATIVES),
+            (profile.process, PROCESS_NATIVES),
+        ];
+
+        self.functions.retain(|name, &mut (_, ref func)| match *func {
+            Func::Interpreted(_) => true,
+            _ => allowed.iter().all(|&(allow, names)| {
+                allow || !names.contains(&name.as_ref())
+            }),
+        });
+    }
+
+    /// The capabilities whose natives are all still defined.
+    pub fn profile(&self) -> Profile {
+        let has = |names: &[&str]| names.iter().all(|name| {
+            self.functions.iter().any(|(key, (_, func))| {
+                key.as_ref() == *name && !matches!(*func, Func::Interpreted(_))
+            })
+        });
+
+        Profile {
+            stdio: has(STDIO_NATIVES),
+            time: has(TIME_NATIVES),
+            env: has(ENV_NATIVES),
+            process: has(PROCESS_NATIVES),
+        }
+    }
+
+    pub fn stdlib() -> Result<Self> {
+        Module::stdlib_in(&Strings::new())
+    }
+
+    /// The stdlib with only the capabilities in `profile`. Use
+    /// `Profile::pure()` for a sandbox that can only compute.
+    pub fn stdlib_with(profile: &Profile) -> Result<Self> {
+        let mut std = Module::stdlib()?;
+
+        if profile.process {
+            std.enable_processes()?;
+        }
+
+        std.restrict(profile);
+        Ok(std)
+    }
+
+    /// Like `stdlib`, but intern names into `strings`.
+    // Natives that only act for their side effects return `Ok({ ... })`
+    #[allow(clippy::unit_arg)]
+    pub fn stdlib_in(strings: &Strings) -> Result<Self> {
+        use self::Argc::*;
+
+        let mut std = Module {
+            begin: InterpretedFn::from_vec(vec![]),
+            source: None,
+            strings: strings.clone(),
+            constants: Constants::default(),
+            functions: HashMap::new(),
+            optimize: true,
+        };
+
+        fn map_to_string(items: Vec<Value>) -> Vec<String> {
+            items.into_iter().map(|i| format!("{}", i)).collect()
+        }
+
+        fn print(interp: &mut Interpreter, args: Vec<Value>) -> Result<()> {
+            writeln!(interp.output(), "{}", map_to_string(args).join(" "))?;
+            Ok(())
+        }
+
+        std.def_native_ctx("print", AtLeast(1), print)?;
+        std.def_native_ctx("say", AtLeast(1), print)?;
+
+        std.def_native_ctx("print_no_nl", AtLeast(1), |interp, args| Ok({
+            write!(interp.output(), "{}", map_to_string(args).join(" "))?;
+        }))?;
+
+        // Like `sprintf`, and like C, it adds no newline
+        std.def_native_ctx("printf", AtLeast(1), |interp, args| Ok({
+            let mut args = args.into_iter();
+            let template = Str::extract(args.next().unwrap())?;
+            let args = args.collect::<Vec<Value>>();
+            let text = format::sprintf(&template, &args)?;
+            write!(interp.output(), "{}", text)?;
+        }))?;
+
+        std.def_native("str", AtLeast(1), |args| Ok({
+            Str::from(map_to_string(args).concat())
+        }))?;
+
+        std.def_native("len", Exactly(1), |mut args| {
+            let len = match args.pop().unwrap() {
+                Value::List(list) => list.borrow().len(),
+                Value::Record(rec) => rec.borrow().len(),
+                Value::Str(text) => text.chars().count(),
+
+                other => return Err(Error::TypeMismatch {
+                    expected: "List|Record|Str",
+                    found: other.type_name(),
+                }),
+            };
+
+            Ok(len as Int)
+        })?;
+
+        std.def_native("split", Between(1, 3), |args| {
+            let mut args = args.into_iter();
+            let text = Str::extract(args.next().unwrap())?;
+            let sep = args.next();
+
+            // Zero or negative limits mean no limit, as in Perl
+            let limit = match args.next() {
+                Some(limit) => Some(Int::extract(limit)?)
+                    .filter(|&n| n > 0)
+                    .map(|n| n as usize),
+                None => None,
+            };
+
+            let pieces = match sep {
+                None => {
+                    text.split_whitespace().map(String::from).collect()
+                },
+
+                Some(Value::Str(sep)) => split_str(&text, &sep, limit),
+
+                Some(Value::Pattern(pat)) => split_pattern(&text, &pat, limit),
+
+                Some(other) => return Err(Error::TypeMismatch {
+                    expected: "Str|Pattern",
+                    found: other.type_name(),
+                }),
+            };
+
+            Ok(Value::from_iter(pieces.into_iter().map(Str::from)))
+        })?;
+
+        std.def_native("chars", Exactly(1), |mut args| Ok({
+            let text = Str::extract(args.pop().unwrap())?;
+            Value::from_iter(text.chars().map(|c| Str::from(c.to_string())))
+        }))?;
+
+        std.def_native("bytes", Exactly(1), |mut args| Ok({
+            let text = Str::extract(args.pop().unwrap())?;
+            Value::from_iter(text.bytes().map(Int::from))
+        }))?;
+
+        // The reverse of `split`, with the separator first as in Perl
+        std.def_native("join", Exactly(2), |mut args| Ok({
+            let items = List::extract(args.pop().unwrap())?;
+            let sep = Str::extract(args.pop().unwrap())?;
+            let items: Vec<Value> = items.borrow().iter().cloned().collect();
+            Str::from(map_to_string(items).join(&sep))
+        }))?;
+
+        std.def_native("sprintf", AtLeast(1), |args| {
+            let mut args = args.into_iter();
+            let template = Str::extract(args.next().unwrap())?;
+            let args = args.collect::<Vec<Value>>();
+            Ok(Str::from(format::sprintf(&template, &args)?))
+        })?;
+
+        std.def_native("substr", Between(2, 3), |args| {
+            let mut args = args.into_iter();
+            let text = Str::extract(args.next().unwrap())?;
+            let len = text.chars().count();
+            let start = char_index(len, Int::extract(args.next().unwrap())?)?;
+
+            let chars = text.chars().skip(start);
+
+            if start > len {
+                return Err(Error::IndexOutOfBounds);
+            }
+
+            Ok(Str::from(match args.next() {
+                Some(count) => {
+                    let count = Int::extract(count)?;
+
+                    if count < 0 {
+                        return Err(Error::NegativeIndex);
+                    }
+
+                    chars.take(count as usize).collect::<String>()
+                },
+
+                None => chars.collect::<String>(),
+            }))
+        })?;
+
+        std.def_native("index", Between(2, 3), |args| {
+            let mut args = args.into_iter();
+            let text = Str::extract(args.next().unwrap())?;
+            let needle = Str::extract(args.next().unwrap())?;
+
+            let from = match args.next() {
+                Some(from) => {
+                    char_index(text.chars().count(), Int::extract(from)?)?
+                },
+                None => 0,
+            };
+
+            let offset = match text.char_indices().nth(from) {
+                Some((offset, _)) => offset,
+                None if from == text.chars().count() => text.len(),
+                None => return Ok(-1 as Int),
+            };
+
+            Ok(match text[offset ..].find(needle.as_ref()) {
+                Some(found) => (from + text[offset .. offset + found].chars().count()) as Int,
+                None => -1,
+            })
+        })?;
+
+        std.def_fn("uc", |text: Str| Ok(Str::from(text.to_uppercase())))?;
+
+        std.def_fn("lc", |text: Str| Ok(Str::from(text.to_lowercase())))?;
+
+        std.def_fn("trim", |text: Str| Ok(Str::from(text.trim())))?;
+
+        std.def_native("keys", Exactly(1), |mut args| Ok({
+            let rec = Record::extract(args.pop().unwrap())?;
+            let mut keys: Vec<Str> = rec.borrow().keys().cloned().collect();
+            keys.sort();
+            Value::from_iter(keys.into_iter())
+        }))?;
+
+        std.def_native("values", Exactly(1), |mut args| Ok({
+            let rec = Record::extract(args.pop().unwrap())?;
+            let rec = rec.borrow();
+            let mut keys: Vec<&Str> = rec.keys().collect();
+            keys.sort();
+            Value::from_iter(keys.into_iter().map(|key| rec[key].clone()))
+        }))?;
+
+        std.def_native("exists", Exactly(2), |mut args| Ok({
+            let key = args.pop().unwrap().record_key()?;
+            let rec = Record::extract(args.pop().unwrap())?;
+            let found = rec.borrow().contains_key(&key);
+            found
+        }))?;
+
+        std.def_native("defined", Exactly(1), |mut args| Ok({
+            !matches!(args.pop().unwrap(), Value::Nil(_))
+        }))?;
+
+        // Like indexing, but a missing key or slot gives the default
+        std.def_native("get", Between(2, 3), |mut args| {
+            let default = if args.len() > 2 {
+                args.pop().unwrap()
+            } else {
+                Value::Nil(())
+            };
+
+            let key = args.pop().unwrap();
+            match args.pop().unwrap().index(key) {
+                Err(Error::IndexOutOfBounds) => Ok(default),
+                result => result,
+            }
+        })?;
+
+        std.def_native("delete", Exactly(2), |mut args| Ok({
+            let key = args.pop().unwrap().record_key()?;
+            let rec = Record::extract(args.pop().unwrap())?;
+            let removed = rec.borrow_mut().remove(&key);
+            removed.unwrap_or(Value::Nil(()))
+        }))?;
+
+        std.def_native("clone", Exactly(1), |mut args| Ok({
+            args.pop().unwrap().deep_clone()
+        }))?;
+
+        std.def_native_ctx("typeof", Exactly(1), |interp, mut args| {
+            let name = args.pop().unwrap().type_name();
+            interp.intern(name)
+        })?;
+
+        for &name in Value::TYPE_NAMES {
+            let pred = format!("is_{}", name.to_lowercase());
+            std.def_native(&pred, Exactly(1), move |mut args| Ok({
+                args.pop().unwrap().type_name() == name
+            }))?;
+        }
+
+        std.def_native("time", Exactly(0), |_| {
+            use std::time::{SystemTime, UNIX_EPOCH};
+
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map_err(|_| Error::UnimplementedFeature {
+                    feature: "clocks set before 1970",
+                })?;
+
+            Ok(now.as_secs() as Int)
+        })?;
+
+        let started = Instant::now();
+        std.def_native("clock_ms", Exactly(0), move |_| Ok({
+            started.elapsed().as_millis() as Int
+        }))?;
+
+        std.def_native("sleep", Exactly(1), |mut args| Ok({
+            let ms = Int::extract(args.pop().unwrap())?.max(0);
+            thread::sleep(Duration::from_millis(ms as u64));
+        }))?;
+
+        std.def_native("readline", Exactly(0), |_| {
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                return Ok(Value::Nil(()));
+            }
+
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+
+            Ok(Str::from(line).into())
+        })?;
+
+        std.def_native("read_all_stdin", Exactly(0), |_| {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            Ok(Str::from(text))
+        })?;
+
+        std.def_native("env", Exactly(1), |mut args| {
+            let name = Str::extract(args.pop().unwrap())?;
+            Ok(match ::std::env::var(name.as_ref()) {
+                Ok(value) => Str::from(value).into(),
+                Err(_) => Value::Nil(()),
+            })
+        })?;
+
+        std.def_native("reverse", Exactly(1), |mut args| Ok({
+            let list = List::extract(args.pop().unwrap())?;
+            let items: Vec<Value> = list.borrow().iter().rev().cloned().collect();
+            Value::from_slice(items)
+        }))?;
+
+        // Keeps the first of each group of equal items, in order
+        std.def_native("uniq", Exactly(1), |mut args| Ok({
+            let list = List::extract(args.pop().unwrap())?;
+            let items = distinct(list.borrow().iter().cloned());
+            Value::from_slice(items)
+        }))?;
+
+        // Set operations keep items in the order they first appear, once each
+        std.def_native("union", Exactly(2), |mut args| Ok({
+            let rhs = List::extract(args.pop().unwrap())?;
+            let lhs = List::extract(args.pop().unwrap())?;
+            let (lhs, rhs) = (lhs.borrow(), rhs.borrow());
+            Value::from_slice(distinct(lhs.iter().chain(rhs.iter()).cloned()))
+        }))?;
+
+        std.def_native("intersect", Exactly(2), |mut args| Ok({
+            let rhs = List::extract(args.pop().unwrap())?;
+            let lhs = List::extract(args.pop().unwrap())?;
+            let rhs: ValueSet = rhs.borrow().iter().collect();
+            let lhs = lhs.borrow();
+            let both = lhs.iter().filter(|&item| rhs.contains(item));
+            Value::from_slice(distinct(both.cloned()))
+        }))?;
+
+        std.def_native("difference", Exactly(2), |mut args| Ok({
+            let rhs = List::extract(args.pop().unwrap())?;
+            let lhs = List::extract(args.pop().unwrap())?;
+            let rhs: ValueSet = rhs.borrow().iter().collect();
+            let lhs = lhs.borrow();
+            let only = lhs.iter().filter(|&item| !rhs.contains(item));
+            Value::from_slice(distinct(only.cloned()))
+        }))?;
+
+        // Only one level deep
+        std.def_native("flatten", Exactly(1), |mut args| Ok({
+            let list = List::extract(args.pop().unwrap())?;
+            let mut items: Vec<Value> = vec![];
+            for item in list.borrow().iter() {
+                match *item {
+                    Value::List(ref inner) => {
+                        items.extend(inner.borrow().iter().cloned());
+                    },
+                    ref other => items.push(other.clone()),
+                }
+            }
+            Value::from_slice(items)
+        }))?;
+
+        std.def_native("same", Exactly(2), |mut args| Ok({
+            let rhs = args.pop().unwrap();
+            let lhs = args.pop().unwrap();
+            lhs.same(&rhs)
+        }))?;
+
+        std.def_native("contains", Exactly(2), |mut args| Ok({
+            let item = args.pop().unwrap();
+            let list = List::extract(args.pop().unwrap())?;
+            let found = list.borrow().contains(&item);
+            found
+        }))?;
+
+        std.def_native("enumerate", Exactly(1), |mut args| Ok({
+            let items = args.pop().unwrap().iterate()?;
+            Value::from_iter(items.into_iter().enumerate().map(|(i, item)| {
+                Value::from_slice([Value::Int(i as Int), item])
+            }))
+        }))?;
+
+        // Stops at the end of the shorter list
+        std.def_native("zip", Exactly(2), |mut args| Ok({
+            let rhs = args.pop().unwrap().iterate()?;
+            let lhs = args.pop().unwrap().iterate()?;
+            Value::from_iter(lhs.into_iter().zip(rhs).map(|(lhs, rhs)| {
+                Value::from_slice([lhs, rhs])
+            }))
+        }))?;
+
+        // Every `step`th Int from `lo` through `hi`
+        std.def_native_ctx("range", Between(2, 3), |interp, args| {
+            use std::mem::size_of;
+
+            let mut args = args.into_iter();
+            let lo = Int::extract(args.next().unwrap())?;
+            let hi = Int::extract(args.next().unwrap())?;
+            let step = args.next().map(Int::extract).unwrap_or(Ok(1))?;
+
+            if step == 0 {
+                return Err(Error::ZeroStep);
+            }
+
+            // Counting the wrong way gives nothing
+            let span = hi as i128 - lo as i128;
+            let count = if span.signum() == -step.signum() as i128 {
+                0
+            } else {
+                span / step as i128 + 1
+            };
+
+            let count = usize::try_from(count).map_err(|_| Error::OutOfMemory)?;
+            interp.reserve(count.saturating_mul(size_of::<Value>()))?;
+
+            let mut ints = VecDeque::new();
+            ints.try_reserve_exact(count).map_err(|_| Error::OutOfMemory)?;
+
+            // Only the step past the last item can overflow, and it's
+            // never used
+            let mut next = lo;
+            for _ in 0 .. count {
+                ints.push_back(Value::Int(next));
+                next = next.saturating_add(step);
+            }
+
+            Ok(List::new(ints.into()))
+        })?;
+
+        std.def_native_ctx("warn", Exactly(1), |interp, mut args| Ok({
+            let message = args.pop().unwrap().to_string();
+            interp.log(Level::Warn, &message);
+        }))?;
+
+        std.def_native_ctx("log", Exactly(2), |interp, mut args| Ok({
+            let message = args.pop().unwrap().to_string();
+            let name = args.pop().unwrap().record_key()?;
+            let level = Level::from_name(&name).ok_or_else(|| {
+                Error::NoSuchLevel { name: name.to_string() }
+            })?;
+            interp.log(level, &message);
+        }))?;
+
+        // Returns how many containers were freed
+        std.def_native_ctx("gc", Exactly(0), |interp, _| {
+            Ok(interp.gc() as Int)
+        })?;
+
+        std.def_native_ctx("gc_stats", Exactly(0), |interp, _| {
+            let stats = interp.gc_stats();
+
+            let record = Record::default();
+            {
+                let mut record = record.borrow_mut();
+                let mut set = |key: &str, count: u64| {
+                    record.insert(Str::from(key), Value::Int(count as Int));
+                };
+
+                set("allocated", stats.allocated);
+                set("live", stats.live());
+                set("collections", stats.collections);
+                set("collected", stats.collected);
+            }
+
+            Ok(record)
+        })?;
+
+        // Handlers for events the host emits
+        std.def_native_ctx("on", Exactly(2), |interp, mut args| Ok({
+            let handler = args.pop().unwrap();
+            let event = Ident::extract(args.pop().unwrap())?;
+            interp.on(event, handler)?;
+        }))?;
+
+        std.def_native_ctx("off", Exactly(1), |interp, mut args| Ok({
+            let event = Ident::extract(args.pop().unwrap())?;
+            interp.off(&event);
+        }))?;
+
+        std.def_native_ctx("argv", Exactly(0), |interp, _| {
+            Ok(interp.argv())
+        })?;
+
+        // Unwinds the whole interpreter; try/catch cannot intercept it
+        std.def_native("exit", Between(0, 1), |mut args| {
+            let code = match args.pop() {
+                Some(code) => Int::extract(code)?,
+                None => 0,
+            };
+
+            let code = i32::try_from(code)
+                .map_err(|_| Error::IntegerOverflow)?;
+
+            Err::<(), _>(Error::Exit { code })
+        })?;
+
+        std.def_native("new", AtLeast(0), |args| Ok({
+            if !args.is_empty() {
+                println!("Warning: Arguments to new() not implemented");
+            }
+
+            Record::new(HashMap::new().into())
+        }))?;
+
+        std.def_native_ctx("map", Exactly(2), |interp, mut args| {
+            let list = List::extract(args.pop().unwrap())?;
+            let func = args.pop().unwrap();
+
+            let items = list.borrow().clone();
+            let mut result = VecDeque::with_capacity(items.len());
+            for item in items.into_iter() {
+                result.push_back(interp.call_value(func.clone(), vec![item])?);
+            }
+
+            Ok(List::new(result.into()))
+        })?;
+
+        std.def_native_ctx("filter", Exactly(2), |interp, mut args| {
+            let list = List::extract(args.pop().unwrap())?;
+            let func = args.pop().unwrap();
+
+            let items = list.borrow().clone();
+            let mut result = VecDeque::new();
+            for item in items.into_iter() {
+                let keep = interp.call_value(func.clone(), vec![item.clone()])?;
+                if keep.truthy() {
+                    result.push_back(item);
+                }
+            }
+
+            Ok(List::new(result.into()))
+        })?;
+
+        std.def_native_ctx("reduce", Exactly(3), |interp, mut args| {
+            let list = List::extract(args.pop().unwrap())?;
+            let mut acc = args.pop().unwrap();
+            let func = args.pop().unwrap();
+
+            let items = list.borrow().clone();
+            for item in items.into_iter() {
+                acc = interp.call_value(func.clone(), vec![acc, item])?;
+            }
+
+            Ok(acc)
+        })?;
+
+        // -1, 0 or 1 by the order `sort` uses, which takes any two values
+        std.def_native("compare", Exactly(2), |mut args| {
+            let rhs = args.pop().unwrap();
+            let lhs = args.pop().unwrap();
+            Ok(lhs.order(&rhs) as Int)
+        })?;
+
+        std.def_native_ctx("sort", Between(1, 2), |interp, args| {
+            let mut args = args.into_iter();
+            let list = List::extract(args.next().unwrap())?;
+            let items = list.borrow().iter().cloned().collect();
+
+            let sorted = match args.next() {
+                None => merge_sort(items, &mut |lhs, rhs| Ok(lhs.order(rhs)))?,
+
+                Some(func) => merge_sort(items, &mut |lhs, rhs| {
+                    let argv = vec![lhs.clone(), rhs.clone()];
+                    let order = interp.call_value(func.clone(), argv)?;
+                    Ok(Int::extract(order)?.cmp(&0))
+                })?,
+            };
+
+            Ok(List::new(VecDeque::from(sorted).into()))
+        })?;
+
+        std.def_native("die", Exactly(1), |mut args| {
+            let message = args.pop().unwrap().to_string();
+            Err::<(), _>(Error::Die { message })
+        })?;
+
+        std.def_native("assert_eq", Exactly(2), |mut args| Ok({
+            let rhs = args.pop().unwrap();
+            let lhs = args.pop().unwrap();
+            if lhs != rhs {
+                return Err(Error::AssertMessage {
+                    expr: "assert_eq".into(),
+                    message: format!("got {}, wanted {}", lhs, rhs),
+                });
+            }
+        }))?;
+
+        Ok(std)
+    }
+}
+
+// What each field of a `Profile` switches on
+const STDIO_NATIVES: &[&str] = &[
+    "print", "say", "print_no_nl", "printf", "readline", "read_all_stdin",
+];
+const TIME_NATIVES: &[&str] = &["time", "clock_ms", "sleep"];
+const ENV_NATIVES: &[&str] = &["env"];
+const PROCESS_NATIVES: &[&str] = &["run"];
+
+/// A stable sort that stops at the first error. Comparators written in
+/// script can be inconsistent, which `slice::sort_by` may panic on.
+fn merge_sort<F>(mut items: Vec<Value>, cmp: &mut F) -> Result<Vec<Value>>
+    where F: FnMut(&Value, &Value) -> Result<Ordering>
+{
+    if items.len() < 2 {
+        return Ok(items);
+    }
+
+    let back = items.split_off(items.len() / 2);
+    let front = merge_sort(items, cmp)?;
+    let back = merge_sort(back, cmp)?;
+
+    let mut merged = Vec::with_capacity(front.len() + back.len());
+    let mut front = front.into_iter().peekable();
+    let mut back = back.into_iter().peekable();
+
+    while let (Some(lhs), Some(rhs)) = (front.peek(), back.peek()) {
+        if cmp(lhs, rhs)? == Ordering::Greater {
+            merged.extend(back.next());
+        } else {
+            merged.extend(front.next());
+        }
+    }
+
+    merged.extend(front);
+    merged.extend(back);
+    Ok(merged)
+}
+
+/// Where `index` falls in a string of `len` characters. Negative
+/// positions count back from the end, as they do for `$str[-1]`.
+fn char_index(len: usize, index: Int) -> Result<usize> {
+    list_index(len, index)
+}
+
+/// Values grouped by hash, for the set natives. Not a `HashSet`, since a
+/// Float that is NaN is not equal to itself.
+#[derive(Default)]
+struct ValueSet {
+    buckets: HashMap<u64, Vec<Value>>,
+}
+
+impl ValueSet {
+    fn bucket(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn contains(&self, value: &Value) -> bool {
+        self.buckets.get(&Self::bucket(value))
+            .is_some_and(|bucket| bucket.contains(value))
+    }
+
+    /// False if it was already there.
+    fn insert(&mut self, value: Value) -> bool {
+        let bucket = self.buckets.entry(Self::bucket(&value)).or_default();
+        if bucket.contains(&value) {
+            return false;
+        }
+
+        bucket.push(value);
+        true
+    }
+}
+
+impl<'a> std::iter::FromIterator<&'a Value> for ValueSet {
+    fn from_iter<I: IntoIterator<Item=&'a Value>>(items: I) -> Self {
+        let mut set = ValueSet::default();
+        for item in items {
+            set.insert(item.clone());
+        }
+        set
     }
+}
 
-    pub fn stdlib() -> Result<Self> {
-        use self::Argc::*;
+/// The first of each group of equal items.
+fn distinct<I: Iterator<Item=Value>>(items: I) -> Vec<Value> {
+    let mut seen = ValueSet::default();
+    items.filter(|item| seen.insert(item.clone())).collect()
+}
 
-        let mut std = Module {
-            begin: InterpretedFn::from_vec(vec![]),
-            strings: Strings::new(),
-            functions: HashMap::new(),
-        };
+fn split_str(text: &str, sep: &str, limit: Option<usize>) -> Vec<String> {
+    let mut pieces: Vec<String> = if sep.is_empty() {
+        let mut chars: Vec<String> = text.chars().map(String::from).collect();
 
-        fn map_to_string(items: Vec<Value>) -> Vec<String> {
-            items.into_iter().map(|i| format!("{}", i)).collect()
+        if let Some(limit) = limit {
+            if chars.len() > limit {
+                let rest = chars.split_off(limit - 1).concat();
+                chars.push(rest);
+            }
         }
 
-        std.def_native("print", AtLeast(1), |args| Ok({
-            println!("{}", map_to_string(args).join(" "));
-        }))?;
+        chars
+    } else if let Some(limit) = limit {
+        text.splitn(limit, sep).map(String::from).collect()
+    } else {
+        text.split(sep).map(String::from).collect()
+    };
 
-        std.def_native("str", AtLeast(1), |args| Ok({
-            Str::from(map_to_string(args).concat())
-        }))?;
+    if limit.is_none() {
+        trim_trailing_empty(&mut pieces);
+    }
 
-        std.def_native("len", Exactly(1), |mut args| Ok({
-            let arg = List::extract(args.pop().unwrap())?;
-            let arg = arg.borrow();
-            arg.len() as Int
-        }))?;
+    pieces
+}
 
-        std.def_native("split", AtLeast(1), |_args| {
-            //use pattern::*;
-
-            //let mut args = args.into_iter();
-            //let text = Str::extract(args.next().unwrap())?;
-            //let pat = match args.next() {
-            //    Some(pat) => Pattern::extract(pat)?,
-            //    None => Pattern::Find(" ".into())
-            //};
-
-            //match pat {
-            //    Pattern::Find(pat) => {
-            //        let pat: &str = pat.as_ref();
-            //        Value::from_iter(text.split(pat).map(|s| {
-            //            Str::from(s)
-            //        }))
-            //    }
-            //}
-
-            if false { return Ok(()) }
-
-            return Err(Error::UnimplementedFeature {
-                feature: "pattern matching",
-            });
-        })?;
+fn split_pattern(text: &str, pat: &Pattern, limit: Option<usize>)
+    -> Vec<String>
+{
+    let mut pieces = vec![];
+    let mut start = 0;
+    let mut search = 0;
 
-        std.def_native("new", AtLeast(0), |args| Ok({
-            if !args.is_empty() {
-                println!("Warning: Arguments to new() not implemented");
+    while limit.is_none_or(|limit| pieces.len() + 1 < limit) {
+        let found = pat.matches(&text[search ..])
+            .and_then(|captures| captures.get(&0).cloned());
+
+        let (left, right) = match found {
+            Some((left, right)) => (search + left, search + right),
+            None => break,
+        };
+
+        // An empty match can't split off an empty piece, so look again
+        // one character further along
+        if left == right && left == start {
+            match text[left ..].chars().next() {
+                Some(ch) => {
+                    search = left + ch.len_utf8();
+                    continue;
+                },
+
+                None => break,
             }
+        }
 
-            Record::new(HashMap::new().into())
-        }))?;
+        pieces.push(text[start .. left].to_owned());
+        start = right;
+        search = right;
+    }
 
-        std.def_native("assert_eq", Exactly(2), |mut args| Ok({
-            let rhs = args.pop().unwrap();
-            let lhs = args.pop().unwrap();
-            assert_eq!(lhs, rhs);
-        }))?;
+    pieces.push(text[start ..].to_owned());
 
-        Ok(std)
+    if limit.is_none() {
+        trim_trailing_empty(&mut pieces);
+    }
+
+    pieces
+}
+
+fn trim_trailing_empty(pieces: &mut Vec<String>) {
+    while pieces.last().is_some_and(|piece| piece.is_empty()) {
+        pieces.pop();
     }
 }
 
 impl ast::Module {
     pub fn translate(self) -> Result<Module> {
-        let mut module = Module::stdlib()?;
+        self.translate_in(&Strings::new())
+    }
 
-        module.begin = {
-            let mut asm = Assembler::new(&mut module.strings, vec![]);
+    /// Translate into a module that shares `strings`, which should be the
+    /// set the source was tokenized with.
+    pub fn translate_in(self, strings: &Strings) -> Result<Module> {
+        let (module, errors) = self.translate_all_in(strings)?;
 
-            for stmt in self.begin.into_iter() {
-                asm.tr_stmt(stmt)?;
-            }
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(module),
+        }
+    }
 
-            asm.build()?
-        };
+    /// Like `translate`, but keep going after a sub fails to compile, and
+    /// return every error alongside whatever was built.
+    pub fn translate_all(self) -> Result<(Module, Vec<Error>)> {
+        self.translate_all_in(&Strings::new())
+    }
+
+    pub fn translate_all_in(self, strings: &Strings)
+        -> Result<(Module, Vec<Error>)>
+    {
+        self.translate_into(Module::stdlib_in(strings)?)
+    }
+
+    /// Like `translate_all`, but add to `module`, compiling with its
+    /// settings. Its strings should be the set the source was tokenized
+    /// with.
+    pub fn translate_into(mut self, mut module: Module)
+        -> Result<(Module, Vec<Error>)>
+    {
+        let mut errors = vec![];
+
+        // Only the compile functions know where to load these from
+        if let Some(name) = self.imports.first() {
+            return Err(Error::UnresolvedImport { name: name.clone() });
+        }
+
+        self.resolve(&module.strings)?;
+
+        let mut asm = Assembler::new(&mut module, vec![]);
+
+        if asm.tr_each(self.begin, &mut errors, |err| err) {
+            match asm.build() {
+                Ok(begin) => module.begin = begin,
+                Err(err) => errors.push(err),
+            }
+        }
 
         for def in self.defs.into_iter() {
-            module.def(def)?;
+            if let Err(err) = module.def_into(def, &mut errors) {
+                errors.push(err);
+            }
         }
 
-        Ok(module)
+        Ok((module, errors))
     }
 }
 
@@ -163,6 +1126,7 @@ enum Lvalue {
 }
 
 impl ast::Expr {
+    #[allow(clippy::wrong_self_convention)]
     fn as_lvalue(self) -> Result<Lvalue> {
         use ast::{Expr, Binop};
 
@@ -183,27 +1147,35 @@ impl ast::Expr {
 }
 
 impl<'a> Assembler<'a> {
-    fn new(strings: &'a mut Strings, args: Vec<Ident>) -> Self {
-        let mut scope = HashMap::new();
+    fn new(module: &'a mut Module, args: Vec<Ident>) -> Self {
+        let mut scope = Scope::default();
         for (i, arg) in args.into_iter().enumerate() {
-            scope.insert(arg, i);
+            scope.names.insert(arg, i);
         }
 
         Assembler {
-            strings,
+            strings: &module.strings,
+            constants: &mut module.constants,
+            optimize: module.optimize,
             code: vec![],
             scopes: vec![scope],
             labels: HashMap::new(),
             next_gensym: 0,
+            lines: vec![],
+            pos: None,
+            vars: vec![],
         }
     }
 
-    fn build(self) -> Result<InterpretedFn> {
+    fn build(mut self) -> Result<InterpretedFn> {
         if self.scopes.len() != 1 {
             return Err(Error::InternalCompilerErr);
         }
 
-        let Assembler { code, labels, .. } = self;
+        self.leave()?;
+        self.vars.sort_by_key(|var| (var.start, var.slot));
+
+        let Assembler { code, labels, mut lines, mut vars, optimize, .. } = self;
 
         let resolve = |label| -> Result<usize> {
             labels.get(&label).cloned().ok_or(Error::NoSuchLabel)
@@ -220,38 +1192,95 @@ impl<'a> Assembler<'a> {
                 Op::JNZ { dst }
             },
 
+            Op::JARG { index, dst } => {
+                let dst = resolve(dst)?;
+                Op::JARG { index, dst }
+            },
+
+            Op::NEXT { iter, dst } => {
+                let dst = resolve(dst)?;
+                Op::NEXT { iter, dst }
+            },
+
+            Op::TRY { dst } => {
+                let dst = resolve(dst)?;
+                Op::TRY { dst }
+            },
+
             Op::NIL => Op::NIL,
             Op::RET => Op::RET,
             Op::NOT => Op::NOT,
+            Op::TYPEOF => Op::TYPEOF,
+            Op::SLICE => Op::SLICE,
             Op::DUP => Op::DUP,
             Op::DROP => Op::DROP,
             Op::GLOBALS => Op::GLOBALS,
             Op::INS => Op::INS,
+            Op::ITER => Op::ITER,
+            Op::UNTRY => Op::UNTRY,
+            Op::THROW => Op::THROW,
             Op::LOAD { src } => Op::LOAD { src },
             Op::STORE { dst } => Op::STORE { dst },
             Op::GROUP { num } => Op::GROUP { num },
+            Op::PUSHB { boolean } => Op::PUSHB { boolean },
             Op::PUSHI { int } => Op::PUSHI { int },
+            Op::PUSHF { float } => Op::PUSHF { float },
             Op::PUSHS { string } => Op::PUSHS { string },
             Op::PUSHN { name } => Op::PUSHN { name },
             Op::PAT { pat } => Op::PAT { pat },
             Op::LIST { len } => Op::LIST { len },
             Op::STR { len } => Op::STR { len },
-            Op::REC => Op::REC,
+            Op::REC { len } => Op::REC { len },
+            Op::UNPACK { len } => Op::UNPACK { len },
+            Op::REST { index } => Op::REST { index },
             Op::CALL { name, argc } => Op::CALL { name, argc },
+            Op::METHOD { name, argc } => Op::METHOD { name, argc },
             Op::BINOP { op } => Op::BINOP { op },
             Op::MARK { len } => Op::MARK { len },
             Op::ASSERT { expr } => Op::ASSERT { expr },
+            Op::FAIL { expr } => Op::FAIL { expr },
         })).collect::<Result<Vec<Op>>>()?;
 
-        Ok(InterpretedFn::from_vec(code))
+        let code = if optimize {
+            optimize::peephole(code, &mut lines, &mut vars)
+        } else {
+            code
+        };
+
+        Ok(InterpretedFn::with_lines(code, lines).with_vars(vars))
     }
 
     fn enter(&mut self) {
-        self.scopes.push(HashMap::new());
+        let start = self.code.len();
+        self.scopes.push(Scope { start, ..Scope::default() });
     }
 
     fn leave(&mut self) -> Result<()> {
-        Ok({ self.scopes.pop().ok_or(Error::InternalCompilerErr)?; })
+        let scope = self.scopes.pop().ok_or(Error::InternalCompilerErr)?;
+        let (start, end) = (scope.start, self.code.len());
+
+        self.vars.extend(scope.names.into_iter().map(|(name, slot)| {
+            LocalVar { name, slot, start, end }
+        }));
+
+        Ok(())
+    }
+
+    /// Translate each of `stmts`, carrying on past any that fail so every
+    /// error is reported. Returns whether they all translated.
+    fn tr_each<F>(&mut self, stmts: Vec<ast::Stmt>, errors: &mut Vec<Error>, context: F)
+        -> bool
+        where F: Fn(Error) -> Error
+    {
+        let failed = errors.len();
+
+        for stmt in stmts.into_iter() {
+            if let Err(err) = self.tr_stmt(stmt) {
+                errors.push(context(err));
+            }
+        }
+
+        errors.len() == failed
     }
 
     fn tr_block(&mut self, body: Vec<ast::Stmt>) -> Result<()> {
@@ -269,9 +1298,21 @@ impl<'a> Assembler<'a> {
         use ast::{Stmt, Expr, Literal};
 
         match stmt {
+            Stmt::At { pos, stmt } => {
+                let outer = self.pos.replace(pos);
+                self.lines.push((self.code.len(), pos));
+                self.tr_stmt(*stmt).map_err(|err| err.at_offset(pos))?;
+
+                // Code after a nested statement belongs to its parent
+                self.pos = outer;
+                if let Some(outer) = outer {
+                    self.lines.push((self.code.len(), outer));
+                }
+            },
+
             Stmt::My { lhs, rhs } => {
                 self.tr_expr(rhs.unwrap_or(Expr::Literal(Literal::Nil)))?;
-                self.local(lhs)?;
+                self.bind(lhs)?;
             },
 
             Stmt::Assign { lhs, rhs } => match lhs.as_lvalue()? {
@@ -289,6 +1330,53 @@ impl<'a> Assembler<'a> {
 
                 Lvalue::SetGlobal { name } => {
                     self.tr_expr(rhs)?;
+                    let name = self.constants.add_name(name);
+                    self.emit(Op::PUSHN { name });
+                    self.emit(Op::GLOBALS);
+                    self.emit(Op::INS);
+                },
+            },
+
+            Stmt::Update { lhs, op, rhs } => match lhs.as_lvalue()? {
+                Lvalue::Store { lhs } => {
+                    self.load(lhs.clone())?;
+                    self.tr_expr(rhs)?;
+                    self.binop(op);
+                    self.store(lhs)?;
+                },
+
+                Lvalue::Insert { lhs, idx } => {
+                    // Evaluate the container and index only once
+                    let len = self.depth();
+                    self.enter();
+
+                    self.tr_expr(lhs)?;
+                    let lhs = self.temp()?;
+                    self.tr_expr(idx)?;
+                    let idx = self.temp()?;
+
+                    self.emit(Op::LOAD { src: lhs });
+                    self.emit(Op::LOAD { src: idx });
+                    self.emit(Op::BINOP { op: Binop::IDX });
+                    self.tr_expr(rhs)?;
+                    self.binop(op);
+
+                    self.emit(Op::LOAD { src: idx });
+                    self.emit(Op::LOAD { src: lhs });
+                    self.emit(Op::INS);
+
+                    self.leave()?;
+                    self.emit(Op::MARK { len });
+                },
+
+                Lvalue::SetGlobal { name } => {
+                    let name = self.constants.add_name(name);
+                    self.emit(Op::GLOBALS);
+                    self.emit(Op::PUSHN { name });
+                    self.emit(Op::BINOP { op: Binop::IDX });
+                    self.tr_expr(rhs)?;
+                    self.binop(op);
+
                     self.emit(Op::PUSHN { name });
                     self.emit(Op::GLOBALS);
                     self.emit(Op::INS);
@@ -300,12 +1388,24 @@ impl<'a> Assembler<'a> {
                 self.emit(Op::RET);
             },
 
-            Stmt::Assert { rhs } => {
-                let expr = (&rhs).to_string();
+            Stmt::Assert { rhs, message: None } => {
+                let expr = self.constants.add_string(rhs.to_string().into());
                 self.tr_expr(rhs)?;
                 self.emit(Op::ASSERT { expr });
             },
 
+            // The message is only built if the assertion fails
+            Stmt::Assert { rhs, message: Some(message) } => {
+                let expr = self.constants.add_string(rhs.to_string().into());
+                let after = self.gensym()?;
+
+                self.tr_expr(rhs)?;
+                self.emit(Op::JNZ { dst: after });
+                self.tr_expr(message)?;
+                self.emit(Op::FAIL { expr });
+                self.label(after)?;
+            },
+
             Stmt::If { clauses, last } => {
                 let after = self.gensym()?;
 
@@ -313,20 +1413,102 @@ impl<'a> Assembler<'a> {
                 for (cond, body) in clauses.into_iter() {
                     let label = self.gensym()?;
                     self.tr_expr(cond)?;
-                    self.emit(Op::JNZ { dst: label.clone() });
+                    self.emit(Op::JNZ { dst: label });
+                    bodies.push((label, body));
+                }
+
+                self.tr_block(last)?;
+                self.emit(Op::JUMP { dst: after });
+
+                for (label, body) in bodies.into_iter() {
+                    self.label(label)?;
+                    self.tr_block(body)?;
+                    self.emit(Op::JUMP { dst: after });
+                }
+
+                self.label(after)?;
+            },
+
+            Stmt::Given { subject, arms, last } => {
+                use ast::Case;
+
+                let len = self.depth();
+                let after = self.gensym()?;
+
+                self.enter();
+                self.tr_expr(subject)?;
+                let subject = self.temp()?;
+
+                let mut bodies = vec![];
+                for (cases, body) in arms.into_iter() {
+                    let label = self.gensym()?;
+
+                    for case in cases.into_iter() {
+                        self.emit(Op::LOAD { src: subject });
+
+                        match case {
+                            Case::Value(expr) => {
+                                self.tr_expr(expr)?;
+                                self.emit(Op::BINOP { op: Binop::EQ });
+                            },
+
+                            Case::Type(name) => {
+                                if !Value::TYPE_NAMES.contains(&name.as_ref()) {
+                                    return Err(Error::NoSuchType { name });
+                                }
+
+                                let name = self.constants.add_name(name);
+                                self.emit(Op::TYPEOF);
+                                self.emit(Op::PUSHN { name });
+                                self.emit(Op::BINOP { op: Binop::EQ });
+                            },
+
+                            Case::Match(pat) => {
+                                // Only strings can match a pattern
+                                let skip = self.gensym()?;
+                                let name = self.strings.intern("Str")?;
+                                let name = self.constants.add_name(name);
+                                self.emit(Op::TYPEOF);
+                                self.emit(Op::PUSHN { name });
+                                self.emit(Op::BINOP { op: Binop::NE });
+                                self.emit(Op::JNZ { dst: skip });
+                                self.emit(Op::LOAD { src: subject });
+                                self.tr_expr(pat)?;
+                                self.emit(Op::BINOP { op: Binop::MATCH });
+                                self.emit(Op::JNZ { dst: label });
+                                self.label(skip)?;
+                                continue;
+                            },
+                        }
+
+                        self.emit(Op::JNZ { dst: label });
+                    }
+
                     bodies.push((label, body));
                 }
 
                 self.tr_block(last)?;
-                self.emit(Op::JUMP { dst: after.clone() });
+                self.emit(Op::JUMP { dst: after });
 
                 for (label, body) in bodies.into_iter() {
                     self.label(label)?;
                     self.tr_block(body)?;
-                    self.emit(Op::JUMP { dst: after.clone() });
+                    self.emit(Op::JUMP { dst: after });
                 }
 
                 self.label(after)?;
+                self.leave()?;
+                self.emit(Op::MARK { len });
+            },
+
+            Stmt::Unless { test, body, last } => {
+                let test = Expr::Not(test.into());
+                self.tr_stmt(Stmt::If { clauses: vec![(test, body)], last })?;
+            },
+
+            Stmt::Until { test, body } => {
+                let test = Expr::Not(test.into());
+                self.tr_stmt(Stmt::While { test, body })?;
             },
 
             Stmt::While { test, body } => {
@@ -334,17 +1516,104 @@ impl<'a> Assembler<'a> {
                 let after = self.gensym()?;
 
                 self.tr_expr(Expr::Not(test.clone().into()))?;
-                self.emit(Op::JNZ { dst: after.clone() });
+                self.emit(Op::JNZ { dst: after });
 
-                self.label(before.clone())?;
+                self.label(before)?;
                 self.tr_block(body)?;
 
                 self.tr_expr(test)?;
-                self.emit(Op::JNZ { dst: before.clone() });
+                self.emit(Op::JNZ { dst: before });
+
+                self.label(after)?;
+            },
+
+            Stmt::For { var, lo, hi, body } => {
+                let len = self.depth();
+                let before = self.gensym()?;
+                let test = self.gensym()?;
+
+                self.enter();
+
+                self.tr_expr(lo)?;
+                self.local(var.clone())?;
+                let counter = self.lookup(var)?;
+
+                self.tr_expr(hi)?;
+                let limit = self.temp()?;
+
+                self.emit(Op::JUMP { dst: test });
+
+                self.label(before)?;
+                self.tr_block(body)?;
+
+                self.emit(Op::LOAD { src: counter });
+                self.emit(Op::PUSHI { int: 1 });
+                self.emit(Op::BINOP { op: Binop::ADD });
+                self.emit(Op::STORE { dst: counter });
+
+                self.label(test)?;
+                self.emit(Op::LOAD { src: counter });
+                self.emit(Op::LOAD { src: limit });
+                self.emit(Op::BINOP { op: Binop::LE });
+                self.emit(Op::JNZ { dst: before });
+
+                self.leave()?;
+                self.emit(Op::MARK { len });
+            },
+
+            Stmt::Foreach { var, list, body } => {
+                let len = self.depth();
+                let before = self.gensym()?;
+                let after = self.gensym()?;
+
+                self.enter();
+
+                self.tr_expr(list)?;
+                self.emit(Op::ITER);
+                let iter = self.temp()?;
+
+                self.emit(Op::NIL);
+                self.local(var.clone())?;
+
+                self.label(before)?;
+                self.emit(Op::NEXT { iter, dst: after });
+                self.store(var)?;
+                self.tr_block(body)?;
+                self.emit(Op::JUMP { dst: before });
+
+                self.label(after)?;
+                self.leave()?;
+                self.emit(Op::MARK { len });
+            },
+
+            Stmt::Try { body, var, handler } => {
+                let len = self.depth();
+                let catch = self.gensym()?;
+                let after = self.gensym()?;
+
+                self.emit(Op::TRY { dst: catch });
+                self.tr_block(body)?;
+                self.emit(Op::UNTRY);
+                self.emit(Op::JUMP { dst: after });
+
+                // The handler finds the thrown value on top of the stack
+                self.label(catch)?;
+                self.enter();
+                self.local(var)?;
+                for stmt in handler.into_iter() {
+                    self.tr_stmt(stmt)?;
+                }
+                self.leave()?;
+                self.emit(Op::MARK { len });
 
                 self.label(after)?;
             },
 
+            Stmt::Throw { rhs } => {
+                self.tr_expr(rhs)?;
+                self.emit(Op::THROW);
+            },
+
             Stmt::Bare { rhs } => {
                 self.tr_expr(rhs)?;
                 self.emit(Op::DROP);
@@ -371,8 +1640,9 @@ impl<'a> Assembler<'a> {
             },
 
             Expr::Global(id) => {
+                let name = self.constants.add_name(id);
                 self.emit(Op::GLOBALS);
-                self.emit(Op::PUSHN { name: id });
+                self.emit(Op::PUSHN { name });
                 self.emit(Op::BINOP { op: Binop::IDX });
             },
 
@@ -404,13 +1674,14 @@ impl<'a> Assembler<'a> {
             },
 
             Expr::Record(pairs) => {
-                self.emit(Op::REC);
+                let len = pairs.len();
 
                 for (key, val) in pairs.into_iter() {
-                    self.emit(Op::PUSHN { name: key });
+                    self.tr_expr(key)?;
                     self.tr_expr(val)?;
-                    self.emit(Op::INS);
                 }
+
+                self.emit(Op::REC { len });
             },
 
             Expr::Binop { lhs, op, rhs } => {
@@ -446,6 +1717,28 @@ impl<'a> Assembler<'a> {
                 self.label(after)?;
             },
 
+            Expr::Slice { lhs, lo, hi } => {
+                self.tr_expr(*lhs)?;
+                self.tr_expr(*lo)?;
+                self.tr_expr(*hi)?;
+                self.emit(Op::SLICE);
+            },
+
+            Expr::Coalesce { lhs, rhs } => {
+                let after = self.gensym()?;
+
+                // lhs ne nil ? lhs : rhs
+
+                self.tr_expr(*lhs)?;
+                self.emit(Op::DUP);
+                self.emit(Op::NIL);
+                self.emit(Op::BINOP { op: Binop::NE });
+                self.emit(Op::JNZ { dst: after });
+                self.emit(Op::DROP);
+                self.tr_expr(*rhs)?;
+                self.label(after)?;
+            },
+
             Expr::Not(expr) => {
                 self.tr_expr(*expr)?;
                 self.emit(Op::NOT);
@@ -460,6 +1753,18 @@ impl<'a> Assembler<'a> {
 
                 self.call(name.as_ref(), argc)?;
             },
+
+            Expr::Method { recv, name, args } => {
+                let argc = args.len();
+
+                self.tr_expr(*recv)?;
+                for arg in args.into_iter() {
+                    self.tr_expr(arg)?;
+                }
+
+                let name = self.constants.add_name(name);
+                self.emit(Op::METHOD { name, argc });
+            },
         }
 
         Ok(())
@@ -469,17 +1774,26 @@ impl<'a> Assembler<'a> {
         use ast::Literal;
 
         match lit.into() {
+            Literal::Bool(boolean) => {
+                self.emit(Op::PUSHB { boolean });
+            },
+
             Literal::Int(int) => {
                 self.emit(Op::PUSHI { int });
             },
 
+            Literal::Float(float) => {
+                self.emit(Op::PUSHF { float });
+            },
+
             Literal::Str(string) => {
                 let string = self.strings.intern(&string)?;
+                let string = self.constants.add_string(string);
                 self.emit(Op::PUSHS { string });
             },
 
             Literal::Pattern(ast) => {
-                use pattern::{Var, Expr};
+                use pattern::Var;
                 use backpat::parse::Ast;
 
                 let ast: Ast<Var<usize>> = ast.map(|var: &Var<Ident>| {
@@ -497,13 +1811,14 @@ impl<'a> Assembler<'a> {
                     }
                 })?;
 
-                let pat: Expr = Arc::new(ast);
+                let pat = self.constants.add_pattern(Arc::new(ast));
 
                 self.emit(Op::PAT { pat });
             },
 
             Literal::Ident(id) => {
-                self.emit(Op::PUSHN { name: id });
+                let name = self.constants.add_name(id);
+                self.emit(Op::PUSHN { name });
             },
 
             Literal::Nil => {
@@ -536,18 +1851,18 @@ impl<'a> Assembler<'a> {
     }
 
     fn depth(&self) -> usize {
-        self.scopes.iter().map(|scope| scope.len()).sum()
+        self.scopes.iter().map(|scope| scope.names.len() + scope.temps).sum()
     }
 
     fn local(&mut self, id: Ident) -> Result<()> {
         let index = self.depth();
 
         if let Some(scope) = self.scopes.last_mut() {
-            if scope.contains_key(&id) {
+            if scope.names.contains_key(&id) {
                 return Err(Error::VariableRenamed);
             }
 
-            scope.insert(id, index);
+            scope.names.insert(id, index);
         } else {
             return Err(Error::InternalCompilerErr);
         }
@@ -557,9 +1872,103 @@ impl<'a> Assembler<'a> {
         Ok(())
     }
 
+    /// Declare a parameter, filling it with `default` if it was not passed.
+    fn param(&mut self, name: Ident, default: ast::Expr) -> Result<()> {
+        let index = self.depth();
+        let passed = self.gensym()?;
+
+        self.emit(Op::JARG { index, dst: passed });
+        self.tr_expr(default)?;
+        self.emit(Op::MARK { len: index + 1 });
+        self.label(passed)?;
+
+        self.scopes.last_mut()
+            .ok_or(Error::InternalCompilerErr)?
+            .names.insert(name, index);
+
+        Ok(())
+    }
+
+    /// Declare a parameter holding the list of all remaining arguments.
+    fn rest(&mut self, name: Ident) -> Result<()> {
+        let index = self.depth();
+        self.emit(Op::REST { index });
+        self.local(name)
+    }
+
+    /// Declare the variables in `binding`, taking their values apart from
+    /// the top of the stack.
+    fn bind(&mut self, binding: ast::Binding) -> Result<()> {
+        use ast::Binding;
+
+        match binding {
+            Binding::Var(name) => self.local(name),
+
+            Binding::List(items) => {
+                let base = self.depth();
+                let len = items.len();
+                self.emit(Op::UNPACK { len });
+
+                // Name every slot before loading nested items above them
+                let mut nested = vec![];
+                for (i, item) in items.into_iter().enumerate() {
+                    let scope = self.scopes.last_mut()
+                        .ok_or(Error::InternalCompilerErr)?;
+
+                    match item {
+                        Binding::Var(name) => {
+                            if scope.names.insert(name, base + i).is_some() {
+                                return Err(Error::VariableRenamed);
+                            }
+                        },
+
+                        other => {
+                            scope.temps += 1;
+                            nested.push((base + i, other));
+                        },
+                    }
+                }
+
+                self.emit(Op::MARK { len: base + len });
+
+                for (src, item) in nested.into_iter() {
+                    self.emit(Op::LOAD { src });
+                    self.bind(item)?;
+                }
+
+                Ok(())
+            },
+
+            Binding::Record(pairs) => {
+                let src = self.temp()?;
+
+                for (key, item) in pairs.into_iter() {
+                    self.emit(Op::LOAD { src });
+                    self.tr_expr(key)?;
+                    self.emit(Op::BINOP { op: Binop::IDX });
+                    self.bind(item)?;
+                }
+
+                Ok(())
+            },
+        }
+    }
+
+    fn temp(&mut self) -> Result<usize> {
+        let index = self.depth();
+
+        self.scopes.last_mut()
+            .ok_or(Error::InternalCompilerErr)?
+            .temps += 1;
+
+        self.emit(Op::MARK { len: index + 1 });
+
+        Ok(index)
+    }
+
     fn lookup(&self, id: Ident) -> Result<usize> {
         for scope in self.scopes.iter().rev() {
-            if let Some(&index) = scope.get(&id) {
+            if let Some(&index) = scope.names.get(&id) {
                 return Ok(index);
             }
         }
@@ -581,6 +1990,7 @@ impl<'a> Assembler<'a> {
 
     fn call(&mut self, name: &str, argc: usize) -> Result<()> {
         let name = self.strings.intern(name)?;
+        let name = self.constants.add_name(name);
         self.emit(Op::CALL { name, argc });
         Ok(())
     }
@@ -591,10 +2001,20 @@ impl<'a> Assembler<'a> {
             ast::Binop::Sub => Binop::SUB,
             ast::Binop::Div => Binop::DIV,
             ast::Binop::Mul => Binop::MUL,
+            ast::Binop::Mod => Binop::MOD,
             ast::Binop::Idx => Binop::IDX,
             ast::Binop::Match => Binop::MATCH,
             ast::Binop::Equal => Binop::EQ,
             ast::Binop::NotEqual => Binop::NE,
+            ast::Binop::Less => Binop::LT,
+            ast::Binop::Greater => Binop::GT,
+            ast::Binop::LessEqual => Binop::LE,
+            ast::Binop::GreaterEqual => Binop::GE,
+            ast::Binop::StrLess => Binop::SLT,
+            ast::Binop::StrGreater => Binop::SGT,
+            ast::Binop::StrLessEqual => Binop::SLE,
+            ast::Binop::StrGreaterEqual => Binop::SGE,
+            ast::Binop::Compare => Binop::CMP,
         };
 
         self.emit(Op::BINOP { op });
@@ -0,0 +1,26 @@
+use eval::Interpreter;
+
+/// What a paused interpreter should do next.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Resume {
+    /// Run until the next breakpoint
+    Continue,
+    /// Pause again before the next opcode
+    Step,
+    /// Stop the script with `Interrupted`
+    Abort,
+}
+
+/// Watches a running script. The interpreter pauses at breakpoints set with
+/// `Interpreter::set_breakpoint`, and before every opcode while stepping,
+/// and hands itself to `pause` to be inspected.
+pub trait Debugger {
+    /// Called before the opcode at `interp.pc()` runs.
+    fn pause(&mut self, interp: &Interpreter) -> Resume;
+}
+
+impl<F> Debugger for F where F: FnMut(&Interpreter) -> Resume {
+    fn pause(&mut self, interp: &Interpreter) -> Resume {
+        self(interp)
+    }
+}
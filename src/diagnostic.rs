@@ -0,0 +1,118 @@
+use std::fmt;
+
+use lalrpop_util::ParseError;
+
+use super::*;
+use opcode::{Location, Source};
+
+/// An error pinned to a place in the source, printed with the offending
+/// line and a caret under the column.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// `None` for errors that don't say where they happened
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Report `err` at its place in `source`, or without a place if it
+    /// doesn't have one.
+    pub fn place(err: &Error, source: &Source) -> Self {
+        Diagnostic::from_error(err, source).unwrap_or_else(|| Diagnostic {
+            location: None,
+            message: err.to_string(),
+        })
+    }
+
+    /// Find where `err` happened in `source`, if the error says.
+    pub fn from_error(err: &Error, source: &Source) -> Option<Self> {
+        let (location, message) = match *err {
+            Error::Parse { ref parse } => match **parse {
+                ParseError::InvalidToken { location } => {
+                    (source.locate(location), "invalid token".into())
+                },
+
+                ParseError::UnrecognizedToken { token: None, .. } => {
+                    let end = source.text.len();
+                    (source.locate(end), "unexpected end of file".into())
+                },
+
+                ParseError::UnrecognizedToken {
+                    token: Some((left, ref token, _)),
+                    ref expected,
+                } => {
+                    let mut message = format!("unexpected `{}`", token);
+
+                    if !expected.is_empty() {
+                        message += &format!(", expected one of {}", expected.join(", "));
+                    }
+
+                    (source.locate(left), message)
+                },
+
+                ParseError::ExtraToken { token: (left, ref token, _) } => {
+                    (source.locate(left), format!("extra token `{}`", token))
+                },
+
+                ParseError::User { ref error } => {
+                    return Diagnostic::from_error(error, source);
+                },
+            },
+
+            Error::AtOffset { offset, ref cause } => {
+                (source.locate(offset), cause.to_string())
+            },
+
+            Error::WithContext { ref context, ref cause } => {
+                let inner = Diagnostic::from_error(cause, source)?;
+                let message = format!("in {}: {}", context, inner.message);
+                return Some(Diagnostic { message, ..inner });
+            },
+
+            Error::WithPosition { line, column, ref cause } => {
+                (source.at(line, column), cause.to_string())
+            },
+
+            Error::AtLocation { ref location, ref cause } => {
+                (location.clone(), cause.to_string())
+            },
+
+            Error::Traced { ref cause, .. } => {
+                return Diagnostic::from_error(cause, source);
+            },
+
+            _ => return None,
+        };
+
+        Some(Diagnostic { location: Some(location), message })
+    }
+}
+
+/// The source line at `location`, with a caret under its column.
+pub fn excerpt(location: &Location) -> String {
+    let gutter = location.line.to_string();
+
+    let indent: String = location.source_line.chars()
+        .take(location.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    format!("{} | {}\n{} | {}^",
+        gutter,
+        location.source_line,
+        " ".repeat(gutter.len()),
+        indent)
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(ref location) => {
+                writeln!(f, "{}: {}", location, self.message)?;
+                write!(f, "{}", excerpt(location))
+            },
+
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use opcode::*;
+
+impl InterpretedFn {
+    /// A readable listing of the bytecode, one instruction per line, with
+    /// jump targets named `L0`, `L1`, ... in the order they appear.
+    /// `constants` should belong to the module the code was compiled for.
+    pub fn disassemble(&self, constants: &Constants) -> String {
+        let ops = self.ops();
+
+        let mut labels = BTreeMap::new();
+        for op in ops.iter() {
+            if let Some(dst) = op.target() {
+                labels.insert(dst, 0);
+            }
+        }
+
+        for (i, name) in labels.values_mut().enumerate() {
+            *name = i;
+        }
+
+        let mut out = String::new();
+
+        for (pc, op) in ops.iter().enumerate() {
+            if let Some(label) = labels.get(&pc) {
+                let _ = writeln!(out, "L{}:", label);
+            }
+
+            let listing = Listing { op, labels: Some(&labels), constants };
+            let _ = writeln!(out, "    {:04}  {}", pc, listing);
+        }
+
+        // A jump may land just past the last instruction
+        if let Some(label) = labels.get(&ops.len()) {
+            let _ = writeln!(out, "L{}:", label);
+        }
+
+        out
+    }
+}
+
+impl Module {
+    /// Disassemble the top-level code and every script sub, sorted by name.
+    pub fn disassemble(&self) -> String {
+        let mut subs: Vec<_> = self.functions.iter()
+            .filter_map(|(name, (_, func))| match *func {
+                Func::Interpreted(ref code) => Some((name.as_ref(), code)),
+                _ => None,
+            })
+            .collect();
+
+        subs.sort_by_key(|&(name, _)| name);
+
+        let constants = &self.constants;
+        let mut out = format!("(top level):\n{}", self.begin.disassemble(constants));
+
+        for (name, code) in subs {
+            let _ = write!(out, "\nsub {}:\n{}", name, code.disassemble(constants));
+        }
+
+        out
+    }
+}
+
+impl Op {
+    /// Show one instruction, with jump targets as plain instruction numbers.
+    pub fn listing<'a>(&'a self, constants: &'a Constants) -> impl fmt::Display + 'a {
+        Listing { op: self, labels: None, constants }
+    }
+}
+
+struct Listing<'a> {
+    op: &'a Op,
+    labels: Option<&'a BTreeMap<usize, usize>>,
+    constants: &'a Constants,
+}
+
+impl<'a> Listing<'a> {
+    // Out of range constants are shown by index, since a listing is often
+    // wanted for exactly the code that is broken
+    fn string(&self, index: usize) -> String {
+        self.constants.string(index)
+            .map_or_else(|_| format!("#{}", index), |s| s.to_string())
+    }
+
+    fn name(&self, index: usize) -> String {
+        self.constants.name(index)
+            .map_or_else(|_| format!("#{}", index), |name| name.to_string())
+    }
+}
+
+mod display {
+    use std::fmt::{Display, Formatter, Result};
+
+    use super::*;
+
+    impl<'a> Display for Listing<'a> {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            let label = |dst: usize| match self.labels.and_then(|labels| labels.get(&dst)) {
+                Some(label) => format!("L{}", label),
+                None => format!("{}", dst),
+            };
+
+            match *self.op {
+                Op::RET => write!(f, "RET"),
+                Op::DUP => write!(f, "DUP"),
+                Op::DROP => write!(f, "DROP"),
+                Op::NOT => write!(f, "NOT"),
+                Op::TYPEOF => write!(f, "TYPEOF"),
+                Op::SLICE => write!(f, "SLICE"),
+                Op::NIL => write!(f, "NIL"),
+                Op::GLOBALS => write!(f, "GLOBALS"),
+                Op::INS => write!(f, "INS"),
+                Op::ITER => write!(f, "ITER"),
+                Op::UNTRY => write!(f, "UNTRY"),
+                Op::THROW => write!(f, "THROW"),
+                Op::CALL { name, argc } => write!(f, "CALL {}, {}", self.name(name), argc),
+                Op::METHOD { name, argc } => write!(f, "METHOD {}, {}", self.name(name), argc),
+                Op::BINOP { op } => write!(f, "BINOP {:?}", op),
+                Op::LOAD { src } => write!(f, "LOAD {}", src),
+                Op::STORE { dst } => write!(f, "STORE {}", dst),
+                Op::GROUP { num } => write!(f, "GROUP {}", num),
+                Op::PUSHB { boolean } => write!(f, "PUSHB {}", boolean),
+                Op::PUSHI { int } => write!(f, "PUSHI {}", int),
+                Op::PUSHF { float } => write!(f, "PUSHF {:?}", float),
+                Op::PUSHS { string } => write!(f, "PUSHS {:?}", self.string(string)),
+                Op::PUSHN { name } => write!(f, "PUSHN :{}", self.name(name)),
+                Op::PAT { pat } => match self.constants.pattern(pat) {
+                    Ok(pat) => write!(f, "PAT {}", pat),
+                    Err(_) => write!(f, "PAT #{}", pat),
+                },
+                Op::LIST { len } => write!(f, "LIST {}", len),
+                Op::STR { len } => write!(f, "STR {}", len),
+                Op::REC { len } => write!(f, "REC {}", len),
+                Op::UNPACK { len } => write!(f, "UNPACK {}", len),
+                Op::REST { index } => write!(f, "REST {}", index),
+                Op::JUMP { dst } => write!(f, "JUMP {}", label(dst)),
+                Op::JNZ { dst } => write!(f, "JNZ {}", label(dst)),
+                Op::JARG { index, dst } => write!(f, "JARG {}, {}", index, label(dst)),
+                Op::NEXT { iter, dst } => write!(f, "NEXT {}, {}", iter, label(dst)),
+                Op::TRY { dst } => write!(f, "TRY {}", label(dst)),
+                Op::MARK { len } => write!(f, "MARK {}", len),
+                Op::ASSERT { expr } => write!(f, "ASSERT {:?}", self.string(expr)),
+                Op::FAIL { expr } => write!(f, "FAIL {:?}", self.string(expr)),
+            }
+        }
+    }
+}
@@ -1,76 +1,937 @@
 use std::iter::FromIterator;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as Atomic};
+use std::time::{Duration, Instant};
 
 use super::*;
 use value::*;
 use opcode::*;
 use pattern::*;
+use debug::{Debugger, Resume};
+use logger::{Level, Logger};
 
 use backpat::GroupNumber;
 
 pub struct Interpreter {
-    main: Module,
+    // Shared by every interpreter spawned from the same module
+    main: Arc<Module>,
     strings: Strings,
     globals: Record,
     frame: Frame,
     saved: Vec<Frame>,
+    thrown: Option<Value>,
+    argv: List,
+    // Opcodes left before FuelExhausted, if limited
+    fuel: Option<usize>,
+    // Heap bytes allowed before MemoryLimit, if limited
+    memory_limit: Option<usize>,
+    // Last measured heap size, plus whatever was made since
+    memory_used: usize,
+    interrupted: Arc<AtomicBool>,
+    // Calls nested deeper than this fail with StackOverflow
+    max_depth: usize,
+    // Unwinding never pops below this many saved frames
+    floor: usize,
+    // Names of the top-level locals, for code entered through `eval`
+    slots: Vec<Option<Ident>>,
+    // Print each opcode to stderr before running it
+    tracing: bool,
+    // Per-function counts, collected while `profiling` is set, keyed by
+    // function (None for the top level)
+    profiling: bool,
+    profile: HashMap<Option<Ident>, Stats>,
+    debugger: Option<Box<dyn Debugger + Send>>,
+    // Where `warn` and `log` send their messages
+    logger: Box<dyn Logger + Send>,
+    // Where `print` writes
+    output: Box<dyn Write + Send>,
+    // Pcs to pause at, keyed by function (None for the top level)
+    breakpoints: HashMap<Option<Ident>, BTreeSet<usize>>,
+    stepping: bool,
+    // Containers stored into, which could be part of a cycle
+    collector: gc::Collector,
+    // Subs to call for each event, in the order they were added
+    handlers: HashMap<Ident, Vec<Value>>,
+    // Saved-frame depths of calls from `begin_call`, innermost last
+    resuming: Vec<usize>,
 }
 
-struct Frame {
+/// How far `Interpreter::run_until` got.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Progress {
+    /// Out of budget; call `run_until` again to carry on
+    Paused,
+    /// The call from `begin_call` returned this, or the top-level code
+    /// ran to the end (giving nil)
+    Finished(Value),
+}
+
+/// What the profiler saw of one function.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stats {
+    pub calls: u64,
+    /// Opcodes run in the function's own frame
+    pub ops: u64,
+    /// Wall time spent on those opcodes, including native calls they make;
+    /// for natives, the time spent in the call
+    pub time: Duration,
+}
+
+/// How deeply script functions may recurse unless `set_max_depth` says
+/// otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// Lets another thread stop a running interpreter. The interpreter fails
+/// with `Interrupted` before its next opcode, then clears the request.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Atomic::SeqCst);
+    }
+}
+
+/// The call stack at the point where an uncaught error was raised,
+/// innermost call first.
+#[derive(Clone, Debug)]
+pub struct Trace(pub Vec<TraceFrame>);
+
+#[derive(Clone, Debug)]
+pub struct TraceFrame {
+    /// `None` for the top level of the script
+    pub func: Option<Ident>,
+    /// The instruction that failed, or the call still in progress
+    pub pc: usize,
+    /// Where `pc` came from, if the module knows its source
+    pub location: Option<Location>,
+}
+
+/// A call in progress, as seen through `Interpreter::frames`.
+pub struct Frame {
+    // None for the top-level statements
+    name: Option<Ident>,
     code: InterpretedFn,
     mark: usize,
     locals: Vec<Value>,
     groups: BTreeMap<GroupNumber, Str>,
+    handlers: Vec<Handler>,
+    pc: usize,
+}
+
+impl Frame {
+    /// The sub this frame is running, or `None` for the top level.
+    pub fn function_name(&self) -> Option<&Ident> {
+        self.name.as_ref()
+    }
+
+    /// The next instruction to run. For callers, this is just past the
+    /// call.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn code(&self) -> &InterpretedFn {
+        &self.code
+    }
+
+    /// Where the frame is in the source: the current statement, or for
+    /// callers, the one making the call.
+    pub fn location(&self) -> Option<Location> {
+        self.code.locate(self.pc.saturating_sub(1))
+    }
+
+    /// The named variables in scope, in the order they were declared.
+    /// Shadowed variables are left out.
+    pub fn locals(&self) -> Vec<(Ident, Value)> {
+        let mut seen = HashSet::new();
+
+        let mut locals: Vec<_> = self.code.vars_at(self.pc, self.mark)
+            .into_iter().rev()
+            .filter(|var| seen.insert(var.name.clone()))
+            .map(|var| (var.name.clone(), self.locals[var.slot].clone()))
+            .collect();
+
+        locals.reverse();
+        locals
+    }
+
+    /// The variable in scope called `name`.
+    pub fn local(&self, name: &str) -> Option<Value> {
+        self.locals().into_iter()
+            .find(|(var, _)| var.as_ref() == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// An active `try` block, restored when something is thrown inside it
+struct Handler {
     pc: usize,
+    mark: usize,
+    depth: usize,
 }
 
 impl Module {
     pub fn start(self) -> Result<Interpreter> {
-        let mut this = Interpreter {
+        let mut this = Interpreter::new(Arc::new(self), Vec::<String>::new())?;
+        this.run()?;
+        Ok(this)
+    }
+
+    /// Like `start`, but scripts can read `args` through `argv()` or the
+    /// `%ARGV` global.
+    pub fn start_with_args<I>(self, args: I) -> Result<Interpreter>
+        where I: IntoIterator, I::Item: AsRef<str>
+    {
+        let mut this = self.prepare(args)?;
+        this.run()?;
+        Ok(this)
+    }
+
+    /// Set up an interpreter without running the top-level code, so it can
+    /// be configured first. Call `run` to start it. Like `start_with_args`,
+    /// it sets `%ARGV`.
+    pub fn prepare<I>(self, args: I) -> Result<Interpreter>
+        where I: IntoIterator, I::Item: AsRef<str>
+    {
+        let mut this = Interpreter::new(Arc::new(self), args)?;
+        let args = this.argv.borrow().clone();
+        this.set_global("ARGV", List::new(args.into()))?;
+        Ok(this)
+    }
+
+    /// Start another interpreter running this module, with globals and
+    /// frames of its own. The bytecode is shared, not copied, so this is
+    /// cheap enough to do once per request.
+    pub fn spawn_interpreter(this: &Arc<Module>) -> Result<Interpreter> {
+        let mut interp = Interpreter::new(this.clone(), Vec::<String>::new())?;
+        interp.run()?;
+        Ok(interp)
+    }
+}
+
+impl Interpreter {
+    fn new<I>(main: Arc<Module>, args: I) -> Result<Interpreter>
+        where I: IntoIterator, I::Item: AsRef<str>
+    {
+        let argv = args.into_iter().map(|arg| Str::from(arg.as_ref()));
+
+        let this = Interpreter {
             frame: Frame {
-                code: self.begin.clone(),
+                name: None,
+                code: main.begin.clone(),
                 locals: vec![],
                 groups: BTreeMap::new(),
+                handlers: vec![],
                 mark: 0,
                 pc: 0,
             },
 
-            main: self,
-            strings: Strings::new(),
+            // Shared with the module, so names interned at runtime match
+            strings: main.strings.clone(),
+            main,
             globals: Record::default(),
             saved: vec![],
+            thrown: None,
+            fuel: None,
+            memory_limit: None,
+            memory_used: 0,
+            interrupted: Arc::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            argv: List::new(argv.map(Value::from).collect::<VecDeque<_>>().into()),
+            floor: 0,
+            slots: vec![],
+            tracing: false,
+            profiling: false,
+            profile: HashMap::new(),
+            debugger: None,
+            logger: Box::new(logger::Stderr),
+            output: Box::new(io::stdout()),
+            breakpoints: HashMap::new(),
+            stepping: false,
+            collector: gc::Collector::default(),
+            handlers: HashMap::new(),
+            resuming: vec![],
         };
 
-        while this.frame.pc < this.frame.code.len() {
-            this.step()?;
-        }
-
         Ok(this)
     }
 }
 
 impl Interpreter {
+    /// Run the top-level code of an interpreter from `Module::prepare`.
+    pub fn run(&mut self) -> Result<()> {
+        while self.frame.pc < self.frame.code.len() {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Run at most `budget` opcodes of the top-level code, or of the
+    /// latest call from `begin_call` if one is unfinished, so scripts can
+    /// share a thread with the host's own loop.
+    pub fn run_until(&mut self, budget: usize) -> Result<Progress> {
+        use std::mem::replace;
+
+        let base = self.resuming.last().cloned();
+
+        // Uncaught errors unwind no further than the call being resumed
+        let floor = match base {
+            Some(base) => replace(&mut self.floor, base + 1),
+            None => self.floor,
+        };
+
+        let mut result = Ok(());
+        let mut spent = 0;
+
+        let progress = loop {
+            let done = match base {
+                Some(base) => self.saved.len() <= base,
+                None => self.frame.pc >= self.frame.code.len(),
+            };
+
+            if done || result.is_err() || spent == budget {
+                break done;
+            }
+
+            result = self.step();
+            spent += 1;
+        };
+
+        self.floor = floor;
+
+        if let Err(err) = result {
+            self.resuming.pop();
+            return Err(err);
+        }
+
+        if !progress {
+            return Ok(Progress::Paused);
+        }
+
+        match self.resuming.pop() {
+            Some(_) => Ok(Progress::Finished(self.pop()?)),
+            None => Ok(Progress::Finished(Value::Nil(()))),
+        }
+    }
+
+    /// Start calling `func` without running it, so `run_until` can run it
+    /// a slice at a time.
+    pub fn begin_call<A: IntoArgs>(&mut self, func: &str, args: A) -> Result<()> {
+        let func: Ident = self.strings.intern(func)?;
+        let base = self.saved.len();
+
+        self.fncall(&func, args.into_args())?;
+        self.resuming.push(base);
+        Ok(())
+    }
+
     pub fn exec(&mut self, func: &str, args: &[Value]) -> Result<Value> {
         let func = self.strings.intern(func)?;
-        self.fncall(&func, args.to_owned())?;
+        self.invoke(&func, args.to_owned())
+    }
 
-        while self.saved.len() > 0 {
-            self.step()?;
+    /// Like `exec`, but convert the arguments from Rust values and the
+    /// result to `R`.
+    pub fn call<R: Extract, A: IntoArgs>(&mut self, func: &str, args: A)
+        -> Result<R>
+    {
+        let result = self.exec(func, &args.into_args())?;
+
+        R::extract(result).map_err(|err| {
+            err.decorate(format!("result of {}", func))
+        })
+    }
+
+    /// Run a function to completion, even from inside a native call.
+    pub(crate) fn invoke(&mut self, name: &Ident, argv: Vec<Value>)
+        -> Result<Value>
+    {
+        use std::mem::replace;
+
+        let base = self.saved.len();
+
+        self.fncall(name, argv)?;
+
+        if self.saved.len() > base {
+            let floor = replace(&mut self.floor, base + 1);
+
+            let mut result = Ok(());
+            while result.is_ok() && self.saved.len() > base {
+                result = self.step();
+            }
+
+            self.floor = floor;
+            result?;
         }
 
         self.pop()
     }
 
+    /// Call a script function through a callable value.
+    pub fn call_value(&mut self, func: Value, argv: Vec<Value>)
+        -> Result<Value>
+    {
+        let name = Ident::extract(func)?;
+        self.invoke(&name, argv)
+    }
+
+    /// Call `handler` whenever the host emits `event`. Scripts do this
+    /// with `on(:event, :sub_name)`.
+    pub fn on(&mut self, event: Ident, handler: Value) -> Result<()> {
+        Ident::extract(handler.clone())?;
+        self.handlers.entry(event).or_default().push(handler);
+        Ok(())
+    }
+
+    /// Forget every handler for `event`.
+    pub fn off(&mut self, event: &Ident) {
+        self.handlers.remove(event);
+    }
+
+    /// Call each handler for `event` with `args`, in the order they were
+    /// added, and collect what they return. Handlers added while this
+    /// runs wait for the next emit.
+    pub fn emit<A: IntoArgs>(&mut self, event: &str, args: A)
+        -> Result<Vec<Value>>
+    {
+        let event: Ident = self.strings.intern(event)?;
+        let args = args.into_args();
+
+        let handlers = match self.handlers.get(&event) {
+            Some(handlers) => handlers.clone(),
+            None => return Ok(vec![]),
+        };
+
+        handlers.into_iter()
+            .map(|handler| self.call_value(handler, args.clone()))
+            .collect()
+    }
+
+    /// The script arguments passed to `start_with_args`.
+    pub fn argv(&self) -> List {
+        self.argv.clone()
+    }
+
+    /// The module this interpreter runs, including subs added by `eval`.
+    pub fn module(&self) -> &Module {
+        &self.main
+    }
+
+    /// Every global that has been set, sorted by name.
+    pub fn globals(&self) -> Vec<(Str, Value)> {
+        let mut globals: Vec<_> = self.globals.borrow().iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        globals.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+        globals
+    }
+
+    /// The same as `globals`, as an iterator. It walks a copy, so scripts
+    /// called meanwhile don't disturb it.
+    pub fn globals_iter(&self) -> impl Iterator<Item=(Str, Value)> {
+        self.globals().into_iter()
+    }
+
+    /// The active calls, innermost first, ending with the top level.
+    pub fn frames(&self) -> Vec<&Frame> {
+        Some(&self.frame).into_iter()
+            .chain(self.saved.iter().rev())
+            .collect()
+    }
+
+    /// The named variables in scope in the running function, or at the
+    /// top level between calls.
+    pub fn locals(&self) -> Vec<(Ident, Value)> {
+        self.frame.locals()
+    }
+
+    /// The variable in scope called `name`.
+    pub fn local(&self, name: &str) -> Option<Value> {
+        self.frame.local(name)
+    }
+
+    /// The running sub, or `None` at the top level.
+    pub fn function(&self) -> Option<&Ident> {
+        self.frame.function_name()
+    }
+
+    /// The next instruction to run in the current function.
+    pub fn pc(&self) -> usize {
+        self.frame.pc
+    }
+
+    /// Where the next instruction came from in the source.
+    pub fn location(&self) -> Option<Location> {
+        self.frame.code.locate(self.frame.pc)
+    }
+
+    /// Make a symbol, sharing storage with the script's own strings.
+    pub fn intern(&mut self, name: &str) -> Result<Ident> {
+        self.strings.intern(name)
+    }
+
+    pub fn global(&mut self, name: &str) -> Result<Option<Value>> {
+        let name: Ident = self.strings.intern(name)?;
+        Ok(self.globals.borrow().get(name.as_ref()).cloned())
+    }
+
+    /// Like `global`, but convert the value to `V`. A global that was
+    /// never set is `None` rather than an error.
+    pub fn get_global<V: Extract>(&mut self, name: &str) -> Result<Option<V>> {
+        match self.global(name)? {
+            Some(value) => V::extract(value).map(Some).map_err(|err| {
+                err.decorate(format!("global %{}", name))
+            }),
+
+            None => Ok(None),
+        }
+    }
+
     pub fn set_global<V>(&mut self, name: &str, value: V) -> Result<()>
         where V: Into<Value>
     {
         let value = value.into();
         let name: Ident = self.strings.intern(name)?;
-        self.globals.borrow_mut().insert(name, value);
+        self.globals.borrow_mut().insert(name.into(), value);
+        Ok(())
+    }
+
+    /// Run `src` as more top-level code, as if it followed everything
+    /// evaluated so far. Locals declared with `my` stay visible to later
+    /// calls, and subs it defines replace any of the same name. A final
+    /// bare expression gives the result; otherwise it's `None`.
+    ///
+    /// If `src` fails, locals it declared are forgotten.
+    pub fn eval(&mut self, name: &str, src: &str) -> Result<Option<Value>> {
+        let source = Source::new(name, src);
+
+        let (code, slots, value) = self.compile_snippet(&source)
+            .map_err(|err| err.diagnose(&source))?;
+
+        let depth = self.frame.locals.len();
+        let mark = self.frame.mark;
+        let handlers = self.frame.handlers.len();
+
+        self.frame.code = code.with_source(source);
+        self.frame.pc = 0;
+
+        let mut result = Ok(());
+        while result.is_ok() && (self.frame.pc < self.frame.code.len() || !self.saved.is_empty()) {
+            result = self.step();
+        }
+
+        if let Err(err) = result {
+            if !self.saved.is_empty() {
+                self.frame = self.saved.swap_remove(0);
+                self.saved.clear();
+            }
+
+            self.frame.locals.truncate(depth);
+            self.frame.handlers.truncate(handlers);
+            self.frame.mark = mark;
+            self.thrown = None;
+            return Err(err);
+        }
+
+        self.slots = slots;
+
+        if value {
+            self.pop().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn compile_snippet(&mut self, source: &Source)
+        -> Result<(InterpretedFn, Vec<Option<Ident>>, bool)>
+    {
+        let tokens = token::Tokenizer::with_strings(&source.text, &self.strings)
+            .spanned();
+        let mut ast = ast::parse_module(tokens)?;
+        ast.resolve(&self.strings)?;
+
+        // Other interpreters sharing the module keep the old definitions
+        let main = Arc::make_mut(&mut self.main);
+
+        for def in ast.defs.into_iter() {
+            main.def(def)?;
+        }
+
+        main.attach_source(source.clone());
+
+        let mut slots = self.slots.clone();
+        slots.resize(self.frame.locals.len(), None);
+        main.snippet(&slots, ast.begin)
+    }
+
+    /// Like `exec`, but give up with `FuelExhausted` after `max_ops`
+    /// opcodes, counting any nested calls made by natives.
+    pub fn exec_with_fuel(&mut self, func: &str, args: &[Value], max_ops: usize)
+        -> Result<Value>
+    {
+        let fuel = self.fuel.replace(max_ops);
+        let result = self.exec(func, args);
+        self.fuel = fuel;
+        result
+    }
+
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Stop the script with `MemoryLimit` once the strings, Lists and
+    /// Records it can reach take up more than `bytes`, roughly. Scripts
+    /// cannot catch the error. `None` lifts the limit.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.memory_limit = bytes;
+        self.memory_used = match bytes {
+            Some(_) => self.memory_usage(),
+            None => 0,
+        };
+    }
+
+    /// Roughly how many heap bytes the values reachable from globals and
+    /// the stack hold. Shared values are counted once.
+    pub fn memory_usage(&self) -> usize {
+        let mut todo: Vec<Value> = vec![
+            self.globals.clone().into(),
+            self.argv.clone().into(),
+        ];
+
+        todo.extend(self.thrown.clone());
+
+        for frame in self.frames() {
+            todo.extend(frame.locals.iter().cloned());
+            todo.extend(frame.groups.values().cloned().map(Value::from));
+        }
+
+        let mut seen = HashSet::new();
+        let mut total = 0;
+
+        while let Some(value) = todo.pop() {
+            let addr = match value {
+                Value::Str(ref s) => s.as_ptr() as usize,
+                Value::List(ref list) => Arc::as_ptr(list) as usize,
+                Value::Record(ref rec) => Arc::as_ptr(rec) as usize,
+                _ => continue,
+            };
+
+            if !seen.insert(addr) {
+                continue;
+            }
+
+            total += value.heap_size();
+
+            match value {
+                Value::List(ref list) => {
+                    todo.extend(list.borrow().iter().cloned());
+                },
+
+                Value::Record(ref rec) => {
+                    todo.extend(rec.borrow().values().cloned());
+                },
+
+                _ => (),
+            }
+        }
+
+        total
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(self.interrupted.clone())
+    }
+
+    /// Log every opcode at the debug level as it runs, along with the
+    /// stack depth and the value on top of the stack.
+    pub fn set_trace(&mut self, tracing: bool) {
+        self.tracing = tracing;
+    }
+
+    /// Count calls, opcodes and time per function while `profiling` is set.
+    /// Turning it off keeps what was collected so far.
+    pub fn set_profile(&mut self, profiling: bool) {
+        self.profiling = profiling;
+    }
+
+    /// The profiled functions, slowest first. The top-level code is the
+    /// one named `None`.
+    pub fn profile(&self) -> Vec<(Option<Ident>, Stats)> {
+        let mut profile: Vec<_> = self.profile.iter()
+            .map(|(name, &stats)| (name.clone(), stats))
+            .collect();
+
+        profile.sort_by(|a, b| b.1.time.cmp(&a.1.time)
+            .then_with(|| a.0.cmp(&b.0)));
+
+        profile
+    }
+
+    /// Free Lists and Records that only refer to each other, such as a list
+    /// stored in itself, and return how many were freed.
+    pub fn gc(&mut self) -> usize {
+        self.collector.collect()
+    }
+
+    pub fn gc_stats(&self) -> gc::Stats {
+        self.collector.stats()
+    }
+
+    /// Attach a debugger, which is paused at breakpoints. If `step` is set
+    /// it is paused before the first opcode as well.
+    pub fn set_debugger<D>(&mut self, debugger: D, step: bool)
+        where D: Debugger + Send + 'static
+    {
+        self.debugger = Some(Box::new(debugger));
+        self.stepping = step;
+    }
+
+    pub fn clear_debugger(&mut self) -> Option<Box<dyn Debugger + Send>> {
+        self.stepping = false;
+        self.debugger.take()
+    }
+
+    /// Send what the script logs to `logger` instead of stderr, returning
+    /// the logger it replaces.
+    pub fn set_logger<L>(&mut self, logger: L) -> Box<dyn Logger + Send>
+        where L: Logger + Send + 'static
+    {
+        std::mem::replace(&mut self.logger, Box::new(logger))
+    }
+
+    /// Send what the script prints to `output` instead of stdout, returning
+    /// the writer it replaces.
+    pub fn set_output<W>(&mut self, output: W) -> Box<dyn Write + Send>
+        where W: Write + Send + 'static
+    {
+        std::mem::replace(&mut self.output, Box::new(output))
+    }
+
+    /// Where `print` writes.
+    pub fn output(&mut self) -> &mut (dyn Write + Send) {
+        &mut *self.output
+    }
+
+    /// Pass `message` to the logger, from wherever the script is now.
+    pub fn log(&mut self, level: Level, message: &str) {
+        let location = self.frame.code.locate(self.frame.pc.saturating_sub(1));
+        self.logger.log(level, message, location);
+    }
+
+    /// Pause before `pc` in the sub `func`, or in the top-level code if
+    /// `func` is `None`.
+    pub fn set_breakpoint(&mut self, func: Option<&str>, pc: usize) -> Result<()> {
+        let func = match func {
+            Some(name) => Some(self.strings.intern(name)?),
+            None => None,
+        };
+
+        self.breakpoints.entry(func).or_default().insert(pc);
+        Ok(())
+    }
+
+    pub fn clear_breakpoint(&mut self, func: Option<&str>, pc: usize) -> Result<()> {
+        let func = match func {
+            Some(name) => Some(self.strings.intern(name)?),
+            None => None,
+        };
+
+        if let Some(pcs) = self.breakpoints.get_mut(&func) {
+            pcs.remove(&pc);
+        }
+
         Ok(())
     }
 
+    fn should_pause(&self) -> bool {
+        self.stepping || self.breakpoints.get(&self.frame.name)
+            .is_some_and(|pcs| pcs.contains(&self.frame.pc))
+    }
+
+    fn trace_op(&mut self) {
+        let frame = &self.frame;
+
+        let op = match frame.code.fetch(frame.pc) {
+            Ok(op) => op,
+            Err(_) => return,
+        };
+
+        let func = match frame.name {
+            Some(ref name) => name.as_ref(),
+            None => "(top level)",
+        };
+
+        let top = match frame.locals.last() {
+            Some(Value::Str(ref s)) => format!("{:?}", s.as_ref() as &str),
+            Some(value) => value.to_string(),
+            None => "-".into(),
+        };
+
+        let line = format!("{}:{:04}  {:<24} depth {}, top {}",
+            func, frame.pc, op.listing(&self.main.constants).to_string(), frame.locals.len(), top);
+
+        self.logger.log(Level::Debug, &line, None);
+    }
+
     pub fn step(&mut self) -> Result<()> {
+        if self.interrupted.swap(false, Atomic::SeqCst) {
+            return self.unwind(Error::Interrupted);
+        }
+
+        if let Some(ref mut fuel) = self.fuel {
+            if *fuel == 0 {
+                return self.unwind(Error::FuelExhausted);
+            }
+
+            *fuel -= 1;
+        }
+
+        if self.debugger.is_some() && self.should_pause() {
+            let mut debugger = self.debugger.take()
+                .ok_or(Error::InternalCompilerErr)?;
+
+            let resume = debugger.pause(self);
+            self.debugger = Some(debugger);
+
+            match resume {
+                Resume::Continue => self.stepping = false,
+                Resume::Step => self.stepping = true,
+                Resume::Abort => return self.unwind(Error::Interrupted),
+            }
+        }
+
+        if self.tracing {
+            self.trace_op();
+        }
+
+        if !self.profiling {
+            return self.dispatch().or_else(|err| self.unwind(err));
+        }
+
+        let name = self.frame.name.clone();
+        let start = Instant::now();
+        let result = self.dispatch();
+
+        let stats = self.profile.entry(name).or_default();
+        stats.ops += 1;
+        stats.time += start.elapsed();
+
+        result.or_else(|err| self.unwind(err))
+    }
+
+    /// Pop frames until one has a handler that can catch `err`.
+    fn unwind(&mut self, err: Error) -> Result<()> {
+        if err.is_fatal() || !self.is_guarded() {
+            let err = match err {
+                Error::Exit { .. } | Error::Traced { .. } => err,
+                cause => {
+                    let trace = self.backtrace();
+
+                    let cause = match trace.0[0].location.clone() {
+                        Some(location) => Error::AtLocation {
+                            location,
+                            cause: Box::new(cause),
+                        },
+
+                        None => cause,
+                    };
+
+                    Error::Traced { trace, cause: Box::new(cause) }
+                },
+            };
+
+            // A nested run hands the error back to its caller's frame
+            if self.floor > 0 {
+                while self.saved.len() >= self.floor {
+                    self.frame = self.saved.pop()
+                        .ok_or(Error::StackUnderflow)?;
+                }
+            } else {
+                // The top level stops where it failed, and can't go on
+                // without the value the failed call would have left
+                while let Some(frame) = self.saved.pop() {
+                    self.frame = frame;
+                }
+
+                self.frame.pc = self.frame.code.len();
+            }
+
+            return Err(err);
+        }
+
+        // A nested run may have traced an error that we can still catch
+        let err = match err {
+            Error::Traced { cause, .. } => match *cause {
+                Error::AtLocation { cause, .. } => *cause,
+                other => other,
+            },
+
+            other => other,
+        };
+
+        let value = match err {
+            Error::Thrown { .. } => {
+                self.thrown.take().ok_or(Error::InternalCompilerErr)?
+            },
+
+            other => self.error_value(&other)?,
+        };
+
+        loop {
+            if let Some(handler) = self.frame.handlers.pop() {
+                self.frame.locals.truncate(handler.depth);
+                self.frame.mark = handler.mark;
+                self.frame.pc = handler.pc;
+                self.push(value);
+                return Ok(());
+            }
+
+            self.frame = self.saved.pop().ok_or(Error::StackUnderflow)?;
+        }
+    }
+
+    /// Describe the active calls, innermost first.
+    pub fn backtrace(&self) -> Trace {
+        let frames = self.frames().into_iter().map(|frame| TraceFrame {
+            func: frame.name.clone(),
+            pc: frame.pc.saturating_sub(1),
+            location: frame.location(),
+        });
+
+        Trace(frames.collect())
+    }
+
+    /// Whether a handler between here and the floor can catch errors.
+    fn is_guarded(&self) -> bool {
+        let floor = self.floor.min(self.saved.len());
+
+        Some(&self.frame).into_iter()
+            .chain(self.saved[floor ..].iter())
+            .any(|frame| !frame.handlers.is_empty())
+    }
+
+    /// Describe a runtime error as `{ error: :Kind, message: "..." }`
+    fn error_value(&mut self, err: &Error) -> Result<Value> {
+        let kind: Ident = self.strings.intern(err.kind())?;
+        let message = Str::from(err.to_string());
+
+        let record = Record::default();
+        {
+            let mut record = record.borrow_mut();
+            record.insert(self.strings.intern("error")?, kind.into());
+            record.insert(self.strings.intern("message")?, message.into());
+        }
+
+        Ok(record.into())
+    }
+
+    fn dispatch(&mut self) -> Result<()> {
         let op = self.frame.code.fetch(self.frame.pc)?;
 
         self.frame.pc += 1;
@@ -121,28 +982,45 @@ impl Interpreter {
                 self.push(globals);
             },
 
+            Op::PUSHB { boolean } => {
+                self.push(boolean);
+            },
+
             Op::PUSHI { int } => {
                 self.push(int);
             },
 
+            Op::PUSHF { float } => {
+                self.push(float);
+            },
+
             Op::PUSHS { string } => {
+                let string = self.main.constants.string(string)?;
                 self.push(string);
             },
 
             Op::PUSHN { name } => {
+                let name = self.main.constants.name(name)?;
                 self.push(name);
             },
 
             Op::PAT { pat } => {
+                let pat = self.main.constants.pattern(pat)?;
                 let pat = self.compile_pattern(pat)?;
                 self.push(pat);
             },
 
             Op::NOT => {
-                let test = self.pop::<bool>()?;
+                let test = self.pop::<Bool>()?;
                 self.push(!test);
             },
 
+            Op::TYPEOF => {
+                let value = self.pop::<Value>()?;
+                let name: Ident = self.strings.intern(value.type_name())?;
+                self.push(name);
+            },
+
             Op::BINOP { op } => {
                 let rhs = self.pop::<Value>()?;
                 let lhs = self.pop::<Value>()?;
@@ -151,31 +1029,104 @@ impl Interpreter {
                     Binop::ADD => lhs + rhs,
                     Binop::SUB => lhs - rhs,
                     Binop::DIV => lhs / rhs,
-                    Binop::MUL => lhs * rhs,
+                    Binop::MUL => {
+                        // Repeating a string can ask for a lot at once, so
+                        // check before building it
+                        if let (Value::Str(s), &Value::Int(n)) = (&lhs, &rhs) {
+                            self.reserve(s.len().saturating_mul(n.max(0) as usize))?;
+                        }
+
+                        lhs * rhs
+                    },
+                    Binop::MOD => lhs % rhs,
                     Binop::IDX => lhs.index(rhs),
 
                     Binop::EQ => Ok((lhs == rhs).into()),
                     Binop::NE => Ok((lhs != rhs).into()),
 
+                    Binop::LT => lhs.compare(rhs).map(|order| {
+                        order.is_some_and(Ordering::is_lt).into()
+                    }),
+
+                    Binop::GT => lhs.compare(rhs).map(|order| {
+                        order.is_some_and(Ordering::is_gt).into()
+                    }),
+
+                    Binop::LE => lhs.compare(rhs).map(|order| {
+                        order.is_some_and(Ordering::is_le).into()
+                    }),
+
+                    Binop::GE => lhs.compare(rhs).map(|order| {
+                        order.is_some_and(Ordering::is_ge).into()
+                    }),
+
+                    Binop::SLT => lhs.compare_str(&rhs).map(|order| {
+                        order.is_lt().into()
+                    }),
+
+                    Binop::SGT => lhs.compare_str(&rhs).map(|order| {
+                        order.is_gt().into()
+                    }),
+
+                    Binop::SLE => lhs.compare_str(&rhs).map(|order| {
+                        order.is_le().into()
+                    }),
+
+                    Binop::SGE => lhs.compare_str(&rhs).map(|order| {
+                        order.is_ge().into()
+                    }),
+
+                    Binop::CMP => lhs.compare_str(&rhs).map(|order| {
+                        Value::Int(order as Int)
+                    }),
+
                     Binop::MATCH => {
                         self.match_pattern(rhs, lhs)
                     },
                 }?;
 
-                self.push(result);
+                match op {
+                    // Joins strings or lists into a new one
+                    Binop::ADD => self.push_new(result)?,
+                    _ => self.push(result),
+                }
             },
 
             Op::INS => {
                 let lhs = self.pop::<Value>()?;
                 let idx = self.pop::<Value>()?;
                 let rhs = self.pop::<Value>()?;
+                self.collector.suspect(&lhs, &rhs);
                 lhs.insert(idx, rhs)?;
+                self.allocated(::std::mem::size_of::<Value>())?;
             },
 
             Op::LIST { len } => {
                 use std::collections::VecDeque;
                 let list: VecDeque<_> = self.capture(len)?;
-                self.push(List::new(list.into()));
+                self.push_new(List::new(list.into()))?;
+            },
+
+            Op::REST { index } => {
+                let len = self.frame.locals.len();
+                let rest: VecDeque<Value> = self.capture(len - index.min(len))?;
+                self.push_new(List::new(rest.into()))?;
+            },
+
+            Op::UNPACK { len } => {
+                let list = self.pop::<List>()?;
+                let items = list.borrow();
+
+                if items.len() != len {
+                    return Err(Error::Unpack {
+                        expected: len,
+                        found: items.len(),
+                    });
+                }
+
+                for item in items.iter() {
+                    self.push(item.clone());
+                }
             },
 
             Op::STR { len } => {
@@ -186,29 +1137,90 @@ impl Interpreter {
                     buf.push_str(&item.to_string());
                 }
 
-                self.push(Str::from(buf));
+                self.push_new(Str::from(buf))?;
             },
 
-            Op::REC => {
-                self.push(Record::default());
+            Op::REC { len } => {
+                let items: Vec<Value> = self.capture(len * 2)?;
+
+                let mut rec = HashMap::with_capacity(len);
+                let mut items = items.into_iter();
+                while let (Some(key), Some(val)) = (items.next(), items.next()) {
+                    rec.insert(key.record_key()?, val);
+                }
+
+                self.push_new(Record::new(rec.into()))?;
             },
 
             Op::JUMP { dst } => {
                 self.frame.pc = dst;
             },
 
+            Op::JARG { index, dst } => {
+                if self.frame.locals.len() > index {
+                    self.frame.pc = dst;
+                }
+            },
+
             Op::JNZ { dst } => {
-                if self.pop::<bool>()? {
+                if self.pop::<Bool>()? {
                     self.frame.pc = dst;
                 }
             },
 
+            Op::SLICE => {
+                let hi = self.pop::<Int>()?;
+                let lo = self.pop::<Int>()?;
+                let slice = self.pop::<Value>()?.slice(lo, hi)?;
+                self.push_new(slice)?;
+            },
+
+            Op::ITER => {
+                let items = self.pop::<Value>()?.iterate()?;
+                self.push(List::new(items.into()));
+            },
+
+            Op::NEXT { iter, dst } => {
+                let iter = self.read::<List>(iter)?;
+                let next = iter.borrow_mut().pop_front();
+
+                match next {
+                    Some(item) => self.push(item),
+                    None => self.frame.pc = dst,
+                }
+            },
+
+            Op::TRY { dst } => {
+                self.frame.handlers.push(Handler {
+                    pc: dst,
+                    mark: self.frame.mark,
+                    depth: self.frame.locals.len(),
+                });
+            },
+
+            Op::UNTRY => {
+                self.frame.handlers.pop().ok_or(Error::StackUnderflow)?;
+            },
+
+            Op::THROW => {
+                let value = self.pop::<Value>()?;
+                self.thrown = Some(value.clone());
+                return Err(Error::Thrown { value: value.to_string() });
+            },
+
             Op::ASSERT { expr } => {
-                if !(self.pop::<bool>()?) {
+                if !self.pop::<Bool>()? {
+                    let expr = self.main.constants.string(expr)?.to_string();
                     return Err(Error::Assert { expr });
                 }
             },
 
+            Op::FAIL { expr } => {
+                let message = self.pop::<Value>()?.to_string();
+                let expr = self.main.constants.string(expr)?.to_string();
+                return Err(Error::AssertMessage { expr, message });
+            },
+
             Op::MARK { len } => {
                 if len > self.frame.locals.len() {
                     return Err(Error::MarkTooHigh);
@@ -219,26 +1231,69 @@ impl Interpreter {
             },
 
             Op::CALL { name, argc } => {
-                let mut argv = self.capture(argc)?;
+                let name = self.main.constants.name(name)?;
+                let argv = self.capture(argc)?;
                 self.fncall(&name, argv)?;
             },
+
+            Op::METHOD { name, argc } => {
+                let name = self.main.constants.name(name)?;
+                let argv = self.capture(argc)?;
+                let recv = self.pop::<Value>()?;
+
+                let host = match recv {
+                    Value::Opaque(ref obj) => obj.methods(),
+                    _ => None,
+                };
+
+                let host = host.ok_or_else(|| Error::NoSuchMethod {
+                    method: name.clone(),
+                    found: recv.type_name(),
+                })?;
+
+                self.push_new(host.call_method(&name, argv)?)?;
+            },
         }
 
         Ok(())
     }
 
     fn fncall(&mut self, name: &Ident, argv: Vec<Value>) -> Result<()> {
-        match self.main.call(name.clone(), &argv)? {
+        let func = self.main.call(name.clone(), &argv)?;
+
+        if self.profiling {
+            self.profile.entry(Some(name.clone())).or_default().calls += 1;
+        }
+
+        let start = Instant::now();
+
+        match func {
             Func::Native(call) => {
                 // Immediately call it and save the return value
-                self.push(call(argv)?);
+                self.push_new(call(argv)?)?;
+                self.native_time(name, start);
+            },
+
+            Func::Context(call) => {
+                let rv = call(self, argv)?;
+                self.push_new(rv)?;
+                self.native_time(name, start);
             },
 
             Func::Interpreted(code) => {
                 use std::mem::swap;
 
+                if self.saved.len() >= self.max_depth {
+                    return Err(Error::StackOverflow {
+                        func: name.clone(),
+                        depth: self.max_depth,
+                    });
+                }
+
                 self.saved.push(Frame {
+                    name: Some(name.clone()),
                     groups: BTreeMap::new(),
+                    handlers: vec![],
                     mark: argv.len(),
                     locals: argv,
                     pc: 0,
@@ -254,6 +1309,12 @@ impl Interpreter {
         Ok(())
     }
 
+    fn native_time(&mut self, name: &Ident, start: Instant) {
+        if self.profiling {
+            self.profile.entry(Some(name.clone())).or_default().time += start.elapsed();
+        }
+    }
+
     pub fn pop<V: Extract>(&mut self) -> Result<V> {
         let val = self.frame.locals.pop()
             .ok_or(Error::StackUnderflow)?;
@@ -269,6 +1330,40 @@ impl Interpreter {
         self.frame.locals.push(item.into());
     }
 
+    /// Push a value that was just made, counting it against the memory
+    /// limit.
+    fn push_new<V: Into<Value>>(&mut self, item: V) -> Result<()> {
+        let item = item.into();
+        let size = item.heap_size();
+        self.push(item);
+        self.allocated(size)
+    }
+
+    fn allocated(&mut self, bytes: usize) -> Result<()> {
+        self.memory_used += bytes;
+        self.reserve(0)
+    }
+
+    /// Fail with `MemoryLimit` unless there is room for `bytes` more.
+    pub(crate) fn reserve(&mut self, bytes: usize) -> Result<()> {
+        let limit = match self.memory_limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        // Some of what was counted may be gone by now, so look again
+        // before giving up
+        if self.memory_used.saturating_add(bytes) > limit {
+            self.memory_used = self.memory_usage();
+
+            if self.memory_used.saturating_add(bytes) > limit {
+                return Err(Error::MemoryLimit { limit });
+            }
+        }
+
+        Ok(())
+    }
+
     fn read<V: Extract>(&self, index: usize) -> Result<V> {
         if index >= self.frame.mark {
             return Err(Error::LocalVarOutOfBounds { index });
@@ -281,7 +1376,8 @@ impl Interpreter {
         if index >= self.frame.mark {
             Err(Error::LocalVarOutOfBounds { index })
         } else {
-            Ok({ self.frame.locals[index] = item.into() })
+            self.frame.locals[index] = item.into();
+            Ok(())
         }
     }
 
@@ -293,8 +1389,6 @@ impl Interpreter {
     }
 
     fn compile_pattern(&mut self, pat: pattern::Expr) -> Result<Pattern> {
-        use std::collections::HashMap;
-
         use pattern::Var;
 
         let mut locals = HashMap::<usize, String>::new();
@@ -302,21 +1396,22 @@ impl Interpreter {
 
         pat.map(|var| Ok(match *var {
             Var::Local { name } => {
-                if !locals.contains_key(&name) {
+                if let Some(value) = locals.get(&name) {
+                    value.clone()
+                } else {
                     let value = self.read::<Value>(name)?.to_string();
-                    locals.insert(name, value.into());
+                    locals.insert(name, value.clone());
+                    value
                 }
-
-                locals.get(&name).cloned().unwrap()
             },
 
             Var::Global { ref name } => {
                 if !globals.contains_key(name) {
                     let dict = self.globals.clone();
-                    let value = dict.borrow().get(name).cloned();
+                    let value = dict.borrow().get(name.as_ref()).cloned();
                     if let Some(value) = value {
                         let value = value.to_string();
-                        globals.insert(name.clone(), value.into());
+                        globals.insert(name.clone(), value);
                     }
                 }
 
@@ -326,7 +1421,8 @@ impl Interpreter {
         })).map(|pat| pat.translate().into())
     }
 
-    fn match_pattern(&mut self, pat: Value, text: Value) -> Result<Value> {
+    /// Match like `=~`, setting the capture groups of the current frame.
+    pub fn match_pattern(&mut self, pat: Value, text: Value) -> Result<Value> {
         let pat = Pattern::extract(pat)?;
         let text = Str::extract(text)?;
 
@@ -358,6 +1454,47 @@ impl fmt::Display for Argc {
         match *self {
             Argc::AtLeast(n) => write!(f, "at least {}", n),
             Argc::Exactly(n) => write!(f, "exactly {}", n),
+            Argc::Between(lo, hi) => write!(f, "{} to {}", lo, hi),
+        }
+    }
+}
+
+mod display {
+    use std::fmt::{Display, Formatter, Result};
+
+    use super::*;
+
+    impl Display for Trace {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            for (i, frame) in self.0.iter().enumerate() {
+                if i > 0 {
+                    writeln!(f)?;
+                }
+
+                write!(f, "  {}", frame)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl Display for TraceFrame {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            match self.func {
+                Some(ref func) => write!(f, "in {} ", func)?,
+                None => write!(f, "in (top level) ")?,
+            }
+
+            match self.location {
+                Some(ref location) => write!(f, "at {}", location),
+                None => write!(f, "at pc {}", self.pc),
+            }
+        }
+    }
+
+    impl Display for Location {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            write!(f, "{}:{}:{}", self.file, self.line, self.column)
         }
     }
 }
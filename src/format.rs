@@ -0,0 +1,164 @@
+use super::*;
+
+use value::*;
+
+/// Perl-style `sprintf`. Supports the `-`, `+`, space and `0` flags, a
+/// field width, a precision, and the conversions `%s %d %i %f %e %x %X %o
+/// %b %c %%`.
+pub fn sprintf(template: &str, args: &[Value]) -> Result<String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = template.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            out.push(ch);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+
+            chars.next();
+        }
+
+        spec.width = digits(&mut chars);
+
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(digits(&mut chars).unwrap_or(0));
+        }
+
+        let conv = chars.next().ok_or(Error::InvalidFormat)?;
+
+        if conv == '%' {
+            out.push('%');
+            continue;
+        }
+
+        let arg = args.next().ok_or(Error::InvalidFormat)?.clone();
+        spec.write(&mut out, conv, arg)?;
+    }
+
+    Ok(out)
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+fn digits<I>(chars: &mut ::std::iter::Peekable<I>) -> Option<usize>
+    where I: Iterator<Item=char>
+{
+    let mut n = None;
+
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        n = Some(n.unwrap_or(0) * 10 + d as usize);
+        chars.next();
+    }
+
+    n
+}
+
+impl Spec {
+    fn write(&self, out: &mut String, conv: char, arg: Value) -> Result<()> {
+        let (sign, body) = match conv {
+            's' => {
+                let mut text = format!("{}", arg);
+                if let Some(p) = self.precision {
+                    text = text.chars().take(p).collect();
+                }
+                ("", text)
+            },
+
+            'd' | 'i' => {
+                let n = integer(arg)?;
+                (self.sign(n < 0), n.unsigned_abs().to_string())
+            },
+
+            'f' | 'e' => {
+                let x = float(arg)?;
+                let p = self.precision.unwrap_or(6);
+                let body = if conv == 'f' {
+                    format!("{:.*}", p, x.abs())
+                } else {
+                    format!("{:.*e}", p, x.abs())
+                };
+                (self.sign(x.is_sign_negative()), body)
+            },
+
+            'x' => ("", format!("{:x}", integer(arg)?)),
+            'X' => ("", format!("{:X}", integer(arg)?)),
+            'o' => ("", format!("{:o}", integer(arg)?)),
+            'b' => ("", format!("{:b}", integer(arg)?)),
+
+            'c' => {
+                let code = integer(arg)?;
+                let ch = ::std::char::from_u32(code as u32)
+                    .ok_or(Error::InvalidFormat)?;
+                ("", ch.to_string())
+            },
+
+            _ => return Err(Error::InvalidFormat),
+        };
+
+        let len = sign.len() + body.chars().count();
+        let pad = self.width.unwrap_or(0).saturating_sub(len);
+
+        if self.left {
+            out.push_str(sign);
+            out.push_str(&body);
+            out.extend(::std::iter::repeat_n(' ', pad));
+        } else if self.zero && conv != 's' && conv != 'c' {
+            out.push_str(sign);
+            out.extend(::std::iter::repeat_n('0', pad));
+            out.push_str(&body);
+        } else {
+            out.extend(::std::iter::repeat_n(' ', pad));
+            out.push_str(sign);
+            out.push_str(&body);
+        }
+
+        Ok(())
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+fn integer(arg: Value) -> Result<Int> {
+    match arg {
+        Value::Float(x) => Ok(x as Int),
+        other => Int::extract(other),
+    }
+}
+
+fn float(arg: Value) -> Result<Float> {
+    match arg {
+        Value::Int(i) => Ok(i as Float),
+        other => Float::extract(other),
+    }
+}
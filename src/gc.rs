@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use value::*;
+
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static FREED: AtomicU64 = AtomicU64::new(0);
+
+/// Suspects are checked for ones that have been freed once there are this
+/// many, or twice as many as survived the last check.
+const PRUNE_AT: usize = 1024;
+
+pub(crate) fn note_alloc() {
+    ALLOCATED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn note_free() {
+    FREED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts of Lists and Records. `allocated` and `freed` cover the whole
+/// process; `collections` and `collected` cover one interpreter.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stats {
+    pub allocated: u64,
+    pub freed: u64,
+    /// How many times the cycle collector has run
+    pub collections: u64,
+    /// Containers it found unreachable and emptied
+    pub collected: u64,
+}
+
+impl Stats {
+    /// Lists and Records that have not been freed yet.
+    pub fn live(&self) -> u64 {
+        self.allocated.saturating_sub(self.freed)
+    }
+}
+
+enum Container {
+    List(Weak<Shared<VecDeque<Value>>>),
+    Record(Weak<Shared<HashMap<Str, Value>>>),
+}
+
+/// Finds Lists and Records that only keep each other alive.
+///
+/// Reference counting frees everything except cycles, and a cycle can
+/// only be made by storing a container into one that already exists. So
+/// the interpreter hands every container it stores into to `suspect`, and
+/// `collect` looks for cycles starting from those.
+#[derive(Default)]
+pub(crate) struct Collector {
+    // Keyed by address; the weak reference keeps the address from being
+    // reused while it is here
+    suspects: HashMap<usize, Container>,
+    // How many suspects were still alive after the last prune
+    kept: usize,
+    collections: u64,
+    collected: u64,
+}
+
+impl Collector {
+    /// Note that `value` was stored into `target`.
+    pub fn suspect(&mut self, target: &Value, value: &Value) {
+        match *value {
+            Value::List(_) | Value::Record(_) => (),
+            _ => return,
+        }
+
+        match *target {
+            Value::List(ref list) => {
+                self.suspects.entry(address(target))
+                    .or_insert_with(|| Container::List(Arc::downgrade(list)));
+            },
+
+            Value::Record(ref rec) => {
+                self.suspects.entry(address(target))
+                    .or_insert_with(|| Container::Record(Arc::downgrade(rec)));
+            },
+
+            _ => (),
+        }
+
+        // Scripts that never collect would otherwise keep an entry for
+        // every container they ever stored into
+        if self.suspects.len() >= PRUNE_AT.max(self.kept * 2) {
+            self.suspects.retain(|_, container| container.upgrade().is_some());
+            self.kept = self.suspects.len();
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            allocated: ALLOCATED.load(Ordering::Relaxed),
+            freed: FREED.load(Ordering::Relaxed),
+            collections: self.collections,
+            collected: self.collected,
+        }
+    }
+
+    /// Empty every container reachable from a suspect that nothing outside
+    /// the containers refers to, and return how many there were.
+    ///
+    /// Reference counts are read without stopping other threads, so a
+    /// container shared with a script running elsewhere must not be
+    /// changed there while this runs.
+    pub fn collect(&mut self) -> usize {
+        self.collections += 1;
+
+        // Everything reachable from the suspects, holding one reference each
+        let mut nodes: HashMap<usize, Value> = HashMap::new();
+        let mut todo: Vec<Value> = self.suspects.values()
+            .filter_map(Container::upgrade)
+            .collect();
+
+        while let Some(value) = todo.pop() {
+            let addr = address(&value);
+            if nodes.contains_key(&addr) {
+                continue;
+            }
+
+            todo.extend(children(&value));
+            nodes.insert(addr, value);
+        }
+
+        // References from outside the graph, less our own
+        let mut outside: HashMap<usize, usize> = nodes.iter()
+            .map(|(&addr, value)| (addr, strong_count(value) - 1))
+            .collect();
+
+        for value in nodes.values() {
+            for child in children(value) {
+                if let Some(count) = outside.get_mut(&address(&child)) {
+                    *count -= 1;
+                }
+            }
+        }
+
+        // Whatever the outside can reach is alive
+        let mut alive = HashSet::new();
+        let mut todo: Vec<Value> = nodes.iter()
+            .filter(|&(addr, _)| outside[addr] > 0)
+            .map(|(_, value)| value.clone())
+            .collect();
+
+        while let Some(value) = todo.pop() {
+            if alive.insert(address(&value)) {
+                todo.extend(children(&value));
+            }
+        }
+
+        // Emptying the rest breaks their cycles. The contents are dropped
+        // after every lock is released.
+        let mut garbage = vec![];
+        for (addr, value) in nodes.iter() {
+            if alive.contains(addr) {
+                continue;
+            }
+
+            match *value {
+                Value::List(ref list) => {
+                    let items = mem::take(&mut *list.borrow_mut());
+                    garbage.extend(items);
+                },
+
+                Value::Record(ref rec) => {
+                    let entries = mem::take(&mut *rec.borrow_mut());
+                    garbage.extend(entries.into_values());
+                },
+
+                _ => (),
+            }
+        }
+
+        let count = nodes.len() - alive.len();
+        self.collected += count as u64;
+
+        drop(garbage);
+        drop(nodes);
+
+        // Suspects that are still alive may be cut loose later
+        self.suspects.retain(|addr, container| {
+            alive.contains(addr) && container.upgrade().is_some()
+        });
+        self.kept = self.suspects.len();
+
+        count
+    }
+}
+
+impl Container {
+    fn upgrade(&self) -> Option<Value> {
+        match *self {
+            Container::List(ref list) => list.upgrade().map(Value::List),
+            Container::Record(ref rec) => rec.upgrade().map(Value::Record),
+        }
+    }
+}
+
+fn address(value: &Value) -> usize {
+    match *value {
+        Value::List(ref list) => Arc::as_ptr(list) as usize,
+        Value::Record(ref rec) => Arc::as_ptr(rec) as usize,
+        _ => 0,
+    }
+}
+
+fn strong_count(value: &Value) -> usize {
+    match *value {
+        Value::List(ref list) => Arc::strong_count(list),
+        Value::Record(ref rec) => Arc::strong_count(rec),
+        _ => 0,
+    }
+}
+
+/// The Lists and Records directly inside `value`.
+fn children(value: &Value) -> Vec<Value> {
+    let is_container = |item: &&Value| {
+        matches!(**item, Value::List(_) | Value::Record(_))
+    };
+
+    match *value {
+        Value::List(ref list) => list.borrow().iter()
+            .filter(is_container)
+            .cloned()
+            .collect(),
+
+        Value::Record(ref rec) => rec.borrow().values()
+            .filter(is_container)
+            .cloned()
+            .collect(),
+
+        _ => vec![],
+    }
+}
+
+#[test]
+fn collect_cycles() {
+    let mut gc = Collector::default();
+
+    // A list holding itself, and a pair of records holding each other
+    let list = Value::from_slice(&[]);
+    list.clone().insert(Value::Int(0), list.clone()).unwrap();
+    gc.suspect(&list, &list);
+
+    let a: Value = Record::default().into();
+    let b: Value = Record::default().into();
+    let key = Value::from(Str::from("next"));
+    a.clone().insert(key.clone(), b.clone()).unwrap();
+    b.clone().insert(key.clone(), a.clone()).unwrap();
+    gc.suspect(&a, &b);
+    gc.suspect(&b, &a);
+
+    let weak = match list {
+        Value::List(ref list) => Arc::downgrade(list),
+        _ => unreachable!(),
+    };
+
+    // Still in use
+    assert_eq!(gc.collect(), 0);
+    assert!(weak.upgrade().is_some());
+
+    drop(list);
+    assert_eq!(gc.collect(), 1);
+    assert!(weak.upgrade().is_none());
+
+    drop(a);
+    assert_eq!(gc.collect(), 0);
+
+    drop(b);
+    assert_eq!(gc.collect(), 2);
+
+    let stats = gc.stats();
+    assert_eq!((stats.collections, stats.collected), (4, 3));
+}
+
+#[test]
+fn prune_freed_suspects() {
+    let mut gc = Collector::default();
+    let key = Value::from(Str::from("x"));
+
+    for _ in 0 .. 10 * PRUNE_AT {
+        let rec: Value = Record::default().into();
+        let list = Value::from_slice(&[Value::Int(1)]);
+        rec.clone().insert(key.clone(), list.clone()).unwrap();
+        gc.suspect(&rec, &list);
+    }
+
+    assert!(gc.suspects.len() <= PRUNE_AT);
+}
@@ -1,35 +1,46 @@
 use std::collections::HashSet;
 use std::borrow::Borrow;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
 
 use super::*;
 use value::Str;
 
-#[derive(Clone, Debug)]
-pub struct Strings(HashSet<Str>);
+/// A set of interned strings. Clones share the same set, so the tokenizer
+/// reading a script, the module compiled from it, and the interpreter
+/// running it can all hand out the same allocation for the same name.
+#[derive(Clone, Debug, Default)]
+pub struct Strings(Arc<Mutex<HashSet<Str>>>);
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+/// A name interned in a `Strings`. Names from the same set compare by
+/// pointer; names from different sets still compare by content.
+#[derive(Clone, Debug, Ord, PartialOrd)]
 pub struct Ident(Str);
 
 pub trait Interned: Sized {
-    fn from_arc(&Str) -> Result<Self>;
+    fn from_arc(arc: &Str) -> Result<Self>;
 }
 
 impl Strings {
     pub fn new() -> Self {
-        Strings(HashSet::new())
+        Strings::default()
     }
 
-    pub fn intern<I, O>(&mut self, input: I) -> Result<O>
+    pub fn intern<I, O>(&self, input: I) -> Result<O>
         where I: AsRef<str>, O: Interned
     {
         let input = input.as_ref();
 
-        if !self.0.contains(input) {
-            self.0.insert(input.into());
+        // Nothing can be left half-inserted, so a poisoned set is fine
+        let mut set = self.0.lock().unwrap_or_else(|err| err.into_inner());
+
+        if !set.contains(input) {
+            set.insert(input.into());
         }
 
-        O::from_arc(self.0.get(input).expect("We just inserted this"))
+        O::from_arc(set.get(input).expect("We just inserted this"))
     }
+
 }
 
 impl Interned for Str {
@@ -45,24 +56,54 @@ impl Interned for Ident {
             Err(Error::InvalidIdent { input })
         };
 
-        let mut chars = arc.chars();
-        match chars.next() {
-            Some(ch) if ch.is_alphabetic() => (),
-            _ => die()?,
-        };
+        // Each segment of a qualified name like pkg::name is checked alone
+        for segment in arc.split("::") {
+            let mut chars = segment.chars();
+            match chars.next() {
+                Some(ch) if ch.is_alphabetic() => (),
+                _ => die()?,
+            };
 
-        for ch in chars {
-            if ch.is_alphabetic() || ch.is_digit(10) || ch == '_' {
-                continue;
-            }
+            for ch in chars {
+                if ch.is_alphabetic() || ch.is_ascii_digit() || ch == '_' {
+                    continue;
+                }
 
-            die()?;
+                die()?;
+            }
         }
 
         Ok(Ident(arc.clone()))
     }
 }
 
+impl PartialEq for Ident {
+    fn eq(&self, other: &Ident) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Ident {}
+
+impl Hash for Ident {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl Ident {
+    /// Whether this name already names a package, as in `pkg::name`.
+    pub fn is_qualified(&self) -> bool {
+        self.0.contains("::")
+    }
+}
+
+impl From<Ident> for Str {
+    fn from(Ident(s): Ident) -> Self {
+        s
+    }
+}
+
 impl From<Ident> for String {
     fn from(Ident(s): Ident) -> Self {
         s.as_ref().to_owned()
@@ -88,3 +129,17 @@ impl fmt::Display for Ident {
         write!(f, "{}", &self.0)
     }
 }
+
+#[test]
+fn shared_interning() {
+    let module = compile_str("test.cy", "sub twice($x) { return $x * 2; }").unwrap();
+
+    let name: Ident = module.strings.intern("twice").unwrap();
+    let (key, _) = module.functions.get_key_value(&name).unwrap();
+    assert!(Arc::ptr_eq(&key.0, &name.0));
+
+    // A separate set still finds the same names
+    let other: Ident = Strings::new().intern("twice").unwrap();
+    assert!(!Arc::ptr_eq(&other.0, &name.0));
+    assert!(module.functions.contains_key(&other));
+}
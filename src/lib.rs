@@ -1,3 +1,6 @@
+// failure_derive expands to impls at crate scope, which this lint predates
+#![allow(non_local_definitions)]
+
 extern crate failure;
 
 #[macro_use]
@@ -7,30 +10,283 @@ extern crate lalrpop_util;
 
 extern crate backpat;
 
+#[cfg(feature = "bigint")]
+extern crate num_bigint;
+#[cfg(feature = "bigint")]
+extern crate num_traits;
+
 pub mod ident;
 pub mod pattern;
+pub mod format;
+pub mod diagnostic;
 pub mod token;
 pub mod ast;
 pub mod value;
+pub mod gc;
 pub mod opcode;
 pub mod build;
+pub mod optimize;
 pub mod eval;
+pub mod debug;
+pub mod logger;
+pub mod disasm;
+pub mod serialize;
 
+use std::collections::HashSet;
+use std::io::Read;
 use std::path::Path;
 
 //use value::*;
 use ident::*;
 use token::Token;
 
+fn read_source(path: &Path) -> Result<opcode::Source> {
+    let file = std::fs::File::open(path)?;
+    read_source_from(&path.to_string_lossy(), file)
+}
+
+fn read_source_from<R: Read>(name: &str, mut reader: R) -> Result<opcode::Source> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    Ok(opcode::Source::new(name, &text))
+}
+
+/// Parse a file without translating it.
+pub fn parse<P: AsRef<Path>>(path: P) -> Result<ast::Module> {
+    parse_with(path, &Options::default())
+}
+
+pub fn parse_with<P: AsRef<Path>>(path: P, options: &Options)
+    -> Result<ast::Module>
+{
+    let source = read_source(path.as_ref())?;
+
+    let tokens = token::Tokenizer::new(&source.text)
+        .newlines(options.newlines)
+        .spanned();
+    ast::parse_module(tokens).map_err(|err| Error::from(err).diagnose(&source))
+}
+
+/// How to compile a script
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Run the peephole pass over each sub. On by default.
+    pub optimize: bool,
+    /// Let a newline end a statement, so semicolons can be left off. Off
+    /// by default.
+    pub newlines: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { optimize: true, newlines: false }
+    }
+}
+
 pub fn compile<P: AsRef<Path>>(path: P) -> Result<opcode::Module> {
-    use std::fs::File;
-    use std::io::Read;
+    compile_with(path, &Options::default())
+}
+
+pub fn compile_with<P: AsRef<Path>>(path: P, options: &Options)
+    -> Result<opcode::Module>
+{
+    compile_source(read_source(path.as_ref())?, options)
+}
+
+/// Compile source held in memory. `name` stands in for the file name in
+/// error messages.
+pub fn compile_str(name: &str, source: &str) -> Result<opcode::Module> {
+    compile_str_with(name, source, &Options::default())
+}
+
+pub fn compile_str_with(name: &str, source: &str, options: &Options)
+    -> Result<opcode::Module>
+{
+    compile_source(opcode::Source::new(name, source), options)
+}
+
+/// Compile source read to the end from `reader`, such as an archive entry
+/// or a network stream. `name` is used in error messages.
+pub fn compile_reader<R: Read>(name: &str, reader: R) -> Result<opcode::Module> {
+    compile_reader_with(name, reader, &Options::default())
+}
+
+pub fn compile_reader_with<R: Read>(name: &str, reader: R, options: &Options)
+    -> Result<opcode::Module>
+{
+    compile_source(read_source_from(name, reader)?, options)
+}
 
-    let mut source = String::new();
-    File::open(path.as_ref())?.read_to_string(&mut source)?;
+fn compile_source(source: opcode::Source, options: &Options)
+    -> Result<opcode::Module>
+{
+    let strings = ident::Strings::new();
 
-    let tokens = token::Tokenizer::new(&source).spanned();
-    ast::parse_module(tokens)?.translate()
+    let mut module = opcode::Module::stdlib_in(&strings)?;
+    module.optimize = options.optimize;
+
+    translate_file(module, source, None, options, &mut HashSet::new())
+}
+
+/// Parse `source` and translate it into `module`, after the packages it
+/// imports. An imported file is translated with `package` set, and its
+/// top-level code becomes a sub that the first file to import it calls
+/// before its own.
+fn translate_file(
+    module: opcode::Module,
+    source: opcode::Source,
+    package: Option<&Ident>,
+    options: &Options,
+    imported: &mut HashSet<Ident>,
+) -> Result<opcode::Module> {
+    let strings = module.strings.clone();
+
+    let tokens = token::Tokenizer::with_strings(&source.text, &strings)
+        .newlines(options.newlines)
+        .spanned();
+    let mut ast = ast::parse_module(tokens)
+        .map_err(|err| Error::from(err).diagnose(&source))?;
+
+    if let Some(name) = package {
+        match ast.package {
+            Some(ref declared) if declared != name => {
+                return Err(Error::WithContext {
+                    cause: Box::new(Error::WrongPackage { name: name.clone() }),
+                    context: format!("import {}", name),
+                });
+            },
+
+            _ => ast.package = Some(name.clone()),
+        }
+
+        ast.begin.push(ast::Stmt::Return { rhs: None });
+    }
+
+    let module = import_all(module, &mut ast, &source, options, imported)?;
+
+    let (mut module, errors) = ast.translate_into(module)
+        .map_err(|err| err.diagnose(&source))?;
+
+    if let Some(err) = errors.into_iter().next() {
+        return Err(err.diagnose(&source));
+    }
+
+    if let Some(name) = package {
+        let empty = opcode::InterpretedFn::from_vec(vec![]);
+        let begin = std::mem::replace(&mut module.begin, empty);
+        let func = opcode::Func::Interpreted(begin);
+        let entry = top_level_of(&strings, name)?;
+        module.functions.insert(entry, (opcode::Argc::Exactly(0), func));
+    }
+
+    module.attach_source(source);
+    Ok(module)
+}
+
+/// Translate the packages `ast` imports into `module`, and start its
+/// top-level code with calls to theirs. Each is loaded from `name.cy`, or
+/// `a/b.cy` for `a::b`, in the directory of `source`, and only once.
+fn import_all(
+    mut module: opcode::Module,
+    ast: &mut ast::Module,
+    source: &opcode::Source,
+    options: &Options,
+    imported: &mut HashSet<Ident>,
+) -> Result<opcode::Module> {
+    let dir = Path::new(&*source.name).parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    let mut calls = vec![];
+
+    for name in std::mem::take(&mut ast.imports) {
+        if !imported.insert(name.clone()) {
+            continue;
+        }
+
+        let context = |cause: Error| Error::WithContext {
+            cause: Box::new(cause),
+            context: format!("import {}", name),
+        };
+
+        let path = dir.join(format!("{}.cy", name.as_ref().replace("::", "/")));
+        let imported_source = read_source(&path).map_err(context)?;
+        module = translate_file(module, imported_source, Some(&name), options,
+                                imported)?;
+
+        calls.push(ast::Stmt::Bare {
+            rhs: ast::Expr::Call {
+                name: top_level_of(&module.strings, &name)?,
+                args: vec![],
+            },
+        });
+    }
+
+    ast.begin.splice(0 .. 0, calls);
+    Ok(module)
+}
+
+/// The sub holding an imported package's top-level code, `pkg::BEGIN`
+fn top_level_of(strings: &Strings, package: &Ident) -> Result<Ident> {
+    strings.intern(format!("{}::BEGIN", package))
+}
+
+/// Like `compile`, but report every syntax and translation error in the
+/// file rather than stopping at the first. The module is only returned if
+/// there were none. Errors that cannot be placed in the file are reported
+/// without a location; only failing to read it or its imports is an `Err`.
+pub fn compile_all<P: AsRef<Path>>(path: P)
+    -> Result<(Option<opcode::Module>, Vec<diagnostic::Diagnostic>)>
+{
+    compile_all_with(path, &Options::default())
+}
+
+pub fn compile_all_with<P: AsRef<Path>>(path: P, options: &Options)
+    -> Result<(Option<opcode::Module>, Vec<diagnostic::Diagnostic>)>
+{
+    let source = read_source(path.as_ref())?;
+
+    let strings = ident::Strings::new();
+
+    let tokens = token::Tokenizer::with_strings(&source.text, &strings)
+        .newlines(options.newlines)
+        .spanned();
+    let (ast, parse_errors) = ast::parse_module_recovering(tokens);
+
+    let mut errors: Vec<Error> = parse_errors.into_iter()
+        .map(Error::from)
+        .collect();
+
+    let module = match ast {
+        Some(mut ast) => {
+            let mut module = opcode::Module::stdlib_in(&strings)?;
+            module.optimize = options.optimize;
+            let module = import_all(module, &mut ast, &source, options,
+                                    &mut HashSet::new())?;
+            let (module, translate_errors) = ast.translate_into(module)?;
+            errors.extend(translate_errors);
+            Some(module)
+        },
+
+        None => None,
+    };
+
+    let mut diagnostics: Vec<_> = errors.iter()
+        .map(|err| diagnostic::Diagnostic::place(err, &source))
+        .collect();
+
+    diagnostics.sort_by_key(|d| {
+        d.location.as_ref().map(|location| (location.line, location.column))
+    });
+
+    if diagnostics.is_empty() {
+        let mut module = module.ok_or(Error::InternalCompilerErr)?;
+        module.attach_source(source);
+        Ok((Some(module), diagnostics))
+    } else {
+        Ok((None, diagnostics))
+    }
 }
 
 #[derive(Debug, Fail)]
@@ -40,10 +296,43 @@ pub enum Error {
         expr: String,
     },
 
+    #[fail(display="assert failed: {}: {}", expr, message)]
+    AssertMessage {
+        expr: String,
+        message: String,
+    },
+
+    #[fail(display="uncaught exception: {}", value)]
+    Thrown { value: String },
+
+    #[fail(display="{}", message)]
+    Die { message: String },
+
+    #[fail(display="exited with status {}", code)]
+    Exit { code: i32 },
+
+    #[fail(display="interrupted")]
+    Interrupted,
+
+    #[fail(display="ran out of fuel")]
+    FuelExhausted,
+
+    #[fail(display="memory limit of {} bytes exceeded", limit)]
+    MemoryLimit { limit: usize },
+
+    #[fail(display="out of memory")]
+    OutOfMemory,
+
+    #[fail(display="call depth exceeded {} in {}", depth, func)]
+    StackOverflow {
+        func: Ident,
+        depth: usize,
+    },
+
     #[fail(display="stack underflow")]
     StackUnderflow,
 
-    #[fail(display="{} was called with {} arguments, wanted {}", func, expected, found)]
+    #[fail(display="{} was called with {} arguments, wanted {}", func, found, expected)]
     WrongArgc {
         func: Ident,
         expected: opcode::Argc,
@@ -59,15 +348,33 @@ pub enum Error {
     #[fail(display="invalid regex")]
     InvalidRegex,
 
+    #[fail(display="invalid format string")]
+    InvalidFormat,
+
+    #[fail(display="not a compiled module, or a corrupt one")]
+    InvalidBytecode,
+
+    #[fail(display="compiled module has version {}, wanted {}", found, expected)]
+    BytecodeVersion { found: u32, expected: u32 },
+
     #[fail(display="invalid escape sequence")]
     InvalidEscape,
 
+    #[fail(display="{:#x} is not a valid character", code)]
+    InvalidCodepoint { code: u32 },
+
     #[fail(display="incorrect indentation")]
     IncorrectIndent,
 
     #[fail(display="malformed string")]
     MalformedString,
 
+    #[fail(display="block comment is never closed")]
+    UnterminatedComment,
+
+    #[fail(display="heredoc has no closing {} line", marker)]
+    UnterminatedHeredoc { marker: String },
+
     #[fail(display="unexpected end of file")]
     Eof,
 
@@ -91,9 +398,18 @@ pub enum Error {
     #[fail(display="divided by zero")]
     DividedByZero,
 
+    #[fail(display="integer overflow")]
+    IntegerOverflow,
+
     #[fail(display="negative repetition")]
     NegativeRepetition,
 
+    #[fail(display="range step of zero")]
+    ZeroStep,
+
+    #[fail(display="no log level named {}", name)]
+    NoSuchLevel { name: String },
+
     #[fail(display="negative index")]
     NegativeIndex,
 
@@ -103,9 +419,15 @@ pub enum Error {
     #[fail(display="program counter {} out of bounds", pc)]
     PcOutOfBounds { pc: usize },
 
+    #[fail(display="constant {} out of bounds", index)]
+    ConstantOutOfBounds { index: usize },
+
     #[fail(display="local var {} out of bounds", index)]
     LocalVarOutOfBounds { index: usize, },
 
+    #[fail(display="cannot unpack {} items into {} variables", found, expected)]
+    Unpack { expected: usize, found: usize },
+
     #[fail(display="list too long")]
     ListTooLong,
 
@@ -121,9 +443,21 @@ pub enum Error {
     #[fail(display="no such label")]
     NoSuchLabel,
 
+    #[fail(display="{} has no method {}", found, method)]
+    NoSuchMethod { method: Ident, found: &'static str },
+
     #[fail(display="no such global")]
     NoSuchGlobal,
 
+    #[fail(display="no type named {}", name)]
+    NoSuchType { name: Ident },
+
+    #[fail(display="imported file declares a package other than {}", name)]
+    WrongPackage { name: Ident },
+
+    #[fail(display="cannot import {} without a file to import it from", name)]
+    UnresolvedImport { name: Ident },
+
     #[fail(display="nested functions are unsupported")]
     NonStaticFunction,
 
@@ -136,6 +470,12 @@ pub enum Error {
     #[fail(display="variable renamed in same scope")]
     VariableRenamed,
 
+    #[fail(display="parameters with defaults must come last")]
+    MisplacedDefault,
+
+    #[fail(display="a rest parameter must come last")]
+    MisplacedRest,
+
     #[fail(display="variable not defined")]
     VariableUndefined,
 
@@ -152,6 +492,30 @@ pub enum Error {
         cause: Box<Error>,
     },
 
+    #[fail(display="{} at offset {}", cause, offset)]
+    AtOffset {
+        offset: usize,
+        cause: Box<Error>,
+    },
+
+    #[fail(display="{} at {}", cause, location)]
+    AtLocation {
+        location: opcode::Location,
+        cause: Box<Error>,
+    },
+
+    #[fail(display="{}\n{}", cause, trace)]
+    Traced {
+        trace: eval::Trace,
+        cause: Box<Error>,
+    },
+
+    #[fail(display="{}", diagnostic)]
+    Diagnosed {
+        diagnostic: diagnostic::Diagnostic,
+        cause: Box<Error>,
+    },
+
     #[fail(display="{}", parse)]
     Parse {
         parse: Box<lalrpop_util::ParseError<usize, Token, Error>>,
@@ -170,6 +534,150 @@ impl Error {
         let cause = Box::new(self);
         Error::WithContext { cause, context }
     }
+
+    /// Note the byte offset where this error happened, unless it already
+    /// has a more precise one.
+    pub fn at_offset(self, offset: usize) -> Self {
+        match self {
+            Error::AtOffset { .. } => self,
+
+            Error::WithContext { context, cause } => Error::WithContext {
+                context,
+                cause: Box::new(cause.at_offset(offset)),
+            },
+
+            cause => Error::AtOffset { offset, cause: Box::new(cause) },
+        }
+    }
+
+    /// Attach a source excerpt, if this error knows where it happened.
+    pub fn diagnose(self, source: &opcode::Source) -> Self {
+        match diagnostic::Diagnostic::from_error(&self, source) {
+            Some(diagnostic) => Error::Diagnosed {
+                diagnostic,
+                cause: Box::new(self),
+            },
+
+            None => self,
+        }
+    }
+
+    /// The error this one wraps with context, a position or a trace, if
+    /// it is such a wrapper.
+    pub fn cause(&self) -> Option<&Error> {
+        match *self {
+            Error::WithContext { ref cause, .. } |
+            Error::WithPosition { ref cause, .. } |
+            Error::AtOffset { ref cause, .. } |
+            Error::AtLocation { ref cause, .. } |
+            Error::Traced { ref cause, .. } |
+            Error::Diagnosed { ref cause, .. } => Some(cause),
+            _ => None,
+        }
+    }
+
+    /// The call stack recorded when this error escaped the interpreter.
+    pub fn trace(&self) -> Option<&eval::Trace> {
+        match *self {
+            Error::Traced { ref trace, .. } => Some(trace),
+            _ => self.cause().and_then(Error::trace),
+        }
+    }
+
+    /// The source position of the statement that raised this error.
+    pub fn location(&self) -> Option<&opcode::Location> {
+        match *self {
+            Error::Diagnosed { ref diagnostic, .. } => diagnostic.location.as_ref(),
+            Error::AtLocation { ref location, .. } => Some(location),
+            _ => self.cause().and_then(Error::location),
+        }
+    }
+
+    /// The underlying error, without any context, position or trace.
+    pub fn root(&self) -> &Error {
+        self.cause().map_or(self, Error::root)
+    }
+
+    /// Errors that stop the whole interpreter; scripts cannot catch them.
+    pub fn is_fatal(&self) -> bool {
+        matches!(*self.root(),
+            Error::Exit { .. } |
+            Error::FuelExhausted |
+            Error::Interrupted |
+            Error::MemoryLimit { .. })
+    }
+
+    /// The variant name, which scripts see when they catch this error.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Error::WithContext { .. } |
+            Error::WithPosition { .. } |
+            Error::AtOffset { .. } |
+            Error::AtLocation { .. } |
+            Error::Traced { .. } |
+            Error::Diagnosed { .. } => self.cause().map_or("Error", Error::kind),
+            Error::Assert { .. } |
+            Error::AssertMessage { .. } => "Assert",
+            Error::Thrown { .. } => "Thrown",
+            Error::Die { .. } => "Die",
+            Error::Exit { .. } => "Exit",
+            Error::FuelExhausted => "FuelExhausted",
+            Error::MemoryLimit { .. } => "MemoryLimit",
+            Error::OutOfMemory => "OutOfMemory",
+            Error::Interrupted => "Interrupted",
+            Error::StackOverflow { .. } => "StackOverflow",
+            Error::StackUnderflow => "StackUnderflow",
+            Error::WrongArgc { .. } => "WrongArgc",
+            Error::TypeMismatch { .. } => "TypeMismatch",
+            Error::InvalidIdent { .. } => "InvalidIdent",
+            Error::InvalidRegex => "InvalidRegex",
+            Error::InvalidFormat => "InvalidFormat",
+            Error::InvalidBytecode => "InvalidBytecode",
+            Error::BytecodeVersion { .. } => "BytecodeVersion",
+            Error::InvalidEscape => "InvalidEscape",
+            Error::InvalidCodepoint { .. } => "InvalidCodepoint",
+            Error::IncorrectIndent => "IncorrectIndent",
+            Error::MalformedString => "MalformedString",
+            Error::UnterminatedComment => "UnterminatedComment",
+            Error::UnterminatedHeredoc { .. } => "UnterminatedHeredoc",
+            Error::Eof => "Eof",
+            Error::UnimplementedToken { .. } => "UnimplementedToken",
+            Error::UnimplementedFeature { .. } => "UnimplementedFeature",
+            Error::IllegalLvalue { .. } => "IllegalLvalue",
+            Error::IllegalAdd => "IllegalAdd",
+            Error::IllegalMultiply => "IllegalMultiply",
+            Error::DividedByZero => "DividedByZero",
+            Error::ZeroStep => "ZeroStep",
+            Error::NoSuchLevel { .. } => "NoSuchLevel",
+            Error::IntegerOverflow => "IntegerOverflow",
+            Error::NegativeRepetition => "NegativeRepetition",
+            Error::NegativeIndex => "NegativeIndex",
+            Error::IndexOutOfBounds => "IndexOutOfBounds",
+            Error::PcOutOfBounds { .. } => "PcOutOfBounds",
+            Error::ConstantOutOfBounds { .. } => "ConstantOutOfBounds",
+            Error::LocalVarOutOfBounds { .. } => "LocalVarOutOfBounds",
+            Error::Unpack { .. } => "Unpack",
+            Error::ListTooLong => "ListTooLong",
+            Error::MarkTooHigh => "MarkTooHigh",
+            Error::PoppedLocalVar => "PoppedLocalVar",
+            Error::NoSuchGroup { .. } => "NoSuchGroup",
+            Error::NoSuchLabel => "NoSuchLabel",
+            Error::NoSuchMethod { .. } => "NoSuchMethod",
+            Error::NoSuchGlobal => "NoSuchGlobal",
+            Error::NoSuchType { .. } => "NoSuchType",
+            Error::WrongPackage { .. } => "WrongPackage",
+            Error::UnresolvedImport { .. } => "UnresolvedImport",
+            Error::NonStaticFunction => "NonStaticFunction",
+            Error::InternalCompilerErr => "InternalCompilerErr",
+            Error::LabelRedefined => "LabelRedefined",
+            Error::VariableRenamed => "VariableRenamed",
+            Error::MisplacedDefault => "MisplacedDefault",
+            Error::MisplacedRest => "MisplacedRest",
+            Error::VariableUndefined => "VariableUndefined",
+            Error::Parse { .. } => "Parse",
+            Error::Io { .. } => "Io",
+        }
+    }
 }
 
 impl From<std::io::Error> for Error {
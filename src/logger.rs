@@ -0,0 +1,63 @@
+use std::fmt;
+
+use opcode::Location;
+
+/// How much a logged message matters.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub const NAMES: &'static [&'static str] = &["debug", "info", "warn", "error"];
+
+    /// The level a script names with `:debug`, `:info`, `:warn` or `:error`.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        Level::NAMES[self as usize]
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Receives what scripts pass to `warn` and `log`, with where in the script
+/// they were called. Install one with `Interpreter::set_logger`; until then
+/// messages go to stderr.
+pub trait Logger {
+    fn log(&mut self, level: Level, message: &str, location: Option<Location>);
+}
+
+impl<F> Logger for F where F: FnMut(Level, &str, Option<Location>) {
+    fn log(&mut self, level: Level, message: &str, location: Option<Location>) {
+        self(level, message, location)
+    }
+}
+
+/// Writes each message to stderr as `level: message at file:line:column`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stderr;
+
+impl Logger for Stderr {
+    fn log(&mut self, level: Level, message: &str, location: Option<Location>) {
+        match location {
+            Some(at) => eprintln!("{}: {} at {}", level, message, at),
+            None => eprintln!("{}: {}", level, message),
+        }
+    }
+}
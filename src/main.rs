@@ -1,34 +1,191 @@
 extern crate canary;
 
+use std::io::IsTerminal;
 use std::path::Path;
 
-use canary::Result;
+use canary::{Error, Result};
+
+/// Flags that change how a script runs
+#[derive(Default)]
+struct Options {
+    trace: bool,
+    profile: bool,
+    compile: canary::Options,
+}
 
 fn main() {
-    if let Some(filename) = std::env::args().nth(1) {
-        load(filename.as_ref())
-    } else {
-        repl()
+    let mut args = std::env::args().skip(1).peekable();
+    let mut options = Options::default();
+
+    loop {
+        match args.peek().map(String::as_str) {
+            Some("--trace") => options.trace = true,
+            Some("--profile") => options.profile = true,
+            Some("--no-optimize") => options.compile.optimize = false,
+            Some("--newlines") => options.compile.newlines = true,
+            _ => break,
+        }
+
+        args.next();
+    }
+
+    match args.next() {
+        Some(ref flag) if flag == "--dump-bytecode" => match args.next() {
+            Some(filename) => dump_bytecode(filename.as_ref(), &options),
+            None => usage(),
+        },
+
+        Some(ref flag) if flag == "--dump-ast" => match args.next() {
+            Some(filename) => dump_ast(filename.as_ref(), &options),
+            None => usage(),
+        },
+
+        Some(ref flag) if flag == "--check" => match args.next() {
+            Some(filename) => check(filename.as_ref(), &options),
+            None => usage(),
+        },
+
+        Some(ref flag) if flag == "-e" => match args.next() {
+            Some(source) => run_inline(&source, args, &options),
+            None => usage(),
+        },
+
+        Some(filename) => load(filename.as_ref(), args, &options),
+
+        // A script piped in runs like a file, without prompts
+        None if !std::io::stdin().is_terminal() => run_stdin(&options),
+
+        None => repl(),
     }.unwrap_or_else(|err| {
-        println!("ERROR: {}", err);
+        use std::io::Write;
+
+        let code = match err {
+            Error::Exit { code } => code,
+
+            err => {
+                report(&err);
+                1
+            },
+        };
+
+        let _ = std::io::stdout().flush();
+        std::process::exit(code);
     });
 }
 
-fn load(path: &Path) -> Result<()> {
-    let _world = canary::compile(path)?.start()?;
+fn report(err: &Error) {
+    match *err {
+        Error::Diagnosed { ref diagnostic, .. } => {
+            eprintln!("ERROR: {}", diagnostic);
+        },
+
+        ref err => match err.location() {
+            Some(location) => {
+                eprintln!("ERROR: {}", err);
+                eprintln!("{}", canary::diagnostic::excerpt(location));
+            },
+
+            None => eprintln!("ERROR: {}", err),
+        },
+    }
+}
+
+fn usage() -> Result<()> {
+    eprintln!("usage: canary [OPTIONS] [--dump-ast | --dump-bytecode] [FILE [ARGS...]]");
+    eprintln!("       canary [OPTIONS] -e SOURCE [ARGS...]");
+    eprintln!("       canary [OPTIONS] --check FILE");
+    eprintln!("options: --trace, --profile, --no-optimize, --newlines");
+    Err(Error::Exit { code: 2 })
+}
+
+fn dump_bytecode(path: &Path, options: &Options) -> Result<()> {
+    let module = canary::compile_with(path, &options.compile)?;
+    print!("{}", module.disassemble());
+    Ok(())
+}
 
+fn dump_ast(path: &Path, options: &Options) -> Result<()> {
+    print!("{}", canary::parse_with(path, &options.compile)?);
     Ok(())
 }
 
+fn check(path: &Path, options: &Options) -> Result<()> {
+    let (_, diagnostics) = canary::compile_all_with(path, &options.compile)?;
+
+    for diagnostic in diagnostics.iter() {
+        eprintln!("ERROR: {}", diagnostic);
+    }
+
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Exit { code: 1 })
+    }
+}
+
+fn run_inline<I>(source: &str, args: I, options: &Options) -> Result<()>
+    where I: Iterator<Item=String>
+{
+    let module = canary::compile_str_with("-e", source, &options.compile)?;
+    execute(module, args, options)
+}
+
+fn run_stdin(options: &Options) -> Result<()> {
+    let stdin = std::io::stdin().lock();
+    let module = canary::compile_reader_with("<stdin>", stdin, &options.compile)?;
+    execute(module, std::iter::empty(), options)
+}
+
+fn load<I>(path: &Path, args: I, options: &Options) -> Result<()>
+    where I: Iterator<Item=String>
+{
+    execute(canary::compile_with(path, &options.compile)?, args, options)
+}
+
+fn execute<I>(mut module: canary::opcode::Module, args: I, options: &Options)
+    -> Result<()>
+    where I: Iterator<Item=String>
+{
+    module.enable_processes()?;
+
+    let mut world = module.prepare(args)?;
+    world.set_trace(options.trace);
+    world.set_profile(options.profile);
+
+    let result = world.run();
+
+    if options.profile {
+        eprintln!("{:<24} {:>10} {:>12} {:>12}", "function", "calls", "ops", "time (us)");
+
+        for (name, stats) in world.profile() {
+            eprintln!("{:<24} {:>10} {:>12} {:>12}",
+                name.as_ref().map_or("(top level)", |name| name.as_ref()),
+                stats.calls,
+                stats.ops,
+                stats.time.as_micros());
+        }
+    }
+
+    result
+}
+
 fn repl() -> Result<()> {
-    loop {
-        use std::io::{self, BufRead, Write};
+    use std::io::{self, BufRead, Write};
+
+    let mut module = canary::opcode::Module::stdlib()?;
+    module.enable_processes()?;
 
-        print!(">>> ");
+    let mut interp = module.start()?;
+
+    // Lines entered so far for an unfinished statement
+    let mut pending = String::new();
+
+    loop {
+        print!("{}", if pending.is_empty() { ">>> " } else { "... " });
 
         io::stdout().flush()?;
 
-        let input = {
+        let line = {
             let mut buf = String::new();
             let stdin = io::stdin();
             let mut stdin = stdin.lock();
@@ -36,24 +193,151 @@ fn repl() -> Result<()> {
             buf
         };
 
-        //let input = input.trim();
-
-        if input.is_empty() {
+        if line.is_empty() {
             return Ok(());
         }
 
-        println!("Read: {:?}", &input);
+        let blank = line.trim().is_empty();
 
-        use canary::token::Tokenizer;
-        use canary::ast::parse_block_body;
+        if blank && pending.is_empty() {
+            continue;
+        }
 
-        let tokens = Tokenizer::new(&input).collect::<Result<Vec<_>, _>>()?;
+        if pending.is_empty() && line.trim_start().starts_with(':') {
+            match command(&mut interp, line.trim()) {
+                Ok(Command::Quit) => return Ok(()),
+                Ok(Command::Continue) => (),
+                Err(err) => report(&err),
+            }
 
-        println!("Tokenized: {:?}", &tokens);
+            continue;
+        }
 
-        let ast = parse_block_body(Tokenizer::new(&input).spanned())?;
+        pending.push_str(&line);
 
-        println!("Parsed: {:?}", ast);
+        // A blank line gives up on an unfinished statement
+        if !blank && unfinished(&pending) {
+            continue;
+        }
+
+        let input = pending.trim_end().to_owned();
+        pending.clear();
+
+        match interp.eval("<stdin>", &input) {
+            Ok(Some(canary::value::Value::Nil(()))) | Ok(None) => (),
+            Ok(Some(value)) => println!("{}", value),
+            Err(err @ Error::Exit { .. }) => return Err(err),
+            Err(err) => report(&err),
+        }
     }
 }
 
+enum Command {
+    Continue,
+    Quit,
+}
+
+/// Run a REPL command such as `:vars`.
+fn command(interp: &mut canary::eval::Interpreter, line: &str) -> Result<Command> {
+    use canary::opcode::Func;
+
+    let mut words = line.split_whitespace();
+
+    match (words.next(), words.next()) {
+        (Some(":quit"), None) | (Some(":q"), None) => return Ok(Command::Quit),
+
+        (Some(":vars"), None) => {
+            for (name, value) in interp.globals() {
+                println!("%{} = {}", name, value);
+            }
+
+            for (name, value) in interp.locals() {
+                println!("${} = {}", name, value);
+            }
+        },
+
+        (Some(":funcs"), None) => {
+            let mut subs: Vec<_> = interp.module().functions.iter()
+                .filter_map(|(name, &(argc, ref func))| match *func {
+                    Func::Interpreted(_) => Some((name.clone(), argc)),
+                    _ => None,
+                })
+                .collect();
+
+            subs.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+
+            for (name, argc) in subs {
+                println!("sub {} (takes {})", name, argc);
+            }
+        },
+
+        (Some(":disasm"), None) => {
+            print!("{}", interp.module().disassemble());
+        },
+
+        (Some(":disasm"), Some(name)) => {
+            let name = interp.intern(name)?;
+
+            match interp.module().functions.get(&name) {
+                Some(&(_, Func::Interpreted(ref code))) => {
+                    print!("{}", code.disassemble(&interp.module().constants));
+                },
+
+                Some(_) => println!("{} is a native function", name),
+                None => println!("no sub named {}", name),
+            }
+        },
+
+        _ => {
+            println!("commands: :vars, :funcs, :disasm [NAME], :quit");
+        },
+    }
+
+    Ok(Command::Continue)
+}
+
+/// Whether `input` ends partway through a statement, so that more lines
+/// might finish it.
+fn unfinished(input: &str) -> bool {
+    use canary::ast::{parse_module, ParseError};
+    use canary::token::Tokenizer;
+
+    let mut tokens = Tokenizer::new(input).spanned();
+
+    match parse_module(&mut tokens) {
+        Err(ParseError::UnrecognizedToken { token: None, .. }) => true,
+
+        Err(ParseError::User { ref error }) => match *error.root() {
+            Error::UnterminatedHeredoc { .. } => true,
+            Error::UnterminatedComment => true,
+            _ => tokens.at_end(),
+        },
+
+        _ => false,
+    }
+}
+
+#[test]
+fn continuation() {
+    for input in &[
+        "sub f() {\n",
+        "if 1 {\n    print(\"a\");\n",
+        "print(\"two\n",
+        "print(<<END);\nline\n",
+        "#[[ notes\n",
+        "print(1)\n",
+    ] {
+        assert!(unfinished(input), "{:?}", input);
+    }
+
+    for input in &[
+        "sub f() {\n}\n",
+        "print(\"two\nlines\");\n",
+        "print(<<END);\nline\nEND\n",
+        "print(1);\n",
+        "print(1));\n",
+        "",
+    ] {
+        assert!(!unfinished(input), "{:?}", input);
+    }
+}
@@ -4,25 +4,122 @@ use std::sync::Arc;
 use super::*;
 use ident::*;
 use value::*;
+use eval::Interpreter;
 
 use pattern;
 
 use backpat::GroupNumber;
 
+#[derive(Clone)]
 pub struct Module {
     pub begin: InterpretedFn,
     pub functions: HashMap<Ident, (Argc, Func)>,
     pub strings: Strings,
+    /// Referred to by index from the code of every script sub
+    pub constants: Constants,
+    /// Used to report where runtime errors happened
+    pub source: Option<Source>,
+    /// Whether subs compiled into this module go through the peephole
+    /// pass. Turning it off keeps bytecode in the shape the compiler
+    /// emitted, which is easier to check against the source.
+    pub optimize: bool,
 }
 
-pub type NativeFn = Arc<Fn(Vec<Value>) -> Result<Value>>;
+/// The strings, names and patterns used by a module's code. Opcodes refer
+/// to them by index, and each string or name is stored once.
+#[derive(Clone, Debug, Default)]
+pub struct Constants {
+    strings: Vec<Str>,
+    names: Vec<Ident>,
+    patterns: Vec<pattern::Expr>,
+    string_index: HashMap<Str, usize>,
+    name_index: HashMap<Ident, usize>,
+}
 
+/// The name and text of the file a module was compiled from
 #[derive(Clone, Debug)]
-pub struct InterpretedFn(Arc<[Op]>);
+pub struct Source {
+    pub name: Str,
+    pub text: Str,
+}
+
+/// A line and column in a source file, both counting from 1
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub file: Str,
+    pub line: usize,
+    pub column: usize,
+    /// The text of that line, for error excerpts
+    pub source_line: Str,
+}
+
+pub type NativeFn = Arc<dyn Fn(Vec<Value>) -> Result<Value> + Send + Sync>;
+
+pub type ContextFn = Arc<dyn Fn(&mut Interpreter, Vec<Value>) -> Result<Value> + Send + Sync>;
+
+#[derive(Clone, Debug)]
+pub struct InterpretedFn {
+    code: Arc<[Op]>,
+    // (pc, byte offset) pairs sorted by pc
+    lines: Arc<[(usize, usize)]>,
+    // What the offsets point into
+    source: Option<Source>,
+    // Which slots hold named variables, and where
+    vars: Arc<[LocalVar]>,
+}
+
+/// A `my` variable or parameter, named in slot `slot` while the pc is
+/// between `start` and `end` inclusive.
+#[derive(Clone, Debug)]
+pub struct LocalVar {
+    pub name: Ident,
+    pub slot: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Which of the stdlib natives that reach outside the interpreter a
+/// module gets. Everything else in the stdlib is pure computation and is
+/// always there.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Profile {
+    /// `print`, `say`, `print_no_nl`, `printf`, `readline` and
+    /// `read_all_stdin`
+    pub stdio: bool,
+    /// `time`, `clock_ms` and `sleep`
+    pub time: bool,
+    /// `env`
+    pub env: bool,
+    /// `run`, from `Module::enable_processes`
+    pub process: bool,
+}
+
+impl Profile {
+    /// What `Module::stdlib` provides: everything but processes.
+    pub fn standard() -> Self {
+        Profile {
+            stdio: true,
+            time: true,
+            env: true,
+            process: false,
+        }
+    }
+
+    /// No way to reach outside the interpreter at all.
+    pub fn pure() -> Self {
+        Profile {
+            stdio: false,
+            time: false,
+            env: false,
+            process: false,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub enum Func {
     Native(NativeFn),
+    Context(ContextFn),
     Interpreted(InterpretedFn),
 }
 
@@ -30,33 +127,70 @@ pub enum Func {
 pub enum Argc {
     Exactly(usize),
     AtLeast(usize),
+    Between(usize, usize),
 }
 
-#[derive(Clone, Debug)]
+/// Strings, names and patterns are indexes into the module's `Constants`.
+#[derive(Copy, Clone, Debug)]
 pub enum Op<Label=usize> {
     RET,
     DUP,
     DROP,
     NOT,
     NIL,
-    CALL { name: Ident, argc: usize, },
+    /// Replace a value with the name of its type, like `typeof`
+    TYPEOF,
+    /// Replace a List or Str and the two bounds above it with a slice
+    SLICE,
+    CALL { name: usize, argc: usize, },
+    /// Call method `name` on the value below the `argc` arguments
+    METHOD { name: usize, argc: usize, },
     BINOP { op: Binop, },
     LOAD { src: usize, },
     STORE { dst: usize, },
     GROUP { num: GroupNumber, },
     GLOBALS,
     INS,
+    PUSHB { boolean: Bool, },
     PUSHI { int: Int, },
-    PUSHS { string: Str, },
-    PUSHN { name: Ident, },
-    PAT { pat: pattern::Expr, },
+    PUSHF { float: Float, },
+    PUSHS { string: usize, },
+    PUSHN { name: usize, },
+    PAT { pat: usize, },
     LIST { len: usize, },
     STR { len: usize, },
-    REC,
+    REC { len: usize, },
+    UNPACK { len: usize, },
+    /// Collect every argument from `index` onward into one list
+    REST { index: usize, },
     JUMP { dst: Label, },
     JNZ { dst: Label, },
+    /// Jump if the caller passed argument number `index`
+    JARG { index: usize, dst: Label, },
+    ITER,
+    NEXT { iter: usize, dst: Label, },
+    TRY { dst: Label, },
+    UNTRY,
+    THROW,
     MARK { len: usize, },
-    ASSERT { expr: String, },
+    /// `expr` is the source of the assertion, kept with the strings
+    ASSERT { expr: usize, },
+    /// Pop a message and fail the assertion of `expr` with it
+    FAIL { expr: usize, },
+}
+
+impl<Label: Copy> Op<Label> {
+    /// Where this instruction may jump to, if anywhere.
+    pub fn target(&self) -> Option<Label> {
+        match *self {
+            Op::JUMP { dst } |
+            Op::JNZ { dst } |
+            Op::JARG { dst, .. } |
+            Op::NEXT { dst, .. } |
+            Op::TRY { dst } => Some(dst),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -65,13 +199,49 @@ pub enum Binop {
     SUB,
     DIV,
     MUL,
+    MOD,
     IDX,
     MATCH,
     EQ,
     NE,
+    LT,
+    GT,
+    LE,
+    GE,
+    SLT,
+    SGT,
+    SLE,
+    SGE,
+    CMP,
 }
 
 impl Module {
+    /// Remember where this module came from, so that runtime errors
+    /// can report a file, line and column.
+    pub fn set_source(&mut self, name: &str, text: &str) {
+        self.attach_source(Source::new(name, text));
+    }
+
+    /// Record `source` for the module, and for every script sub that does
+    /// not already know where it came from.
+    pub fn attach_source(&mut self, source: Source) {
+        let attach = |code: &mut InterpretedFn| {
+            if code.source.is_none() {
+                code.source = Some(source.clone());
+            }
+        };
+
+        attach(&mut self.begin);
+
+        for (_, func) in self.functions.values_mut() {
+            if let Func::Interpreted(ref mut code) = *func {
+                attach(code);
+            }
+        }
+
+        self.source = Some(source);
+    }
+
     pub fn call(&self, name: Ident, argv: &[Value]) -> Result<Func> {
         let (wanted, func) = self.functions.get(&name).cloned()
             .ok_or(Error::NoSuchLabel)?;
@@ -83,6 +253,10 @@ impl Module {
                 Ok(func)
             },
 
+            Argc::Between(lo, hi) if lo <= argv.len() && argv.len() <= hi => {
+                Ok(func)
+            },
+
             expected => Err(Error::WrongArgc {
                 expected,
                 func: name,
@@ -94,14 +268,165 @@ impl Module {
 
 impl InterpretedFn {
     pub fn from_vec(code: Vec<Op>) -> Self {
-        InterpretedFn(code.into())
+        InterpretedFn::with_lines(code, vec![])
+    }
+
+    pub fn with_lines(code: Vec<Op>, lines: Vec<(usize, usize)>) -> Self {
+        InterpretedFn {
+            code: code.into(),
+            lines: lines.into(),
+            source: None,
+            vars: Arc::new([]),
+        }
+    }
+
+    pub fn with_vars(mut self, vars: Vec<LocalVar>) -> Self {
+        self.vars = vars.into();
+        self
+    }
+
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.code
+    }
+
+    /// (pc, byte offset) pairs marking where each statement begins.
+    pub fn lines(&self) -> &[(usize, usize)] {
+        &self.lines
+    }
+
+    pub fn vars(&self) -> &[LocalVar] {
+        &self.vars
+    }
+
+    /// The variables in scope at `pc`, given that the first `mark` slots
+    /// have been declared, sorted by slot.
+    pub fn vars_at(&self, pc: usize, mark: usize) -> Vec<&LocalVar> {
+        let mut vars: Vec<_> = self.vars.iter()
+            .filter(|var| var.start <= pc && pc <= var.end && var.slot < mark)
+            .collect();
+
+        vars.sort_by_key(|var| var.slot);
+        vars
     }
 
     pub fn fetch(&self, pc: usize) -> Result<Op> {
-        self.0.get(pc).cloned().ok_or(Error::PcOutOfBounds { pc })
+        self.code.get(pc).copied().ok_or(Error::PcOutOfBounds { pc })
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// The source offset of the statement that `pc` belongs to.
+    pub fn offset(&self, pc: usize) -> Option<usize> {
+        let end = self.lines.partition_point(|&(start, _)| start <= pc);
+        end.checked_sub(1).map(|i| self.lines[i].1)
+    }
+
+    /// Where the statement that `pc` belongs to appears in the source.
+    pub fn locate(&self, pc: usize) -> Option<Location> {
+        let source = self.source.as_ref()?;
+        self.offset(pc).map(|pos| source.locate(pos))
+    }
+}
+
+impl Constants {
+    pub fn add_string(&mut self, string: Str) -> usize {
+        if let Some(&index) = self.string_index.get(&string) {
+            return index;
+        }
+
+        let index = self.strings.len();
+        self.strings.push(string.clone());
+        self.string_index.insert(string, index);
+        index
+    }
+
+    pub fn add_name(&mut self, name: Ident) -> usize {
+        if let Some(&index) = self.name_index.get(&name) {
+            return index;
+        }
+
+        let index = self.names.len();
+        self.names.push(name.clone());
+        self.name_index.insert(name, index);
+        index
+    }
+
+    pub fn add_pattern(&mut self, pat: pattern::Expr) -> usize {
+        self.patterns.push(pat);
+        self.patterns.len() - 1
+    }
+
+    pub fn string(&self, index: usize) -> Result<Str> {
+        self.strings.get(index).cloned()
+            .ok_or(Error::ConstantOutOfBounds { index })
+    }
+
+    pub fn name(&self, index: usize) -> Result<Ident> {
+        self.names.get(index).cloned()
+            .ok_or(Error::ConstantOutOfBounds { index })
+    }
+
+    pub fn pattern(&self, index: usize) -> Result<pattern::Expr> {
+        self.patterns.get(index).cloned()
+            .ok_or(Error::ConstantOutOfBounds { index })
+    }
+
+    pub fn strings(&self) -> &[Str] {
+        &self.strings
+    }
+
+    pub fn names(&self) -> &[Ident] {
+        &self.names
+    }
+
+    pub fn patterns(&self) -> &[pattern::Expr] {
+        &self.patterns
+    }
+}
+
+impl Source {
+    pub fn new(name: &str, text: &str) -> Self {
+        Source { name: name.into(), text: text.into() }
+    }
+
+    /// The location of a byte offset into the text. An offset inside a
+    /// character, which only bad bytecode can give, counts as the start
+    /// of that character.
+    pub fn locate(&self, offset: usize) -> Location {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let before = &self.text[.. offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start ..].chars().count() + 1;
+        self.at(line, column)
+    }
+
+    pub fn at(&self, line: usize, column: usize) -> Location {
+        let source_line = self.text.lines()
+            .nth(line.saturating_sub(1))
+            .unwrap_or("");
+
+        Location {
+            file: self.name.clone(),
+            line,
+            column,
+            source_line: source_line.into(),
+        }
     }
 }
@@ -0,0 +1,184 @@
+use opcode::*;
+
+/// Tidy up a function after its labels are resolved: jumps to jumps go
+/// straight to the end of the chain, code that nothing can reach is
+/// dropped, and so are `DUP` `DROP` pairs and jumps to the next
+/// instruction. Line and variable tables are moved to match.
+pub(crate) fn peephole(
+    mut code: Vec<Op>,
+    lines: &mut Vec<(usize, usize)>,
+    vars: &mut [LocalVar],
+) -> Vec<Op> {
+    // Removing code can leave more to remove
+    loop {
+        let len = code.len();
+        code = pass(code, lines, vars);
+
+        if code.len() == len {
+            return code;
+        }
+    }
+}
+
+fn pass(
+    mut code: Vec<Op>,
+    lines: &mut Vec<(usize, usize)>,
+    vars: &mut [LocalVar],
+) -> Vec<Op> {
+    for pc in 0 .. code.len() {
+        if let Some(dst) = code[pc].target() {
+            let dst = follow(&code, dst);
+            set_target(&mut code[pc], dst);
+        }
+    }
+
+    let mut keep = reachable(&code);
+
+    let mut landing = vec![false; code.len() + 1];
+    for (pc, op) in code.iter().enumerate() {
+        if keep[pc] {
+            if let Some(dst) = op.target() {
+                landing[dst] = true;
+            }
+        }
+    }
+
+    for pc in 0 .. code.len() {
+        if !keep[pc] {
+            continue;
+        }
+
+        match code[pc] {
+            Op::JUMP { dst } if dst == pc + 1 => keep[pc] = false,
+
+            Op::DUP if pc + 1 < code.len() && keep[pc + 1] && !landing[pc + 1] => {
+                if let Op::DROP = code[pc + 1] {
+                    keep[pc] = false;
+                    keep[pc + 1] = false;
+                }
+            },
+
+            _ => (),
+        }
+    }
+
+    // New position of every old pc; removed code maps to what follows it
+    let mut moved = Vec::with_capacity(code.len() + 1);
+    let mut next = 0;
+    for &kept in keep.iter() {
+        moved.push(next);
+        if kept {
+            next += 1;
+        }
+    }
+    moved.push(next);
+
+    let code = code.into_iter().zip(keep)
+        .filter(|&(_, kept)| kept)
+        .map(|(mut op, _)| {
+            if let Some(dst) = op.target() {
+                set_target(&mut op, moved[dst]);
+            }
+
+            op
+        })
+        .collect();
+
+    // A statement whose code is gone gives way to the one after it
+    let mut moved_lines: Vec<(usize, usize)> = vec![];
+    for &(pc, offset) in lines.iter() {
+        let pc = moved[pc.min(moved.len() - 1)];
+
+        if moved_lines.last().is_some_and(|&(last, _)| last == pc) {
+            moved_lines.pop();
+        }
+
+        moved_lines.push((pc, offset));
+    }
+    *lines = moved_lines;
+
+    for var in vars.iter_mut() {
+        var.start = moved[var.start.min(moved.len() - 1)];
+        var.end = moved[var.end.min(moved.len() - 1)];
+    }
+
+    code
+}
+
+fn set_target(op: &mut Op, to: usize) {
+    match *op {
+        Op::JUMP { ref mut dst } |
+        Op::JNZ { ref mut dst } |
+        Op::JARG { ref mut dst, .. } |
+        Op::NEXT { ref mut dst, .. } |
+        Op::TRY { ref mut dst } => *dst = to,
+        _ => (),
+    }
+}
+
+/// Where a jump to `dst` finally lands, skipping unconditional jumps.
+fn follow(code: &[Op], mut dst: usize) -> usize {
+    // A loop of jumps never lands anywhere, so give up after going round
+    for _ in 0 .. code.len() {
+        match code.get(dst) {
+            Some(&Op::JUMP { dst: next }) if next != dst => dst = next,
+            _ => break,
+        }
+    }
+
+    dst
+}
+
+/// Which instructions can run, starting from the first.
+fn reachable(code: &[Op]) -> Vec<bool> {
+    let mut seen = vec![false; code.len()];
+    let mut todo = vec![0];
+
+    while let Some(pc) = todo.pop() {
+        if pc >= code.len() || seen[pc] {
+            continue;
+        }
+
+        seen[pc] = true;
+
+        match code[pc] {
+            Op::RET | Op::THROW | Op::FAIL { .. } => (),
+            Op::JUMP { dst } => todo.push(dst),
+
+            ref op => {
+                todo.push(pc + 1);
+                if let Some(dst) = op.target() {
+                    todo.push(dst);
+                }
+            },
+        }
+    }
+
+    seen
+}
+
+#[test]
+fn peephole_pass() {
+    let code = vec![
+        Op::JUMP { dst: 2 },
+        Op::PUSHI { int: 1 },
+        Op::JUMP { dst: 3 },
+        Op::DUP,
+        Op::DROP,
+        Op::JNZ { dst: 7 },
+        Op::RET,
+        Op::NIL,
+        Op::RET,
+        Op::PUSHI { int: 2 },
+    ];
+
+    let mut lines = vec![(0, 0), (1, 10), (3, 20), (7, 30)];
+    let code = peephole(code, &mut lines, &mut []);
+
+    let constants = Constants::default();
+    let listing: Vec<_> = code.iter()
+        .map(|op| op.listing(&constants).to_string())
+        .collect();
+    assert_eq!(listing, vec!["JNZ 2", "RET", "NIL", "RET"]);
+    assert_eq!(lines, vec![(0, 20), (2, 30)]);
+}
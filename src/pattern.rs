@@ -42,7 +42,7 @@ mod display {
     use super::*;
     use std::fmt::{Display, Formatter, Result};
 
-    impl Display for Var<Ident> {
+    impl<Local: Display> Display for Var<Local> {
         fn fmt(&self, f: &mut Formatter) -> Result {
             match *self {
                 Var::Local { ref name } => write!(f, "${}", name),
@@ -0,0 +1,588 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use backpat::parse::{Ast, Branch, Class, Group, Leaf, Repeat};
+
+use super::*;
+use opcode::*;
+use pattern::Var;
+use value::*;
+
+const MAGIC: &[u8; 8] = b"CANARY\0\0";
+
+/// Bumped whenever the encoding below changes.
+pub const VERSION: u32 = 12;
+
+impl Module {
+    /// Write the compiled script to `out`. Only script subs are saved;
+    /// native functions are referred to by name, and `deserialize`
+    /// provides the stdlib again, with the same `Profile`. Natives defined
+    /// by the host have to be redefined after loading.
+    pub fn serialize<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut enc = Encoder { out };
+
+        enc.bytes(MAGIC)?;
+        enc.u32(VERSION)?;
+
+        match self.source {
+            Some(ref source) => {
+                enc.u8(1)?;
+                enc.str(&source.name)?;
+                enc.str(&source.text)?;
+            },
+
+            None => enc.u8(0)?,
+        }
+
+        enc.profile(&self.profile())?;
+        enc.constants(&self.constants)?;
+        enc.function(&self.begin)?;
+
+        let mut subs: Vec<_> = self.functions.iter()
+            .filter_map(|(name, (argc, func))| match *func {
+                Func::Interpreted(ref code) => Some((name, *argc, code)),
+                _ => None,
+            })
+            .collect();
+
+        subs.sort_by_key(|&(name, _, _)| name.as_ref());
+
+        enc.usize(subs.len())?;
+        for (name, argc, code) in subs {
+            enc.str(name.as_ref())?;
+            enc.argc(argc)?;
+            enc.function(code)?;
+        }
+
+        Ok(())
+    }
+
+    /// Load a module written by `serialize`.
+    pub fn deserialize<R: Read>(input: &mut R) -> Result<Module> {
+        let mut module = Module::stdlib()?;
+
+        {
+            let mut dec = Decoder { input, strings: &module.strings };
+
+            let mut magic = [0; 8];
+            dec.input.read_exact(&mut magic)?;
+            if &magic != MAGIC {
+                return Err(Error::InvalidBytecode);
+            }
+
+            let found = dec.u32()?;
+            if found != VERSION {
+                return Err(Error::BytecodeVersion { found, expected: VERSION });
+            }
+
+            let source = match dec.u8()? {
+                0 => None,
+                1 => Some(Source { name: dec.string()?, text: dec.string()? }),
+                _ => return Err(Error::InvalidBytecode),
+            };
+
+            let profile = dec.profile()?;
+            let constants = dec.constants()?;
+            let begin = dec.function()?;
+
+            let mut subs = vec![];
+            for _ in 0 .. dec.usize()? {
+                let name: Ident = dec.ident()?;
+                let argc = dec.argc()?;
+                let code = dec.function()?;
+                subs.push((name, argc, code));
+            }
+
+            module.begin = begin;
+            module.constants = constants;
+
+            for (name, argc, code) in subs {
+                module.functions.insert(name, (argc, Func::Interpreted(code)));
+            }
+
+            if let Some(source) = source {
+                module.attach_source(source);
+            }
+
+            if profile.process {
+                module.enable_processes()?;
+            }
+
+            module.restrict(&profile);
+        }
+
+        Ok(module)
+    }
+}
+
+struct Encoder<'a, W: 'a> {
+    out: &'a mut W,
+}
+
+impl<'a, W: Write> Encoder<'a, W> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        Ok(self.out.write_all(bytes)?)
+    }
+
+    fn u8(&mut self, n: u8) -> Result<()> {
+        self.bytes(&[n])
+    }
+
+    fn u32(&mut self, n: u32) -> Result<()> {
+        self.bytes(&n.to_le_bytes())
+    }
+
+    fn usize(&mut self, n: usize) -> Result<()> {
+        self.bytes(&(n as u64).to_le_bytes())
+    }
+
+    fn str(&mut self, s: &str) -> Result<()> {
+        self.usize(s.len())?;
+        self.bytes(s.as_bytes())
+    }
+
+    fn argc(&mut self, argc: Argc) -> Result<()> {
+        match argc {
+            Argc::Exactly(n) => { self.u8(0)?; self.usize(n) },
+            Argc::AtLeast(n) => { self.u8(1)?; self.usize(n) },
+            Argc::Between(lo, hi) => { self.u8(2)?; self.usize(lo)?; self.usize(hi) },
+        }
+    }
+
+    fn profile(&mut self, profile: &Profile) -> Result<()> {
+        self.u8(profile.stdio as u8)?;
+        self.u8(profile.time as u8)?;
+        self.u8(profile.env as u8)?;
+        self.u8(profile.process as u8)
+    }
+
+    fn constants(&mut self, constants: &Constants) -> Result<()> {
+        self.usize(constants.strings().len())?;
+        for string in constants.strings() {
+            self.str(string)?;
+        }
+
+        self.usize(constants.names().len())?;
+        for name in constants.names() {
+            self.str(name.as_ref())?;
+        }
+
+        self.usize(constants.patterns().len())?;
+        for pat in constants.patterns() {
+            self.u8(pat.ignore_case as u8)?;
+            self.group(&pat.root)?;
+        }
+
+        Ok(())
+    }
+
+    fn function(&mut self, code: &InterpretedFn) -> Result<()> {
+        self.usize(code.len())?;
+        for op in code.ops() {
+            self.op(op)?;
+        }
+
+        let lines = code.lines();
+        self.usize(lines.len())?;
+        for &(pc, offset) in lines {
+            self.usize(pc)?;
+            self.usize(offset)?;
+        }
+
+        let vars = code.vars();
+        self.usize(vars.len())?;
+        for var in vars {
+            self.str(var.name.as_ref())?;
+            self.usize(var.slot)?;
+            self.usize(var.start)?;
+            self.usize(var.end)?;
+        }
+
+        Ok(())
+    }
+
+    fn op(&mut self, op: &Op) -> Result<()> {
+        match *op {
+            Op::RET => self.u8(0),
+            Op::DUP => self.u8(1),
+            Op::DROP => self.u8(2),
+            Op::NOT => self.u8(3),
+            Op::NIL => self.u8(4),
+            Op::CALL { name, argc } => {
+                self.u8(5)?;
+                self.usize(name)?;
+                self.usize(argc)
+            },
+            Op::BINOP { op } => { self.u8(6)?; self.u8(op as u8) },
+            Op::LOAD { src } => { self.u8(7)?; self.usize(src) },
+            Op::STORE { dst } => { self.u8(8)?; self.usize(dst) },
+            Op::GROUP { num } => { self.u8(9)?; self.u8(num) },
+            Op::GLOBALS => self.u8(10),
+            Op::INS => self.u8(11),
+            Op::PUSHB { boolean } => { self.u8(12)?; self.u8(boolean as u8) },
+            Op::PUSHI { int } => { self.u8(13)?; self.bytes(&int.to_le_bytes()) },
+            Op::PUSHF { float } => {
+                self.u8(14)?;
+                self.bytes(&float.to_bits().to_le_bytes())
+            },
+            Op::PUSHS { string } => { self.u8(15)?; self.usize(string) },
+            Op::PUSHN { name } => { self.u8(16)?; self.usize(name) },
+            Op::PAT { pat } => { self.u8(17)?; self.usize(pat) },
+            Op::LIST { len } => { self.u8(18)?; self.usize(len) },
+            Op::STR { len } => { self.u8(19)?; self.usize(len) },
+            Op::REC { len } => { self.u8(20)?; self.usize(len) },
+            Op::UNPACK { len } => { self.u8(21)?; self.usize(len) },
+            Op::REST { index } => { self.u8(22)?; self.usize(index) },
+            Op::JUMP { dst } => { self.u8(23)?; self.usize(dst) },
+            Op::JNZ { dst } => { self.u8(24)?; self.usize(dst) },
+            Op::JARG { index, dst } => {
+                self.u8(25)?;
+                self.usize(index)?;
+                self.usize(dst)
+            },
+            Op::ITER => self.u8(26),
+            Op::NEXT { iter, dst } => {
+                self.u8(27)?;
+                self.usize(iter)?;
+                self.usize(dst)
+            },
+            Op::TRY { dst } => { self.u8(28)?; self.usize(dst) },
+            Op::UNTRY => self.u8(29),
+            Op::THROW => self.u8(30),
+            Op::MARK { len } => { self.u8(31)?; self.usize(len) },
+            Op::ASSERT { expr } => { self.u8(32)?; self.usize(expr) },
+            Op::METHOD { name, argc } => {
+                self.u8(33)?;
+                self.usize(name)?;
+                self.usize(argc)
+            },
+            Op::TYPEOF => self.u8(34),
+            Op::SLICE => self.u8(35),
+            Op::FAIL { expr } => { self.u8(36)?; self.usize(expr) },
+        }
+    }
+
+    fn group(&mut self, group: &Group<Var<usize>>) -> Result<()> {
+        self.u8(group.number)?;
+        self.usize(group.branches.len())?;
+
+        for branch in group.branches.iter() {
+            self.usize(branch.leaves.len())?;
+            for leaf in branch.leaves.iter() {
+                self.leaf(leaf)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn leaf(&mut self, leaf: &Leaf<Var<usize>>) -> Result<()> {
+        match *leaf {
+            Leaf::Group(ref group) => { self.u8(0)?; self.group(group) },
+            Leaf::Raw(ref text) => { self.u8(1)?; self.str(text) },
+            Leaf::Class(ref class) => { self.u8(2)?; self.class(class) },
+            Leaf::AnchorStart => self.u8(3),
+            Leaf::AnchorEnd => self.u8(4),
+            Leaf::Repeat { ref prefix, times, lazy } => {
+                self.u8(5)?;
+                self.leaf(prefix)?;
+                match times {
+                    Repeat::OneOrZero => self.u8(0)?,
+                    Repeat::ZeroOrMore => self.u8(1)?,
+                    Repeat::OneOrMore => self.u8(2)?,
+                    Repeat::Count(n) => { self.u8(3)?; self.usize(n)? },
+                    Repeat::Between(min, max) => {
+                        self.u8(4)?;
+                        self.usize(min)?;
+                        self.u8(max.is_some() as u8)?;
+                        self.usize(max.unwrap_or(0))?
+                    },
+                }
+                self.u8(lazy as u8)
+            },
+            Leaf::Payload(Var::Local { name }) => { self.u8(6)?; self.usize(name) },
+            Leaf::Payload(Var::Global { ref name }) => {
+                self.u8(7)?;
+                self.str(name.as_ref())
+            },
+        }
+    }
+
+    fn class(&mut self, class: &Class) -> Result<()> {
+        match *class {
+            Class::Dot => self.u8(0),
+            Class::Digit => self.u8(1),
+            Class::Word => self.u8(2),
+            Class::Space => self.u8(3),
+            Class::Custom { invert, ref members } => {
+                self.u8(4)?;
+                self.u8(invert as u8)?;
+
+                // Sorted so that the same module always encodes the same way
+                let mut members: Vec<char> = members.iter().cloned().collect();
+                members.sort();
+
+                self.usize(members.len())?;
+                for c in members {
+                    self.u32(c as u32)?;
+                }
+
+                Ok(())
+            },
+        }
+    }
+}
+
+struct Decoder<'a, R: 'a> {
+    input: &'a mut R,
+    strings: &'a Strings,
+}
+
+impl<'a, R: Read> Decoder<'a, R> {
+    fn u8(&mut self) -> Result<u8> {
+        let mut buf = [0; 1];
+        self.input.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.input.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        self.input.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let n = self.u64()?;
+        if n > usize::MAX as u64 {
+            return Err(Error::InvalidBytecode);
+        }
+
+        Ok(n as usize)
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::InvalidBytecode),
+        }
+    }
+
+    fn profile(&mut self) -> Result<Profile> {
+        Ok(Profile {
+            stdio: self.bool()?,
+            time: self.bool()?,
+            env: self.bool()?,
+            process: self.bool()?,
+        })
+    }
+
+    fn text(&mut self) -> Result<String> {
+        let len = self.usize()?;
+
+        let mut buf = vec![];
+        self.input.by_ref().take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(Error::InvalidBytecode);
+        }
+
+        String::from_utf8(buf).map_err(|_| Error::InvalidBytecode)
+    }
+
+    fn string(&mut self) -> Result<Str> {
+        Ok(self.text()?.into())
+    }
+
+    fn ident(&mut self) -> Result<Ident> {
+        let name = self.text()?;
+        self.strings.intern(name)
+    }
+
+    fn argc(&mut self) -> Result<Argc> {
+        Ok(match self.u8()? {
+            0 => Argc::Exactly(self.usize()?),
+            1 => Argc::AtLeast(self.usize()?),
+            2 => Argc::Between(self.usize()?, self.usize()?),
+            _ => return Err(Error::InvalidBytecode),
+        })
+    }
+
+    fn constants(&mut self) -> Result<Constants> {
+        let mut constants = Constants::default();
+
+        // Each entry must land at the index the code expects
+        for i in 0 .. self.usize()? {
+            if constants.add_string(self.string()?) != i {
+                return Err(Error::InvalidBytecode);
+            }
+        }
+
+        for i in 0 .. self.usize()? {
+            if constants.add_name(self.ident()?) != i {
+                return Err(Error::InvalidBytecode);
+            }
+        }
+
+        for _ in 0 .. self.usize()? {
+            let ignore_case = self.bool()?;
+            let root = self.group()?;
+            constants.add_pattern(Arc::new(Ast { root, ignore_case }));
+        }
+
+        Ok(constants)
+    }
+
+    fn function(&mut self) -> Result<InterpretedFn> {
+        let mut code = vec![];
+        for _ in 0 .. self.usize()? {
+            code.push(self.op()?);
+        }
+
+        let mut lines = vec![];
+        for _ in 0 .. self.usize()? {
+            lines.push((self.usize()?, self.usize()?));
+        }
+
+        let mut vars = vec![];
+        for _ in 0 .. self.usize()? {
+            vars.push(LocalVar {
+                name: self.ident()?,
+                slot: self.usize()?,
+                start: self.usize()?,
+                end: self.usize()?,
+            });
+        }
+
+        Ok(InterpretedFn::with_lines(code, lines).with_vars(vars))
+    }
+
+    fn op(&mut self) -> Result<Op> {
+        Ok(match self.u8()? {
+            0 => Op::RET,
+            1 => Op::DUP,
+            2 => Op::DROP,
+            3 => Op::NOT,
+            4 => Op::NIL,
+            5 => Op::CALL { name: self.usize()?, argc: self.usize()? },
+            6 => Op::BINOP { op: self.binop()? },
+            7 => Op::LOAD { src: self.usize()? },
+            8 => Op::STORE { dst: self.usize()? },
+            9 => Op::GROUP { num: self.u8()? },
+            10 => Op::GLOBALS,
+            11 => Op::INS,
+            12 => Op::PUSHB { boolean: self.bool()? },
+            13 => Op::PUSHI { int: self.u64()? as Int },
+            14 => Op::PUSHF { float: Float::from_bits(self.u64()?) },
+            15 => Op::PUSHS { string: self.usize()? },
+            16 => Op::PUSHN { name: self.usize()? },
+            17 => Op::PAT { pat: self.usize()? },
+            18 => Op::LIST { len: self.usize()? },
+            19 => Op::STR { len: self.usize()? },
+            20 => Op::REC { len: self.usize()? },
+            21 => Op::UNPACK { len: self.usize()? },
+            22 => Op::REST { index: self.usize()? },
+            23 => Op::JUMP { dst: self.usize()? },
+            24 => Op::JNZ { dst: self.usize()? },
+            25 => Op::JARG { index: self.usize()?, dst: self.usize()? },
+            26 => Op::ITER,
+            27 => Op::NEXT { iter: self.usize()?, dst: self.usize()? },
+            28 => Op::TRY { dst: self.usize()? },
+            29 => Op::UNTRY,
+            30 => Op::THROW,
+            31 => Op::MARK { len: self.usize()? },
+            32 => Op::ASSERT { expr: self.usize()? },
+            33 => Op::METHOD { name: self.usize()?, argc: self.usize()? },
+            34 => Op::TYPEOF,
+            35 => Op::SLICE,
+            36 => Op::FAIL { expr: self.usize()? },
+            _ => return Err(Error::InvalidBytecode),
+        })
+    }
+
+    fn binop(&mut self) -> Result<Binop> {
+        use opcode::Binop::*;
+
+        let ops = [
+            ADD, SUB, DIV, MUL, MOD, IDX, MATCH, EQ, NE, LT, GT, LE, GE,
+            SLT, SGT, SLE, SGE, CMP,
+        ];
+
+        ops.get(self.u8()? as usize).cloned().ok_or(Error::InvalidBytecode)
+    }
+
+    fn group(&mut self) -> Result<Group<Var<usize>>> {
+        let number = self.u8()?;
+
+        let mut branches = vec![];
+        for _ in 0 .. self.usize()? {
+            let mut leaves = vec![];
+            for _ in 0 .. self.usize()? {
+                leaves.push(self.leaf()?);
+            }
+
+            branches.push(Branch { leaves });
+        }
+
+        Ok(Group { number, branches })
+    }
+
+    fn leaf(&mut self) -> Result<Leaf<Var<usize>>> {
+        Ok(match self.u8()? {
+            0 => Leaf::Group(self.group()?),
+            1 => Leaf::Raw(self.text()?),
+            2 => Leaf::Class(self.class()?),
+            3 => Leaf::AnchorStart,
+            4 => Leaf::AnchorEnd,
+            5 => {
+                let prefix = Box::new(self.leaf()?);
+                let times = match self.u8()? {
+                    0 => Repeat::OneOrZero,
+                    1 => Repeat::ZeroOrMore,
+                    2 => Repeat::OneOrMore,
+                    3 => Repeat::Count(self.usize()?),
+                    4 => {
+                        let min = self.usize()?;
+                        let bounded = self.bool()?;
+                        let max = self.usize()?;
+                        Repeat::Between(min, Some(max).filter(|_| bounded))
+                    },
+                    _ => return Err(Error::InvalidBytecode),
+                };
+                let lazy = self.bool()?;
+
+                Leaf::Repeat { prefix, times, lazy }
+            },
+            6 => Leaf::Payload(Var::Local { name: self.usize()? }),
+            7 => Leaf::Payload(Var::Global { name: self.ident()? }),
+            _ => return Err(Error::InvalidBytecode),
+        })
+    }
+
+    fn class(&mut self) -> Result<Class> {
+        Ok(match self.u8()? {
+            0 => Class::Dot,
+            1 => Class::Digit,
+            2 => Class::Word,
+            3 => Class::Space,
+            4 => {
+                let invert = self.bool()?;
+
+                let mut members = ::std::collections::HashSet::new();
+                for _ in 0 .. self.usize()? {
+                    let c = ::std::char::from_u32(self.u32()?)
+                        .ok_or(Error::InvalidBytecode)?;
+                    members.insert(c);
+                }
+
+                Class::Custom { invert, members }
+            },
+            _ => return Err(Error::InvalidBytecode),
+        })
+    }
+}
@@ -13,8 +13,10 @@ pub enum Token {
     GLOBAL(Ident),
     GROUP(u8),
     VAR(Ident),
+    REST(Ident),
     SYM(Ident),
     INT(Int),
+    FLOAT(Float),
     STR(Vec<Interp>),
     PAT(pattern::Ast),
     LPAR,
@@ -23,28 +25,61 @@ pub enum Token {
     RSQB,
     LCBR,
     RCBR,
-    DEF,
+    /// `sub`, with the doc comment written just before it
+    DEF(Option<Str>),
     LET,
     IF,
     ELSE,
+    ELSIF,
+    UNLESS,
+    UNTIL,
+    GIVEN,
+    WHEN,
     WHILE,
+    PACKAGE,
+    IMPORT,
+    FOR,
+    FOREACH,
+    IN,
     COLON,
     RETURN,
     ASSERT,
+    TRY,
+    CATCH,
+    THROW,
     EQUAL,
     COMMA,
     MATCH,
+    COALESCE,
     DOT,
+    RANGE,
     NOT,
     EQ,
     NE,
+    STRLT,
+    STRGT,
+    STRLE,
+    STRGE,
+    CMP,
+    LT,
+    GT,
+    LE,
+    GE,
     ADD,
     SUB,
     DIV,
     MUL,
+    MOD,
+    ADDEQ,
+    SUBEQ,
+    MULEQ,
+    DIVEQ,
+    MODEQ,
     EOL,
     AND,
     OR,
+    TRUE,
+    FALSE,
 }
 
 use std::str::Chars;
@@ -53,31 +88,47 @@ use std::iter::Peekable;
 #[derive(Clone, Debug, PartialEq)]
 pub enum Interp {
     S(Str),
-    V(Ident),
-    G(Ident),
+    V(Ident, Vec<Access>),
+    G(Ident, Vec<Access>),
     C(GroupNumber),
 }
 
+/// One step into a variable interpolated in a string, as in `"$a[0].b"`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Access {
+    /// `[0]`
+    Index(Int),
+    /// `[$i]`
+    Local(Ident),
+    /// `.name`
+    Field(Ident),
+}
+
 pub struct Spanned<'a> {
     inner: Tokenizer<'a>,
 }
 
+impl<'a> Spanned<'a> {
+    /// Whether every character of the input has been read.
+    pub fn at_end(&self) -> bool {
+        self.inner.right >= self.inner.src.len()
+    }
+}
+
 impl<'a> Iterator for Spanned<'a> {
     type Item = Result<(usize, Token, usize)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let left = self.inner.right;
-
         self.inner.next().map(|result| match result {
             Ok(t) => {
-                Ok((left, t, self.inner.right))
+                Ok((self.inner.start, t, self.inner.right))
             },
 
             Err(mut err) => {
                 if let Some((line, col)) = self.inner.line_and_col() {
                     err = Error::WithPosition {
                         cause: err.into(),
-                        line: line,
+                        line,
                         column: col,
                     };
                 }
@@ -92,19 +143,79 @@ pub struct Tokenizer<'a> {
     src: &'a str,
     input: Peekable<Chars<'a>>,
     strings: Strings,
+    // Where the most recent token began, after any whitespace
+    start: usize,
     left: usize,
     right: usize,
+    // The newline ending a line that started heredocs, and where their
+    // bodies end
+    heredoc: Option<(usize, usize)>,
+    // `##` lines since the last token
+    doc: Vec<String>,
+    // Whether a newline can end a statement
+    newlines: bool,
+    brackets: Vec<Bracket>,
+    // The last token could end a statement
+    complete: bool,
+    // The last token was `return`, which may take a value on the next line
+    returned: bool,
+    // The last token was a far word, which may take a global argument
+    farword: bool,
+    // A `{` after the last token would open a block, not a record
+    block_next: bool,
+    // Bracket depth of a `when` still waiting for its block
+    when: Option<usize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Bracket {
+    Paren,
+    Square,
+    Block,
+    Record,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(src: &'a str) -> Self {
-        Tokenizer {
+        Tokenizer::with_strings(src, &Strings::new())
+    }
+
+    /// Intern names into `strings`, which should be the set the module
+    /// will be compiled with.
+    pub fn with_strings(src: &'a str, strings: &Strings) -> Self {
+        let mut tokenizer = Tokenizer {
             src,
             input: src.chars().peekable(),
-            strings: Strings::new(),
+            strings: strings.clone(),
+            start: 0,
             left: 0,
             right: 0,
+            heredoc: None,
+            doc: vec![],
+            newlines: false,
+            brackets: vec![],
+            complete: false,
+            returned: false,
+            farword: false,
+            block_next: true,
+            when: None,
+        };
+
+        // The `#!/usr/bin/env canary` line of an executable script
+        if src.starts_with("#!") {
+            while let Some(c) = tokenizer.getc() {
+                if c == '\n' { break; }
+            }
         }
+
+        tokenizer
+    }
+
+    /// Let a newline end a statement when the line could end one, rather
+    /// than requiring a semicolon after every statement.
+    pub fn newlines(mut self, on: bool) -> Self {
+        self.newlines = on;
+        self
     }
 
     pub fn spanned(self) -> Spanned<'a> {
@@ -116,7 +227,7 @@ impl<'a> Tokenizer<'a> {
             return None;
         }
 
-        let mut line = 0;
+        let mut line = 1;
         let mut col = 0;
 
         for (i, c) in self.src.char_indices() {
@@ -147,6 +258,20 @@ impl<'a> Tokenizer<'a> {
             self.right = self.left + c.len_utf8();
         }
 
+        // Heredoc bodies were already read; skip over them
+        match self.heredoc {
+            Some((newline, end)) if next.is_some() && self.left == newline => {
+                self.heredoc = None;
+
+                while self.right < end {
+                    let c = self.input.next().unwrap();
+                    self.right += c.len_utf8();
+                }
+            },
+
+            _ => (),
+        }
+
         next
     }
 
@@ -161,34 +286,54 @@ impl<'a> Tokenizer<'a> {
 
         word.push(start);
 
-        while let Some(c) = self.lookahead() {
-            if in_ident(c) {
-                word.push(c);
-                self.getc();
-            } else {
-                break;
+        loop {
+            match self.lookahead() {
+                Some(c) if in_ident(c) => {
+                    word.push(c);
+                    self.getc();
+                },
+
+                // Qualified names like pkg::name are a single word
+                Some(':') => {
+                    let mut ahead = self.input.clone();
+                    ahead.next();
+                    let qualified = ahead.next() == Some(':')
+                        && ahead.next().is_some_and(char::is_alphabetic);
+
+                    if !qualified { break; }
+
+                    word.push_str("::");
+                    self.getc();
+                    self.getc();
+                },
+
+                _ => break,
             }
         }
 
         self.strings.intern(word)
     }
 
-    fn interp(&mut self) -> Result<Token> {
+    /// Read interpolated text up to `close`, or to the end of input if
+    /// there is none.
+    fn interp(&mut self, close: Option<char>) -> Result<Vec<Interp>> {
         let err = || Error::MalformedString;
 
         let mut items = Vec::new();
 
         while let Some(ch) = self.getc() {
-            match ch {
-                '"' => return Ok(Token::STR(items)),
+            if Some(ch) == close {
+                return Ok(items);
+            }
 
+            match ch {
                 '$' => {
                     let c = self.lookahead().ok_or(err())?;
 
-                    if c.is_digit(10) {
+                    if c.is_ascii_digit() {
                         let mut digits = String::new();
                         while let Some(c) = self.lookahead() {
-                            if c.is_digit(10) {
+                            if c.is_ascii_digit() {
                                 digits.push(c);
                                 self.getc();
                             } else {
@@ -202,21 +347,28 @@ impl<'a> Tokenizer<'a> {
                         items.push(Interp::C(num));
                     } else {
                         let word = self.word().unwrap_or(Err(err()))?;
-                        items.push(Interp::V(word));
+                        let path = self.access_path()?;
+                        items.push(Interp::V(word, path));
                     }
                 },
 
                 '%' => {
                     let word = self.word().unwrap_or(Err(err()))?;
-                    items.push(Interp::G(word));
+                    let path = self.access_path()?;
+                    items.push(Interp::G(word, path));
                 },
 
                 other => {
                     let mut s = String::new();
-                    s.push(other);
+
+                    if other == '\\' {
+                        s.push(self.unescape()?);
+                    } else {
+                        s.push(other);
+                    }
 
                     while let Some(c) = self.lookahead() {
-                        if "$%\"".contains(c) { break; }
+                        if c == '$' || c == '%' || Some(c) == close { break; }
 
                         self.getc();
 
@@ -232,22 +384,378 @@ impl<'a> Tokenizer<'a> {
             }
         }
 
-        Err(err())
+        match close {
+            Some(_) => Err(err()),
+            None => Ok(items),
+        }
+    }
+
+    /// Read the marker after `<<`, and the body from the lines following
+    /// this one up to a line holding only the marker. A quoted marker
+    /// makes the body raw text.
+    fn heredoc(&mut self) -> Result<Vec<Interp>> {
+        let raw = self.lookahead() == Some('\'');
+        if raw {
+            self.getc();
+        }
+
+        let mut marker = String::new();
+        while let Some(c) = self.lookahead() {
+            if !in_ident(c) { break; }
+            marker.push(c);
+            self.getc();
+        }
+
+        if marker.is_empty() || raw && self.getc() != Some('\'') {
+            return Err(Error::MalformedString);
+        }
+
+        // Later heredocs on the same line follow the earlier ones
+        let begin = match self.heredoc {
+            Some((_, end)) => end,
+            None => match self.src[self.right..].find('\n') {
+                Some(i) => self.right + i + 1,
+                None => self.src.len(),
+            },
+        };
+
+        let mut end = begin;
+        let body = loop {
+            if end >= self.src.len() {
+                return Err(Error::UnterminatedHeredoc { marker });
+            }
+
+            let line = &self.src[end..];
+            let line = &line[.. line.find('\n').map_or(line.len(), |i| i + 1)];
+
+            if line.trim() == marker {
+                let body = &self.src[begin .. end];
+                end += line.len();
+                break body;
+            }
+
+            end += line.len();
+        };
+
+        let items = if raw {
+            if body.is_empty() {
+                vec![]
+            } else {
+                vec![Interp::S(self.strings.intern(body)?)]
+            }
+        } else {
+            let mut inner = Tokenizer::with_strings(body, &self.strings);
+
+            let items = inner.interp(None);
+            if items.is_err() {
+                self.left = begin + inner.left;
+            }
+
+            items?
+        };
+
+        let newline = self.heredoc.map_or(begin - 1, |(newline, _)| newline);
+        self.heredoc = Some((newline, end));
+
+        Ok(items)
+    }
+
+    /// Indexes and fields following a variable in a string. A `.` or `[`
+    /// that can't start one is left as text.
+    fn access_path(&mut self) -> Result<Vec<Access>> {
+        let err = || Error::MalformedString;
+
+        let mut path = vec![];
+
+        loop {
+            let mut ahead = self.input.clone();
+
+            match (ahead.next(), ahead.next()) {
+                (Some('.'), Some(c)) if c.is_alphabetic() => {
+                    self.getc();
+                    let word = self.word().unwrap_or(Err(err()))?;
+                    path.push(Access::Field(word));
+                },
+
+                (Some('['), Some('$')) => {
+                    self.getc();
+                    self.getc();
+                    let word = self.word().unwrap_or(Err(err()))?;
+                    path.push(Access::Local(word));
+
+                    if self.getc() != Some(']') {
+                        return Err(err());
+                    }
+                },
+
+                (Some('['), Some(c)) if c.is_ascii_digit() => {
+                    self.getc();
+
+                    let mut digits = String::new();
+                    while let Some(c) = self.getc() {
+                        match c {
+                            ']' => break,
+                            c if c.is_ascii_digit() => digits.push(c),
+                            _ => return Err(err()),
+                        }
+                    }
+
+                    let index = digits.parse::<Int>().map_err(|_| err())?;
+                    path.push(Access::Index(index));
+                },
+
+                _ => return Ok(path),
+            }
+        }
+    }
+
+    /// Skip a `# line` or `#[[ block ]]` comment, keeping the text of a
+    /// `## doc` line for the next `sub`.
+    fn comment(&mut self) -> Result<()> {
+        let begin = self.right;
+        self.getc();
+
+        let rest = &self.src[self.right ..];
+        let rest = &rest[.. rest.find('\n').unwrap_or(rest.len())];
+
+        if rest.starts_with("[[") {
+            let end = match self.src[begin ..].find("]]") {
+                Some(i) => begin + i + 2,
+                None => {
+                    self.left = begin;
+                    return Err(Error::UnterminatedComment);
+                },
+            };
+
+            while self.right < end {
+                self.getc();
+            }
+
+            return Ok(());
+        }
+
+        if rest.starts_with('#') && !rest.starts_with("##") {
+            let text = &rest[1 ..];
+            self.doc.push(text.strip_prefix(' ').unwrap_or(text).to_owned());
+        }
+
+        // The newline is left to end a statement
+        while let Some(c) = self.lookahead() {
+            if c == '\n' { break; }
+            self.getc();
+        }
+
+        Ok(())
+    }
+
+    /// Whether a newline just read ends a statement. It does when the
+    /// line could end one, outside parentheses, brackets, and records,
+    /// and the next line doesn't continue it: a line starting with an
+    /// infix operator, `.`, `,`, `{`, `else`, `elsif`, or `catch`
+    /// continues the line before.
+    fn ends_statement(&self) -> bool {
+        if !self.newlines || !self.complete {
+            return false;
+        }
+
+        match self.brackets.last() {
+            None | Some(&Bracket::Block) => (),
+            Some(_) => return false,
+        }
+
+        let mut rest = &self.src[self.right ..];
+
+        loop {
+            rest = rest.trim_start();
+
+            if rest.starts_with("#[[") {
+                match rest.find("]]") {
+                    Some(i) => rest = &rest[i + 2 ..],
+                    None => return true,
+                }
+            } else if rest.starts_with('#') {
+                rest = &rest[rest.find('\n').unwrap_or(rest.len()) ..];
+            } else {
+                break;
+            }
+        }
+
+        let word = &rest[.. rest.find(|c| !in_ident(c)).unwrap_or(rest.len())];
+
+        match word {
+            "" => (),
+            "and" | "or" | "eq" | "ne" | "lt" | "gt" | "le" | "ge" | "cmp" |
+            "else" | "elsif" | "catch" | "in" => {
+                return false;
+            },
+            _ => return true,
+        }
+
+        match rest.chars().next() {
+            // A global starts a new statement; anything else is modulo
+            Some('%') => rest[1 ..].starts_with(in_ident),
+            Some(c) => !"+-*/.=<>,;{".contains(c),
+            None => true,
+        }
+    }
+
+    /// Whether a `}` coming next closes a block whose last statement has
+    /// no semicolon, as in `{ return $x }`.
+    fn ends_block(&mut self) -> bool {
+        self.newlines
+            && self.complete
+            && self.lookahead() == Some('}')
+            && self.brackets.last() == Some(&Bracket::Block)
+    }
+
+    /// Track brackets, and whether a statement could end, after `token`.
+    fn note(&mut self, token: &Token) {
+        let mut closed = None;
+
+        match *token {
+            Token::LPAR => self.brackets.push(Bracket::Paren),
+            Token::LSQB => self.brackets.push(Bracket::Square),
+
+            Token::LCBR => if self.block_next {
+                self.brackets.push(Bracket::Block);
+                self.when = None;
+            } else {
+                self.brackets.push(Bracket::Record);
+            },
+
+            Token::WHEN => self.when = Some(self.brackets.len()),
+
+            Token::RPAR | Token::RSQB | Token::RCBR => {
+                closed = self.brackets.pop();
+            },
+
+            _ => (),
+        }
+
+        self.complete = match *token {
+            Token::VAR(_) | Token::GLOBAL(_) | Token::GROUP(_) |
+            Token::INT(_) | Token::FLOAT(_) | Token::STR(_) |
+            Token::SYM(_) | Token::PAT(_) | Token::FARWORD(_) |
+            Token::TRUE | Token::FALSE | Token::RETURN |
+            Token::RPAR | Token::RSQB => true,
+
+            // The end of a block already ends its statement
+            Token::RCBR => closed == Some(Bracket::Record),
+
+            _ => false,
+        };
+
+        self.returned = *token == Token::RETURN;
+
+        self.farword = matches!(*token, Token::FARWORD(_));
+
+        // A far word before `{` is a call with a record argument, unless
+        // it names a type in a `when`
+        self.block_next = match *token {
+            Token::FARWORD(_) => self.when == Some(self.brackets.len()),
+            Token::RETURN => false,
+            Token::ELSE | Token::TRY | Token::RCBR => true,
+            _ => self.complete,
+        };
+    }
+
+    fn take_doc(&mut self) -> Option<Str> {
+        if self.doc.is_empty() {
+            return None;
+        }
+
+        Some(self.doc.join("\n").into())
     }
 
+    /// Read an escape after its backslash. Errors point at the backslash.
     fn unescape(&mut self) -> Result<char> {
+        let start = self.left;
+
+        let result = self.escaped();
+        if result.is_err() {
+            self.left = start;
+        }
+
+        result
+    }
+
+    fn escaped(&mut self) -> Result<char> {
         Ok(match self.getc().ok_or(Error::MalformedString)? {
             '$' => '$',
             '%' => '%',
             '"' => '"',
+            // So text right after a variable isn't read as an index
+            '.' => '.',
+            '[' => '[',
             'n' => '\n',
             'r' => '\r',
             't' => '\t',
             '\\' => '\\',
+            '0' => '\0',
+
+            // Two hex digits, ASCII only
+            'x' => {
+                let code = self.hex_digits(2, 2)?;
+
+                if code > 0x7f {
+                    return Err(Error::InvalidCodepoint { code });
+                }
+
+                code as u8 as char
+            },
+
+            // One to six hex digits in braces
+            'u' => {
+                if self.getc() != Some('{') {
+                    return Err(Error::InvalidEscape);
+                }
+
+                let code = self.hex_digits(1, 6)?;
+
+                if self.getc() != Some('}') {
+                    return Err(Error::InvalidEscape);
+                }
+
+                // Surrogates and anything past U+10FFFF
+                ::std::char::from_u32(code)
+                    .ok_or(Error::InvalidCodepoint { code })?
+            },
+
             _ => return Err(Error::InvalidEscape),
         })
     }
 
+    fn hex_digits(&mut self, min: usize, max: usize) -> Result<u32> {
+        let mut code = 0;
+        let mut len = 0;
+
+        while len < max {
+            let digit = match self.lookahead().and_then(|c| c.to_digit(16)) {
+                Some(digit) => digit,
+                None => break,
+            };
+
+            self.getc();
+            code = code * 16 + digit;
+            len += 1;
+        }
+
+        if len < min {
+            return Err(Error::InvalidEscape);
+        }
+
+        Ok(code)
+    }
+
+    fn assign_op(&mut self, op: Token, update: Token) -> Token {
+        if let Some('=') = self.lookahead() {
+            self.getc();
+            update
+        } else {
+            op
+        }
+    }
+
     fn token(&mut self, first: char) -> Result<Token> {
         Ok(match first {
             '(' => Token::LPAR,
@@ -259,12 +767,22 @@ impl<'a> Tokenizer<'a> {
 
             ',' => Token::COMMA,
             ';' => Token::EOL,
-            '.' => Token::DOT,
+            '.' => if let Some('.') = self.lookahead() {
+                self.getc();
+                Token::RANGE
+            } else {
+                Token::DOT
+            },
 
-            '+' => Token::ADD,
-            '-' => Token::SUB,
-            '/' => Token::DIV,
-            '*' => Token::MUL,
+            '+' => self.assign_op(Token::ADD, Token::ADDEQ),
+            '-' => self.assign_op(Token::SUB, Token::SUBEQ),
+            '/' => if let Some('/') = self.lookahead() {
+                self.getc();
+                Token::COALESCE
+            } else {
+                self.assign_op(Token::DIV, Token::DIVEQ)
+            },
+            '*' => self.assign_op(Token::MUL, Token::MULEQ),
 
             '=' => if let Some('~') = self.lookahead() {
                 self.getc();
@@ -273,7 +791,28 @@ impl<'a> Tokenizer<'a> {
                 Token::EQUAL
             },
 
-            '"' => self.interp()?,
+            '<' => match self.lookahead() {
+                Some('=') => {
+                    self.getc();
+                    Token::LE
+                },
+
+                Some('<') => {
+                    self.getc();
+                    Token::STR(self.heredoc()?)
+                },
+
+                _ => Token::LT,
+            },
+
+            '>' => if let Some('=') = self.lookahead() {
+                self.getc();
+                Token::GE
+            } else {
+                Token::GT
+            },
+
+            '"' => Token::STR(self.interp(Some('"'))?),
 
             ':' => match self.lookahead() {
                 Some(w) if w.is_alphabetic() => {
@@ -288,7 +827,7 @@ impl<'a> Tokenizer<'a> {
                 let mut word = String::new();
                 word.push(w);
 
-                if w.is_digit(10) {
+                if w.is_ascii_digit() {
                     Token::GROUP(word.parse::<u8>().unwrap())
                 } else if w.is_alphabetic() {
                     Token::VAR(self.endword(w)?)
@@ -297,26 +836,63 @@ impl<'a> Tokenizer<'a> {
                 }
             },
 
-            '%' => {
-                Token::GLOBAL(self.word().ok_or(Error::Eof)??)
+            '@' => match self.lookahead() {
+                Some(w) if w.is_alphabetic() => {
+                    Token::REST(self.word().ok_or(Error::Eof)??)
+                },
+
+                _ => return Err(Error::UnimplementedToken { ch: '@' }),
+            },
+
+            // After an operand % means modulo; anywhere else it starts a
+            // global, as it does when glued to the argument of `say %x` or
+            // `return %x`
+            '%' => match self.lookahead() {
+                Some(c) if (self.farword || self.returned) && in_ident(c) => {
+                    Token::GLOBAL(self.word().ok_or(Error::Eof)??)
+                },
+
+                _ if self.complete => self.assign_op(Token::MOD, Token::MODEQ),
+
+                _ => Token::GLOBAL(self.word().ok_or(Error::Eof)??),
             },
 
             w if w.is_alphabetic() => {
                 let word = self.endword(w)?;
 
                 match word.as_ref() {
-                    "sub" => Token::DEF,
+                    "sub" => Token::DEF(self.take_doc()),
                     "my" => Token::LET,
                     "if" => Token::IF,
                     "else" => Token::ELSE,
+                    "elsif" => Token::ELSIF,
+                    "unless" => Token::UNLESS,
+                    "until" => Token::UNTIL,
+                    "given" => Token::GIVEN,
+                    "when" => Token::WHEN,
                     "while" => Token::WHILE,
+                    "package" => Token::PACKAGE,
+                    "import" => Token::IMPORT,
+                    "for" => Token::FOR,
+                    "foreach" => Token::FOREACH,
+                    "in" => Token::IN,
                     "return" => Token::RETURN,
                     "assert" => Token::ASSERT,
+                    "try" => Token::TRY,
+                    "catch" => Token::CATCH,
+                    "throw" => Token::THROW,
                     "not" => Token::NOT,
                     "eq" => Token::EQ,
                     "ne" => Token::NE,
+                    "lt" => Token::STRLT,
+                    "gt" => Token::STRGT,
+                    "le" => Token::STRLE,
+                    "ge" => Token::STRGE,
+                    "cmp" => Token::CMP,
                     "and" => Token::AND,
                     "or" => Token::OR,
+                    "true" => Token::TRUE,
+                    "false" => Token::FALSE,
 
                     "re" => return pattern::Ast::parse(self).map(|pat| {
                         Token::PAT(pat)
@@ -332,16 +908,36 @@ impl<'a> Tokenizer<'a> {
                 }
             },
 
-            d if d.is_digit(10) => {
+            d if d.is_ascii_digit() => {
                 let mut digits = String::new();
                 digits.push(d);
                 while let Some(d) = self.lookahead() {
-                    if !d.is_digit(10) { break; }
+                    if !d.is_ascii_digit() { break; }
                     digits.push(d);
                     self.getc();
                 }
 
-                Token::INT(digits.parse::<Int>().unwrap())
+                // A dot only continues the number if a digit follows it,
+                // so that ranges like 1..10 still tokenize
+                let mut ahead = self.input.clone();
+                let fraction = ahead.next() == Some('.')
+                    && ahead.next().is_some_and(|d| d.is_ascii_digit());
+
+                if fraction {
+                    digits.push('.');
+                    self.getc();
+
+                    while let Some(d) = self.lookahead() {
+                        if !d.is_ascii_digit() { break; }
+                        digits.push(d);
+                        self.getc();
+                    }
+
+                    return Ok(Token::FLOAT(digits.parse::<Float>().unwrap()));
+                }
+
+                Token::INT(digits.parse::<Int>()
+                    .map_err(|_| Error::IntegerOverflow)?)
             },
 
             other => {
@@ -355,25 +951,55 @@ impl<'a> Iterator for Tokenizer<'a> {
     type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.start < self.right {
+            self.doc.clear();
+        }
+
         while let Some(s) = self.lookahead() {
             if s == '#' {
-                while self.getc()? != '\n' {
-                    continue;
+                if let Err(err) = self.comment() {
+                    return Some(Err(err));
                 }
             } else if s.is_whitespace() {
                 self.getc();
-                continue;
+
+                if s == '\n' && self.ends_statement() {
+                    self.start = self.left;
+                    self.note(&Token::EOL);
+                    return Some(Ok(Token::EOL));
+                }
             } else {
                 break;
             }
         }
 
-        self.getc().map(|first| self.token(first))
+        if self.ends_block() {
+            self.start = self.right;
+            self.note(&Token::EOL);
+            return Some(Ok(Token::EOL));
+        }
+
+        self.start = self.right;
+
+        let token = match self.getc() {
+            Some(first) => self.token(first),
+
+            // The last line may leave off its newline
+            None if self.ends_statement() => Ok(Token::EOL),
+
+            None => return None,
+        };
+
+        if let Ok(ref token) = token {
+            self.note(token);
+        }
+
+        Some(token)
     }
 }
 
 fn in_ident(c: char) -> bool {
-    c.is_alphabetic() || c.is_digit(10) || c == '_'
+    c.is_alphabetic() || c.is_ascii_digit() || c == '_'
 }
 
 use std::fmt;
@@ -381,36 +1007,70 @@ use std::fmt;
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Token::DEF => write!(f, "sub"),
+            Token::DEF(_) => write!(f, "sub"),
             Token::LET => write!(f, "my"),
             Token::IF => write!(f, "if"),
             Token::ELSE => write!(f, "else"),
+            Token::ELSIF => write!(f, "elsif"),
+            Token::UNLESS => write!(f, "unless"),
+            Token::UNTIL => write!(f, "until"),
+            Token::GIVEN => write!(f, "given"),
+            Token::WHEN => write!(f, "when"),
             Token::WHILE => write!(f, "while"),
+            Token::PACKAGE => write!(f, "package"),
+            Token::IMPORT => write!(f, "import"),
+            Token::FOR => write!(f, "for"),
+            Token::FOREACH => write!(f, "foreach"),
+            Token::IN => write!(f, "in"),
             Token::RETURN => write!(f, "return"),
             Token::ASSERT => write!(f, "assert"),
+            Token::TRY => write!(f, "try"),
+            Token::CATCH => write!(f, "catch"),
+            Token::THROW => write!(f, "throw"),
             Token::NOT => write!(f, "not"),
             Token::EQ => write!(f, "eq"),
             Token::NE => write!(f, "ne"),
+            Token::STRLT => write!(f, "lt"),
+            Token::STRGT => write!(f, "gt"),
+            Token::STRLE => write!(f, "le"),
+            Token::STRGE => write!(f, "ge"),
+            Token::CMP => write!(f, "cmp"),
+            Token::LT => write!(f, "<"),
+            Token::GT => write!(f, ">"),
+            Token::LE => write!(f, "<="),
+            Token::GE => write!(f, ">="),
             Token::AND => write!(f, "and"),
             Token::OR => write!(f, "or"),
+            Token::TRUE => write!(f, "true"),
+            Token::FALSE => write!(f, "false"),
             Token::EOL => write!(f, ";"),
             Token::DOT => write!(f, "."),
+            Token::RANGE => write!(f, ".."),
             Token::COMMA => write!(f, ","),
             Token::COLON => write!(f, ":"),
             Token::EQUAL => write!(f, "="),
             Token::MATCH => write!(f, "=~"),
+            Token::COALESCE => write!(f, "//"),
             Token::ADD => write!(f, "+"),
             Token::SUB => write!(f, "-"),
             Token::DIV => write!(f, "/"),
             Token::MUL => write!(f, "*"),
+            Token::MOD => write!(f, "%"),
+            Token::ADDEQ => write!(f, "+="),
+            Token::SUBEQ => write!(f, "-="),
+            Token::MULEQ => write!(f, "*="),
+            Token::DIVEQ => write!(f, "/="),
+            Token::MODEQ => write!(f, "%="),
             Token::NEARWORD(ref id) => write!(f, "{}", id),
             Token::FARWORD(ref id) => write!(f, "{}", id),
             Token::GLOBAL(ref id) => write!(f, "%{}", id),
             Token::GROUP(num) => write!(f, "${}", num),
             Token::VAR(ref id) => write!(f, "${}", id),
+            Token::REST(ref id) => write!(f, "@{}", id),
             Token::SYM(ref id) => write!(f, ":{}", id),
             Token::STR(ref s) => write!(f, "{:?}", s),
             Token::INT(i) => write!(f, "{}", i),
+            Token::FLOAT(x) => write!(f, "{:?}", x),
             Token::PAT(ref p) => write!(f, "{}", p),
             Token::LPAR => write!(f, "("),
             Token::RPAR => write!(f, ")"),
@@ -427,6 +1087,7 @@ fn various() {
     let sources = &[
         "$foo $1 $9 $0 $ok_what_now $UPPERCASE",
         "1 23 456 7890",
+        "1.5 0.25 1..10",
         " spaces # Comment at end\n",
     ];
 
@@ -442,13 +1103,13 @@ fn various() {
 #[test]
 fn syntax() {
     let src = "sub foo() { return $bar; }";
-    let mut t = Tokenizer::new(src);
+    let t = Tokenizer::new(src);
     let foo = t.strings.intern("foo").unwrap();
     let bar = t.strings.intern("bar").unwrap();
 
     let items = t.collect::<Result<Vec<_>, _>>().unwrap();
     assert_eq!(&items, &[
-               Token::DEF,
+               Token::DEF(None),
                Token::NEARWORD(foo),
                Token::LPAR,
                Token::RPAR,
@@ -460,12 +1121,31 @@ fn syntax() {
     ]);
 }
 
+#[test]
+fn modulo() {
+    let src = "%n % %m %= 2";
+    let t = Tokenizer::new(src);
+    let n = t.strings.intern("n").unwrap();
+    let m = t.strings.intern("m").unwrap();
+
+    let items = t.collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(&items, &[
+               Token::GLOBAL(n),
+               Token::MOD,
+               Token::GLOBAL(m),
+               Token::MODEQ,
+               Token::INT(2),
+    ]);
+}
+
 #[test]
 fn string() {
     let strings = &[
         r#" "let's go" "#,
         r#" "okay $friend" "#,
         r#" "hello\nworld" "#,
+        r#" "\%d $x\n" "#,
+        r#" "$a: $b::c" "#,
     ];
 
     for string in strings {
@@ -490,3 +1170,181 @@ fn pattern() {
         assert_eq!(tokens.len(), 1);
     }
 }
+
+#[test]
+fn escapes() {
+    let decode = |src: &str| match Tokenizer::new(src).next() {
+        Some(Ok(Token::STR(ref items))) => match items[..] {
+            [Interp::S(ref s)] => Ok(s.to_string()),
+            _ => panic!("not a literal: {}", src),
+        },
+
+        Some(Err(err)) => Err(err),
+        other => panic!("not a string: {:?}", other),
+    };
+
+    assert_eq!(decode(r#""\x41\x7e\0""#).unwrap(), "A~\0");
+    assert_eq!(decode(r#""\u{e9}\u{1F600}\u{10FFFF}""#).unwrap(),
+        "\u{e9}\u{1F600}\u{10FFFF}");
+
+    let bad = &[
+        r#""\x4""#,
+        r#""\xzz""#,
+        r#""\u41""#,
+        r#""\u{}""#,
+        r#""\u{1234567}""#,
+        r#""\u{41""#,
+        r#""\q""#,
+    ];
+
+    for src in bad {
+        match decode(src) {
+            Err(Error::InvalidEscape) => (),
+            other => panic!("{}: {:?}", src, other),
+        }
+    }
+
+    let codepoints = &[
+        (r#""\x80""#, 0x80),
+        (r#""\u{D800}""#, 0xd800),
+        (r#""\u{dfff}""#, 0xdfff),
+        (r#""\u{110000}""#, 0x110000),
+    ];
+
+    for &(src, expected) in codepoints {
+        match decode(src) {
+            Err(Error::InvalidCodepoint { code }) => assert_eq!(code, expected),
+            other => panic!("{}: {:?}", src, other),
+        }
+    }
+
+    // Positions point at the backslash
+    let src = "my $s = 1;\nmy $t = \"ok \\u{d800}\";";
+    let err = Tokenizer::new(src).spanned()
+        .find(|t| t.is_err())
+        .unwrap()
+        .unwrap_err();
+
+    match err {
+        Error::WithPosition { line, column, .. } => {
+            assert_eq!((line, column), (2, 13));
+        },
+
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn heredoc() {
+    let src = "f(<<A, <<'B'); # two\n  $x\\t\"q\"\nA\n  $x\\t\n  B\nreturn;";
+
+    let tokens = Tokenizer::new(src).collect::<Result<Vec<_>>>().unwrap();
+    let strings: Vec<_> = tokens.iter().filter_map(|t| match *t {
+        Token::STR(ref items) => Some(items.clone()),
+        _ => None,
+    }).collect();
+
+    assert_eq!(tokens.len(), 9);
+    assert_eq!(tokens[7], Token::RETURN);
+
+    match (&strings[0][..], &strings[1][..]) {
+        (&[Interp::S(ref indent), Interp::V(ref x, _), Interp::S(ref rest)],
+         &[Interp::S(ref raw)]) => {
+            assert_eq!((indent.as_ref(), x.as_ref()), ("  ", "x"));
+            assert_eq!(rest.as_ref(), "\t\"q\"\n");
+            assert_eq!(raw.as_ref(), "  $x\\t\n");
+        },
+
+        other => panic!("{:?}", other),
+    }
+
+    for src in &["<<END\nno end\n", "<<END", "<<'END\nEND\n", "<< END\nEND\n"] {
+        assert!(Tokenizer::new(src).collect::<Result<Vec<_>>>().is_err(), "{}", src);
+    }
+}
+
+#[test]
+fn comments() {
+    let src = "
+        #[[ a block
+            comment ]] my $x = 1; #[[]]
+        ## Adds one.
+        ##
+        #   (not part of the doc)
+        ##   Indented
+        sub inc() {}
+        ## Dropped, since a statement follows
+        ###############
+        $x; sub bare() {}
+    ";
+
+    let tokens = Tokenizer::new(src).collect::<Result<Vec<_>>>().unwrap();
+    let docs: Vec<_> = tokens.iter().filter_map(|t| match *t {
+        Token::DEF(ref doc) => Some(doc.clone()),
+        _ => None,
+    }).collect();
+
+    assert_eq!(tokens[0], Token::LET);
+    assert_eq!(docs, vec![Some("Adds one.\n\n  Indented".into()), None]);
+
+    let err = Tokenizer::new("my $x;\n  #[[ open").spanned()
+        .find(|t| t.is_err())
+        .unwrap()
+        .unwrap_err();
+
+    match err {
+        Error::WithPosition { line, column, cause } => {
+            assert_eq!((line, column), (2, 3));
+            assert_eq!(cause.kind(), "UnterminatedComment");
+        },
+
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn shebang() {
+    let src = "#!/usr/bin/env canary --trace [[\nmy $x;";
+    let tokens = Tokenizer::new(src).spanned().collect::<Result<Vec<_>>>().unwrap();
+
+    assert_eq!(tokens[0], (33, Token::LET, 35));
+    assert_eq!(tokens.len(), 3);
+
+    // Only on the first line
+    assert!(Tokenizer::new(" #!x\n").collect::<Result<Vec<_>>>().unwrap().is_empty());
+    assert!(Tokenizer::new("1;\n#!x [[").collect::<Result<Vec<_>>>().is_ok());
+}
+
+#[test]
+fn newlines() {
+    let eols = |src: &str| Tokenizer::new(src)
+        .newlines(true)
+        .collect::<Result<Vec<_>>>()
+        .unwrap()
+        .iter()
+        .filter(|t| **t == Token::EOL)
+        .count();
+
+    assert_eq!(eols("my $x = 1\nprint $x\n"), 2);
+    assert_eq!(eols("my $x = 1;\nprint $x;\n"), 2);
+    assert_eq!(eols("f(1,\n2)\n[\n1\n]"), 2);
+    assert_eq!(eols("my $r = {\na: 1\n}\n%g = 1"), 2);
+    assert_eq!(eols("$a\n  # note\n  + $b\n  % 2"), 1);
+    assert_eq!(eols("if $a {\nb()\n}\nelse {\n}\n"), 1);
+    assert_eq!(eols("sub f()\n{\nreturn\n}\nsub g() {}"), 1);
+    assert_eq!(eols("print\n{a: 1}"), 1);
+
+    // A bare `return` ends at the newline like any other complete line
+    assert_eq!(eols("sub f() {\nreturn\nprint(\"x\")\n}"), 2);
+
+    // The end of a block ends the statement before it
+    assert_eq!(eols("sub f($s) { return uc($s) }"), 1);
+    assert_eq!(eols("if $a { b(); }\n"), 1);
+    assert_eq!(eols("my $r = {a: $x}"), 1);
+
+    // Semicolons are required unless newlines are turned on
+    let strict = Tokenizer::new("my $x = 1\nprint $x")
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert!(!strict.contains(&Token::EOL));
+}
@@ -1,7 +1,11 @@
-use std::sync::Arc;
-use std::cell::RefCell;
+use std::any::Any;
+use std::convert::TryFrom;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::cmp::Ordering;
 use std::collections::{HashMap, VecDeque};
-use std::ops::{Add, Sub, Div, Mul};
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Sub, Div, Mul, Rem};
 
 use super::*;
 
@@ -9,10 +13,155 @@ use ident::*;
 use pattern::*;
 
 pub type Nil = ();
-pub type Int = i32;
+pub type Bool = bool;
+pub type Int = i64;
+pub type Float = f64;
+/// An integer too wide for an Int. Arithmetic only makes one when a result
+/// doesn't fit, so a BigInt never holds a value an Int could.
+#[cfg(feature = "bigint")]
+pub type BigInt = Arc<num_bigint::BigInt>;
 pub type Str = Arc<str>;
-pub type List = Arc<RefCell<VecDeque<Value>>>;
-pub type Record = Arc<RefCell<HashMap<Ident, Value>>>;
+pub type List = Arc<Shared<VecDeque<Value>>>;
+pub type Record = Arc<Shared<HashMap<Str, Value>>>;
+
+/// The mutable inside of a List or Record. It works like a `RefCell`, but
+/// can be shared between threads: `borrow` and `borrow_mut` wait for
+/// another thread to finish rather than panicking.
+pub struct Shared<T>(RwLock<T>);
+
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        gc::note_alloc();
+        Shared(RwLock::new(value))
+    }
+
+    // A panic can't leave a List or Record half-updated, so poisoning is
+    // ignored
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T: Default> Default for Shared<T> {
+    fn default() -> Self {
+        Shared::new(T::default())
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        gc::note_free();
+    }
+}
+
+impl<T> From<T> for Shared<T> {
+    fn from(value: T) -> Self {
+        Shared::new(value)
+    }
+}
+
+impl<T> PartialEq for Shared<T> {
+    /// The same container, not merely equal ones. `Value` compares
+    /// contents itself.
+    fn eq(&self, other: &Shared<T>) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl<T: Debug> Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.try_read() {
+            Ok(value) => value.fmt(f),
+            Err(_) => f.write_str("<locked>"),
+        }
+    }
+}
+
+/// A Rust value owned by the host, such as a file handle or a database
+/// connection. Scripts can store and pass it around but not look inside;
+/// natives get it back with `downcast`. Clones share the same object.
+#[derive(Clone)]
+pub struct Opaque {
+    object: Arc<dyn Any + Send + Sync>,
+    // The same object, if scripts can call its methods
+    host: Option<Arc<dyn HostObject>>,
+    type_name: &'static str,
+}
+
+/// A host object with methods, so `$obj.name(args)` in a script calls
+/// `call_method` with the method name and arguments.
+pub trait HostObject: Any + Send + Sync {
+    /// Unknown methods should fail with `Error::NoSuchMethod`.
+    fn call_method(&self, name: &Ident, args: Vec<Value>) -> Result<Value>;
+}
+
+impl Opaque {
+    pub fn new<T: Any + Send + Sync>(object: T) -> Self {
+        Opaque::from_arc(Arc::new(object))
+    }
+
+    pub fn from_arc<T: Any + Send + Sync>(object: Arc<T>) -> Self {
+        Opaque {
+            object,
+            host: None,
+            type_name: std::any::type_name::<T>(),
+        }
+    }
+
+    /// Like `new`, but scripts can call the object's methods.
+    pub fn host<T: HostObject>(object: T) -> Self {
+        let object = Arc::new(object);
+
+        Opaque {
+            host: Some(object.clone()),
+            object,
+            type_name: std::any::type_name::<T>(),
+        }
+    }
+
+    /// The object's methods, if it was made with `Opaque::host`.
+    pub fn methods(&self) -> Option<Arc<dyn HostObject>> {
+        self.host.clone()
+    }
+
+    /// The Rust type this was made from, for messages.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.object.is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.object.downcast_ref()
+    }
+
+    /// A shared handle to the object, or `TypeMismatch` if it is not a `T`.
+    pub fn downcast<T: Any + Send + Sync>(&self) -> Result<Arc<T>> {
+        self.object.clone().downcast().map_err(|_| Error::TypeMismatch {
+            expected: std::any::type_name::<T>(),
+            found: self.type_name,
+        })
+    }
+}
+
+impl PartialEq for Opaque {
+    /// The same object, not merely equal ones.
+    fn eq(&self, other: &Opaque) -> bool {
+        Arc::ptr_eq(&self.object, &other.object)
+    }
+}
+
+impl Debug for Opaque {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Opaque({})", self.type_name)
+    }
+}
 
 pub trait Extract: Sized {
     const TYPE_NAME: &'static str;
@@ -20,28 +169,155 @@ pub trait Extract: Sized {
     fn extract(value: Value) -> Result<Self>;
 }
 
+/// The parameters of a typed native, as a tuple of `Extract` types.
+pub trait FromValues: Sized {
+    const ARGC: usize;
+
+    /// Convert exactly `ARGC` arguments to a call of `func`.
+    fn from_values(args: Vec<Value>, func: &str) -> Result<Self>;
+}
+
+/// A Rust closure that `Module::def_fn` can call with `Args`.
+pub trait NativeFunction<Args>: Send + Sync + 'static {
+    fn call_with(&self, args: Args) -> Result<Value>;
+}
+
+/// Arguments for `Interpreter::call`: a tuple of values that convert to
+/// `Value`, a `Vec<Value>`, or an `Args`.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+/// Arguments of mixed types, built up one at a time.
+#[derive(Clone, Debug, Default)]
+pub struct Args(Vec<Value>);
+
+impl Args {
+    pub fn new() -> Self {
+        Args(vec![])
+    }
+
+    pub fn arg<V: Into<Value>>(mut self, value: V) -> Self {
+        self.0.push(value.into());
+        self
+    }
+}
+
+impl IntoArgs for Args {
+    fn into_args(self) -> Vec<Value> {
+        self.0
+    }
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+macro_rules! impl_native_args {
+    ( $len:expr; $( $arg:ident ),* ) => {
+        impl<$( $arg: Into<Value> ),*> IntoArgs for ( $( $arg, )* ) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ( $( $arg, )* ) = self;
+                vec![ $( $arg.into() ),* ]
+            }
+        }
+
+        impl<$( $arg: Extract ),*> FromValues for ( $( $arg, )* ) {
+            const ARGC: usize = $len;
+
+            #[allow(unused_variables, unused_mut)]
+            fn from_values(args: Vec<Value>, func: &str) -> Result<Self> {
+                let mut args = args.into_iter().enumerate();
+
+                Ok(( $( {
+                    let (index, arg) = args.next()
+                        .ok_or(Error::StackUnderflow)?;
+
+                    $arg::extract(arg).map_err(|err| {
+                        err.decorate(format!("argument {} of {}", index + 1, func))
+                    })?
+                }, )* ))
+            }
+        }
+
+        impl<F, R, $( $arg ),*> NativeFunction<( $( $arg, )* )> for F
+            where F: Fn( $( $arg ),* ) -> Result<R> + Send + Sync + 'static,
+                  R: Into<Value>
+        {
+            #[allow(non_snake_case)]
+            fn call_with(&self, ( $( $arg, )* ): ( $( $arg, )* )) -> Result<Value> {
+                self( $( $arg ),* ).map(Into::into)
+            }
+        }
+    }
+}
+
+impl_native_args!(0; );
+impl_native_args!(1; A);
+impl_native_args!(2; A, B);
+impl_native_args!(3; A, B, C);
+impl_native_args!(4; A, B, C, D);
+impl_native_args!(5; A, B, C, D, E);
+impl_native_args!(6; A, B, C, D, E, G);
+
 macro_rules! impl_value {
-    ( $( $type:ident ),* ) => {
-        #[derive(Clone, Debug, Eq, PartialEq)]
+    ( $( $( #[$attr:meta] )* $type:ident ),* ) => {
+        #[derive(Clone, Debug)]
         pub enum Value {
-            $( $type($type), )*
+            $( $( #[$attr] )* $type($type), )*
         }
 
         impl Value {
+            pub const TYPE_NAMES: &'static [&'static str] = &[
+                $( $( #[$attr] )* stringify!($type), )*
+            ];
+
             pub fn type_name(&self) -> &'static str {
                 match *self {
-                    $( Value::$type(_) => stringify!($type), )*
+                    $( $( #[$attr] )* Value::$type(_) => stringify!($type), )*
+                }
+            }
+
+            /// Whether both are the same List, Record or Opaque, or equal
+            /// values of any other type.
+            pub fn same(&self, other: &Value) -> bool {
+                match (self, other) {
+                    $(
+                        $( #[$attr] )*
+                        (Value::$type(lhs), Value::$type(rhs)) => lhs == rhs,
+                    )*
+                    _ => false,
                 }
             }
         }
 
         $(
+            $( #[$attr] )*
             impl From<$type> for Value {
                 fn from(t: $type) -> Self {
                     Value::$type(t)
                 }
             }
 
+            $( #[$attr] )*
+            impl TryFrom<Value> for $type {
+                type Error = Error;
+
+                fn try_from(value: Value) -> Result<Self> {
+                    Self::extract(value)
+                }
+            }
+        )*
+    }
+}
+
+macro_rules! impl_extract {
+    ( $( $( #[$attr:meta] )* $type:ident ),* ) => {
+        $(
+            $( #[$attr] )*
             impl Extract for $type {
                 const TYPE_NAME: &'static str = stringify!($type);
 
@@ -60,43 +336,366 @@ macro_rules! impl_value {
     }
 }
 
-impl_value!(Nil, Int, Str, List, Record, Pattern, Ident);
+impl_value!(Nil, Bool, Int, Float, Str, List, Record, Pattern, Ident, Opaque,
+    #[cfg(feature = "bigint")] BigInt);
+
+impl_extract!(Nil, Int, Float, Str, List, Record, Pattern, Ident, Opaque,
+    #[cfg(feature = "bigint")] BigInt);
+
+/// Anything converts to a Bool by its truthiness, so natives see their
+/// flags the way `if` would.
+impl Extract for Bool {
+    const TYPE_NAME: &'static str = "Bool";
+
+    fn extract(value: Value) -> Result<Self> {
+        Ok(value.truthy())
+    }
+}
+
+impl PartialEq for Value {
+    /// Lists and Records are equal when their contents are.
+    fn eq(&self, other: &Value) -> bool {
+        self.equal(other, &mut vec![])
+    }
+}
+
+impl Hash for Value {
+    /// Agrees with `==`. Only the first few levels of Lists and Records
+    /// are hashed, so cycles end.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_to(state, 4);
+    }
+}
+
+impl Value {
+    fn hash_to<H: Hasher>(&self, state: &mut H, depth: usize) {
+        self.rank().hash(state);
+        self.type_name().hash(state);
+
+        if depth == 0 {
+            return;
+        }
+
+        match *self {
+            Value::Nil(_) | Value::Pattern(_) => (),
+            Value::Bool(b) => b.hash(state),
+            Value::Int(i) => i.hash(state),
+            // Zero and negative zero are equal
+            Value::Float(f) => {
+                let f = if f == 0.0 { 0.0 } else { f };
+                f.to_bits().hash(state);
+            },
+            Value::Str(ref s) => s.hash(state),
+            Value::Ident(ref i) => i.as_ref().hash(state),
+            Value::Opaque(ref o) => {
+                (Arc::as_ptr(&o.object) as *const ()).hash(state);
+            },
+
+            #[cfg(feature = "bigint")]
+            Value::BigInt(ref n) => n.hash(state),
+
+            Value::List(ref list) => {
+                let list = list.borrow();
+                list.len().hash(state);
+                for item in list.iter() {
+                    item.hash_to(state, depth - 1);
+                }
+            },
+
+            Value::Record(ref rec) => {
+                let rec = rec.borrow();
+                let mut keys: Vec<&Str> = rec.keys().collect();
+                keys.sort();
+                for key in keys {
+                    key.hash(state);
+                    rec[key].hash_to(state, depth - 1);
+                }
+            },
+        }
+    }
+
+    // `seen` holds the pairs of containers being compared further up. A
+    // cycle that comes back to one of them can only agree with itself, so
+    // it is taken as equal.
+    fn equal(&self, other: &Value, seen: &mut Vec<(usize, usize)>) -> bool {
+        if self.same(other) {
+            return true;
+        }
+
+        let pair = match (self, other) {
+            (Value::List(_), Value::List(_)) |
+            (Value::Record(_), Value::Record(_)) => {
+                (address(self), address(other))
+            },
+
+            _ => return false,
+        };
+
+        if seen.contains(&pair) {
+            return true;
+        }
+
+        seen.push(pair);
+
+        let equal = match (self, other) {
+            (Value::List(lhs), Value::List(rhs)) => {
+                let (lhs, rhs) = (lhs.borrow(), rhs.borrow());
+                lhs.len() == rhs.len() && lhs.iter().zip(rhs.iter())
+                    .all(|(lhs, rhs)| lhs.equal(rhs, seen))
+            },
+
+            (Value::Record(lhs), Value::Record(rhs)) => {
+                let (lhs, rhs) = (lhs.borrow(), rhs.borrow());
+                lhs.len() == rhs.len() && lhs.iter().all(|(key, lhs)| {
+                    rhs.get(key).is_some_and(|rhs| lhs.equal(rhs, seen))
+                })
+            },
+
+            _ => false,
+        };
+
+        seen.pop();
+        equal
+    }
+}
+
+/// Where a List or Record is kept, to recognize it when it comes around
+/// again in a cycle.
+fn address(value: &Value) -> usize {
+    match *value {
+        Value::List(ref list) => Arc::as_ptr(list) as usize,
+        Value::Record(ref rec) => Arc::as_ptr(rec) as usize,
+        _ => 0,
+    }
+}
+
+/// Widen an Int or Float operand for mixed arithmetic.
+fn promote(value: Value) -> Result<Float> {
+    match value {
+        Value::Int(i) => Ok(i as Float),
+        Value::Float(f) => Ok(f),
+
+        #[cfg(feature = "bigint")]
+        Value::BigInt(n) => {
+            use num_traits::ToPrimitive;
+            Ok(n.to_f64().unwrap_or(Float::NAN))
+        },
+
+        other => Err(Error::TypeMismatch {
+            expected: "Int|Float",
+            found: other.type_name(),
+        }),
+    }
+}
 
 impl Value {
+    /// Whether a condition holding this value counts as true. Nil, zero
+    /// and the empty string are false, and everything else is true. `if`,
+    /// `while`, `not`, `and`, `or`, `assert` and `bool` arguments to
+    /// natives all go by this.
+    pub fn truthy(&self) -> bool {
+        match *self {
+            Value::Nil(_) => false,
+            Value::Bool(b) => b,
+            Value::Int(i) => i != 0,
+            Value::Float(f) => f != 0.0,
+            Value::Str(ref s) => !s.is_empty(),
+
+            // Never zero, or it would be an Int
+            #[cfg(feature = "bigint")]
+            Value::BigInt(_) => true,
+
+            Value::Ident(_) | Value::List(_) | Value::Record(_) |
+            Value::Pattern(_) | Value::Opaque(_) => true,
+        }
+    }
+
+    /// Roughly how many bytes this value holds on the heap, not counting
+    /// what the Lists and Records inside it hold.
+    pub fn heap_size(&self) -> usize {
+        use std::mem::size_of;
+
+        match *self {
+            Value::Str(ref s) => s.len(),
+
+            Value::List(ref list) => list.borrow().len() * size_of::<Value>(),
+
+            Value::Record(ref rec) => rec.borrow().keys()
+                .map(|key| key.len() + size_of::<Str>() + size_of::<Value>())
+                .sum(),
+
+            #[cfg(feature = "bigint")]
+            Value::BigInt(ref n) => (n.bits() / 8) as usize,
+
+            _ => 0,
+        }
+    }
+
+    /// Wrap a host object to hand to a script.
+    pub fn opaque<T: Any + Send + Sync>(object: T) -> Self {
+        Value::Opaque(Opaque::new(object))
+    }
+
+    /// Wrap a host object whose methods scripts can call.
+    pub fn host<T: HostObject>(object: T) -> Self {
+        Value::Opaque(Opaque::host(object))
+    }
+
+    /// The host object inside an `Opaque` value, if it is a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match *self {
+            Value::Opaque(ref obj) => obj.downcast_ref(),
+            _ => None,
+        }
+    }
+
     pub fn from_slice<T: AsRef<[Value]>>(slice: T) -> Self {
         let slice = slice.as_ref();
         let vec_deque = slice.iter().cloned().collect();
-        let list = Arc::new(RefCell::new(vec_deque));
+        let list = Arc::new(Shared::new(vec_deque));
         Value::List(list)
     }
 
     pub fn from_iter<I, T>(iter: I) -> Self
         where I: Iterator<Item=T>, T: Into<Value>
     {
-        Value::List(Arc::new(RefCell::new(iter.map(|t| t.into()).collect())))
+        Value::List(Arc::new(Shared::new(iter.map(|t| t.into()).collect())))
     }
 
-    pub fn index(self, rhs: Self) -> Result<Self> {
-        match self {
-            Value::List(lhs) => {
-                let lhs = lhs.borrow();
-                let rhs = Int::extract(rhs)?;
+    /// Copy nested Lists and Records. Containers reached more than once,
+    /// including through a cycle, are copied once and shared in the result.
+    pub fn deep_clone(&self) -> Self {
+        self.deep_clone_in(&mut HashMap::new())
+    }
 
-                if rhs < 0 {
-                    return Err(Error::NegativeIndex);
+    fn deep_clone_in(&self, seen: &mut HashMap<usize, Value>) -> Self {
+        match *self {
+            Value::List(ref list) => {
+                let addr = Arc::as_ptr(list) as usize;
+                if let Some(copy) = seen.get(&addr) {
+                    return copy.clone();
                 }
 
-                let rhs = rhs as usize;
+                let copy = List::default();
+                seen.insert(addr, Value::List(copy.clone()));
 
+                let items = list.borrow().iter()
+                    .map(|item| item.deep_clone_in(seen))
+                    .collect();
+                *copy.borrow_mut() = items;
+                Value::List(copy)
+            },
+
+            Value::Record(ref rec) => {
+                let addr = Arc::as_ptr(rec) as usize;
+                if let Some(copy) = seen.get(&addr) {
+                    return copy.clone();
+                }
+
+                let copy = Record::default();
+                seen.insert(addr, Value::Record(copy.clone()));
+
+                let entries = rec.borrow().iter()
+                    .map(|(k, v)| (k.clone(), v.deep_clone_in(seen)))
+                    .collect();
+                *copy.borrow_mut() = entries;
+                Value::Record(copy)
+            },
+
+            ref other => other.clone(),
+        }
+    }
+
+    /// Records are keyed by strings; a symbol stands for its own name.
+    pub fn record_key(self) -> Result<Str> {
+        match self {
+            Value::Str(key) => Ok(key),
+            Value::Ident(key) => Ok(key.into()),
+
+            other => Err(Error::TypeMismatch {
+                expected: "Str|Ident",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    pub fn index(self, rhs: Self) -> Result<Self> {
+        match self {
+            Value::List(lhs) => {
+                let lhs = lhs.borrow();
+                let rhs = list_index(lhs.len(), Int::extract(rhs)?)?;
                 lhs.get(rhs).cloned().ok_or(Error::IndexOutOfBounds)
             },
 
+            // One character, as a string
+            Value::Str(lhs) => {
+                let rhs = list_index(lhs.chars().count(), Int::extract(rhs)?)?;
+                let found = lhs.chars().nth(rhs).ok_or(Error::IndexOutOfBounds)?;
+                Ok(Str::from(found.to_string()).into())
+            },
+
             Value::Record(lhs) => {
                 let lhs = lhs.borrow();
-                let rhs = Ident::extract(rhs)?;
+                let rhs = rhs.record_key()?;
                 lhs.get(&rhs).cloned().ok_or(Error::IndexOutOfBounds)
             },
 
+            other => Err(Error::TypeMismatch {
+                expected: "List|Record|Str",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// The items or characters from `lo` through `hi`. Negative bounds
+    /// count back from the end, and bounds past either end are clamped, so
+    /// a slice can be empty but never fails.
+    pub fn slice(self, lo: Int, hi: Int) -> Result<Self> {
+        let span = |len: usize| {
+            let from_end = |i: Int| if i < 0 { i + len as Int } else { i };
+            let lo = from_end(lo).max(0).min(len as Int);
+            let hi = from_end(hi).saturating_add(1).min(len as Int);
+            lo as usize .. hi.max(lo) as usize
+        };
+
+        match self {
+            Value::List(list) => {
+                let list = list.borrow();
+                Ok(Value::from_iter(list.range(span(list.len())).cloned()))
+            },
+
+            Value::Str(text) => {
+                let span = span(text.chars().count());
+                let text: String = text.chars()
+                    .skip(span.start)
+                    .take(span.len())
+                    .collect();
+                Ok(Str::from(text).into())
+            },
+
+            other => Err(Error::TypeMismatch {
+                expected: "List|Str",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// Snapshot the items visited by `foreach`. Records yield `[key, value]`
+    /// pairs in key order.
+    pub fn iterate(self) -> Result<VecDeque<Value>> {
+        match self {
+            Value::List(list) => Ok(list.borrow().clone()),
+
+            Value::Record(rec) => {
+                let rec = rec.borrow();
+                let mut keys: Vec<&Str> = rec.keys().collect();
+                keys.sort();
+
+                Ok(keys.into_iter().map(|key| {
+                    Value::from_slice([key.clone().into(), rec[key].clone()])
+                }).collect())
+            },
+
             other => Err(Error::TypeMismatch {
                 expected: "List|Record",
                 found: other.type_name(),
@@ -104,27 +703,163 @@ impl Value {
         }
     }
 
+    /// Numeric ordering, promoting to Float when the operands are mixed.
+    pub fn compare(self, rhs: Self) -> Result<Option<Ordering>> {
+        #[cfg(feature = "bigint")]
+        {
+            if let Some((lhs, rhs)) = big_operands(&self, &rhs) {
+                return Ok(Some(lhs.cmp(&rhs)));
+            }
+        }
+
+        match (self, rhs) {
+            (Value::Int(lhs), Value::Int(rhs)) => Ok(Some(lhs.cmp(&rhs))),
+            (lhs, rhs) => Ok(promote(lhs)?.partial_cmp(&promote(rhs)?)),
+        }
+    }
+
+    /// The ordering used by `lt`, `gt`, `le` and `ge`: both sides
+    /// must be strings, compared byte by byte with no regard for locale.
+    pub fn compare_str(&self, rhs: &Self) -> Result<Ordering> {
+        match (self, rhs) {
+            (Value::Str(lhs), Value::Str(rhs)) => Ok(lhs.cmp(rhs)),
+
+            (Value::Str(_), other) | (other, _) => Err(Error::TypeMismatch {
+                expected: "Str",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// The ordering used by `sort` and `compare`, which puts every pair of
+    /// values in order. Values of different types order by type, except
+    /// that numbers compare numerically; Lists compare item by item, and
+    /// Records by their entries in key order. Patterns and Opaque values
+    /// only order by where they are in memory.
+    pub fn order(&self, rhs: &Self) -> Ordering {
+        self.order_in(rhs, &mut vec![])
+    }
+
+    fn order_in(&self, other: &Self, seen: &mut Vec<(usize, usize)>)
+        -> Ordering
+    {
+        if self.same(other) {
+            return Ordering::Equal;
+        }
+
+        #[cfg(feature = "bigint")]
+        {
+            if let Some((lhs, rhs)) = big_operands(self, other) {
+                return lhs.cmp(&rhs);
+            }
+        }
+
+        match (self, other) {
+            (Value::Bool(lhs), Value::Bool(rhs)) => lhs.cmp(rhs),
+            (Value::Int(lhs), Value::Int(rhs)) => lhs.cmp(rhs),
+            (Value::Str(lhs), Value::Str(rhs)) => lhs.cmp(rhs),
+            (Value::Ident(lhs), Value::Ident(rhs)) => {
+                lhs.as_ref().cmp(rhs.as_ref())
+            },
+
+            (Value::Pattern(lhs), Value::Pattern(rhs)) => {
+                Arc::as_ptr(lhs).cmp(&Arc::as_ptr(rhs))
+            },
+
+            (Value::Opaque(lhs), Value::Opaque(rhs)) => {
+                let address = |o: &Opaque| Arc::as_ptr(&o.object) as *const ();
+                address(lhs).cmp(&address(rhs))
+            },
+
+            (Value::List(lhs), Value::List(rhs)) => {
+                let pair = (address(self), address(other));
+                if seen.contains(&pair) {
+                    return Ordering::Equal;
+                }
+
+                seen.push(pair);
+                let (lhs, rhs) = (lhs.borrow(), rhs.borrow());
+                let order = lhs.iter().zip(rhs.iter())
+                    .map(|(lhs, rhs)| lhs.order_in(rhs, seen))
+                    .find(|&order| order != Ordering::Equal)
+                    .unwrap_or_else(|| lhs.len().cmp(&rhs.len()));
+                seen.pop();
+                order
+            },
+
+            (Value::Record(lhs), Value::Record(rhs)) => {
+                let pair = (address(self), address(other));
+                if seen.contains(&pair) {
+                    return Ordering::Equal;
+                }
+
+                seen.push(pair);
+                let (lhs, rhs) = (lhs.borrow(), rhs.borrow());
+                let mut lhs: Vec<_> = lhs.iter().collect();
+                let mut rhs: Vec<_> = rhs.iter().collect();
+                lhs.sort_by(|a, b| a.0.cmp(b.0));
+                rhs.sort_by(|a, b| a.0.cmp(b.0));
+
+                let order = lhs.iter().zip(rhs.iter())
+                    .map(|(lhs, rhs)| {
+                        lhs.0.cmp(rhs.0).then_with(|| lhs.1.order_in(rhs.1, seen))
+                    })
+                    .find(|&order| order != Ordering::Equal)
+                    .unwrap_or_else(|| lhs.len().cmp(&rhs.len()));
+                seen.pop();
+                order
+            },
+
+            (lhs, rhs) if lhs.rank() == rhs.rank() => {
+                // Mixed numbers; an Int and a Float that are equal still
+                // differ in type
+                let number = |value: &Value| promote(value.clone())
+                    .unwrap_or(Float::NAN);
+                number(lhs).total_cmp(&number(rhs))
+                    .then_with(|| lhs.type_name().cmp(rhs.type_name()))
+            },
+
+            (lhs, rhs) => lhs.rank().cmp(&rhs.rank()),
+        }
+    }
+
+    /// Where values of this type go among other types in `order`.
+    fn rank(&self) -> u8 {
+        match *self {
+            Value::Nil(_) => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) | Value::Float(_) => 2,
+            #[cfg(feature = "bigint")]
+            Value::BigInt(_) => 2,
+            Value::Str(_) => 3,
+            Value::Ident(_) => 4,
+            Value::List(_) => 5,
+            Value::Record(_) => 6,
+            Value::Pattern(_) => 7,
+            Value::Opaque(_) => 8,
+        }
+    }
+
     pub fn insert(self, key: Self, val: Self) -> Result<()> {
         match self {
             Value::List(lhs) => {
                 let mut lhs = lhs.borrow_mut();
-                let key = Int::extract(key)?;
-                if key < 0 {
-                    return Err(Error::NegativeIndex);
-                }
-                let rhs = key as usize;
+                let rhs = list_index(lhs.len(), Int::extract(key)?)?;
 
                 if rhs > lhs.len() {
                     return Err(Error::IndexOutOfBounds);
+                } else if rhs == lhs.len() {
+                    lhs.push_back(val);
+                } else {
+                    lhs[rhs] = val;
                 }
 
-                lhs[rhs] = val;
                 Ok(())
             },
 
             Value::Record(lhs) => {
                 let mut lhs = lhs.borrow_mut();
-                let key = Ident::extract(key)?;
+                let key = key.record_key()?;
                 *lhs.entry(key).or_insert(().into()) = val;
                 Ok(())
             },
@@ -137,6 +872,18 @@ impl Value {
     }
 }
 
+/// Where `index` falls in a list of `len` items. Negative indices count
+/// back from the end, so -1 is the last item.
+pub(crate) fn list_index(len: usize, index: Int) -> Result<usize> {
+    let index = if index < 0 { index + len as Int } else { index };
+
+    if index < 0 {
+        Err(Error::IndexOutOfBounds)
+    } else {
+        Ok(index as usize)
+    }
+}
+
 impl Extract for Value {
     const TYPE_NAME: &'static str = "Anything";
 
@@ -145,28 +892,114 @@ impl Extract for Value {
     }
 }
 
-impl Extract for bool {
-    const TYPE_NAME: &'static str = "Bool";
+// Conversions for common Rust types. Lists and Records are copied in and
+// out, so changes on one side are not seen on the other.
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::from_iter(items.into_iter())
+    }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(entries: HashMap<String, T>) -> Self {
+        let entries = entries.into_iter()
+            .map(|(key, val)| (Str::from(key), val.into()))
+            .collect::<HashMap<_, _>>();
+
+        Value::Record(Arc::new(Shared::new(entries)))
+    }
+}
+
+/// `None` becomes nil.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(option: Option<T>) -> Self {
+        match option {
+            Some(value) => value.into(),
+            None => Value::Nil(()),
+        }
+    }
+}
+
+impl Extract for String {
+    const TYPE_NAME: &'static str = "Str";
 
     fn extract(value: Value) -> Result<Self> {
-        match value {
-            Value::Nil(_) => Ok(false),
-            Value::Ident(_) => Ok(true),
-            Value::List(list) => Ok(!list.borrow().is_empty()),
-            Value::Record(_) => Ok(true),
-            Value::Int(0) => Ok(false),
-            Value::Int(_) => Ok(true),
-            Value::Str(s) => Ok(!s.is_empty()),
+        Str::extract(value).map(|s| s.to_string())
+    }
+}
+
+impl<T: Extract> Extract for Vec<T> {
+    const TYPE_NAME: &'static str = "List";
 
-            // TODO: Do we want this?
-            Value::Pattern(_) => Ok(true),
+    fn extract(value: Value) -> Result<Self> {
+        let list = List::extract(value)?;
+        let items = list.borrow().iter().cloned().collect::<Vec<_>>();
+        items.into_iter().map(T::extract).collect()
+    }
+}
+
+impl<T: Extract> Extract for HashMap<String, T> {
+    const TYPE_NAME: &'static str = "Record";
+
+    fn extract(value: Value) -> Result<Self> {
+        let rec = Record::extract(value)?;
+        let entries = rec.borrow().iter()
+            .map(|(key, val)| (key.to_string(), val.clone()))
+            .collect::<Vec<_>>();
+
+        entries.into_iter()
+            .map(|(key, val)| Ok((key, T::extract(val)?)))
+            .collect()
+    }
+}
+
+/// Nil becomes `None`. There is no `TryFrom` to match, since the standard
+/// library already converts any value into an `Option`.
+impl<T: Extract> Extract for Option<T> {
+    const TYPE_NAME: &'static str = T::TYPE_NAME;
+
+    fn extract(value: Value) -> Result<Self> {
+        match value {
+            Value::Nil(_) => Ok(None),
+            value => T::extract(value).map(Some),
         }
     }
 }
 
-impl From<bool> for Value {
-    fn from(b: bool) -> Self {
-        Value::Int(if b { 1 } else { 0 })
+impl TryFrom<Value> for String {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        Self::extract(value)
+    }
+}
+
+impl<T: Extract> TryFrom<Value> for Vec<T> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        Self::extract(value)
+    }
+}
+
+impl<T: Extract> TryFrom<Value> for HashMap<String, T> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        Self::extract(value)
     }
 }
 
@@ -174,10 +1007,26 @@ impl Add for Value {
     type Output = Result<Self>;
 
     fn add(self, rhs: Self) -> Result<Self> {
+        #[cfg(feature = "bigint")]
+        {
+            if let Some((lhs, rhs)) = big_operands(&self, &rhs) {
+                return Arith::Add.bigs(&lhs, &rhs);
+            }
+        }
+
         match self {
-            Value::Int(lhs) => {
-                let rhs = Int::extract(rhs)?;
-                Ok((lhs + rhs).into())
+            Value::Int(lhs) => match rhs {
+                Value::Float(rhs) => Ok((lhs as Float + rhs).into()),
+                rhs => Arith::Add.ints(lhs, Int::extract(rhs)?),
+            },
+
+            Value::Float(lhs) => {
+                Ok((lhs + promote(rhs)?).into())
+            },
+
+            #[cfg(feature = "bigint")]
+            lhs @ Value::BigInt(_) => {
+                Ok((promote(lhs)? + promote(rhs)?).into())
             },
 
             Value::List(lhs) => match rhs {
@@ -189,14 +1038,14 @@ impl Add for Value {
                         rhs.iter().cloned()
                     }).collect();
 
-                    Ok(Value::List(Arc::new(RefCell::new(list))))
+                    Ok(Value::List(Arc::new(Shared::new(list))))
                 },
 
                 other => {
                     let lhs = lhs.borrow();
                     let mut list = VecDeque::with_capacity(lhs.len() + 1);
                     list.push_back(other);
-                    Ok(Value::List(Arc::new(RefCell::new(list))))
+                    Ok(Value::List(Arc::new(Shared::new(list))))
                 },
             },
 
@@ -213,9 +1062,17 @@ impl Sub for Value {
     type Output = Result<Self>;
 
     fn sub(self, rhs: Self) -> Result<Self> {
-        let lhs = Int::extract(self)?;
-        let rhs = Int::extract(rhs)?;
-        Ok((lhs - rhs).into())
+        #[cfg(feature = "bigint")]
+        {
+            if let Some((lhs, rhs)) = big_operands(&self, &rhs) {
+                return Arith::Sub.bigs(&lhs, &rhs);
+            }
+        }
+
+        match (self, rhs) {
+            (Value::Int(lhs), Value::Int(rhs)) => Arith::Sub.ints(lhs, rhs),
+            (lhs, rhs) => Ok((promote(lhs)? - promote(rhs)?).into()),
+        }
     }
 }
 
@@ -223,20 +1080,26 @@ impl Div for Value {
     type Output = Result<Self>;
 
     fn div(self, rhs: Self) -> Result<Self> {
+        #[cfg(feature = "bigint")]
+        {
+            if let Some((lhs, rhs)) = big_operands(&self, &rhs) {
+                return Arith::Div.bigs(&lhs, &rhs);
+            }
+        }
+
         match (self, rhs) {
             (_, Value::Int(0)) => {
                 Err(Error::DividedByZero)
             },
 
-            (Value::Int(lhs), Value::Int(rhs)) => {
-                Ok((lhs / rhs).into())
+            (_, Value::Float(0.0)) => {
+                Err(Error::DividedByZero)
             },
 
-            (_, other) => {
-                Err(Error::TypeMismatch {
-                    expected: "Int",
-                    found: other.type_name(),
-                })
+            (Value::Int(lhs), Value::Int(rhs)) => Arith::Div.ints(lhs, rhs),
+
+            (lhs, rhs) => {
+                Ok((promote(lhs)? / promote(rhs)?).into())
             },
         }
     }
@@ -246,21 +1109,32 @@ impl Mul for Value {
     type Output = Result<Self>;
 
     fn mul(self, rhs: Self) -> Result<Self> {
-        let rhs = Int::extract(rhs)?;
+        #[cfg(feature = "bigint")]
+        {
+            if let Some((lhs, rhs)) = big_operands(&self, &rhs) {
+                return Arith::Mul.bigs(&lhs, &rhs);
+            }
+        }
 
-        match self {
-            Value::Int(lhs) => {
-                Ok((lhs * rhs).into())
-            },
+        match (self, rhs) {
+            (Value::Int(lhs), Value::Int(rhs)) => Arith::Mul.ints(lhs, rhs),
+
+            (Value::Str(lhs), rhs) => {
+                let rhs = Int::extract(rhs)?;
 
-            Value::Str(lhs) => {
                 if rhs < 0 {
                     return Err(Error::NegativeRepetition);
                 }
 
                 let rhs = rhs as usize;
 
-                let mut buf = String::with_capacity(lhs.len() * rhs);
+                // Too long to allocate is an error, not a crash
+                let len = lhs.len().checked_mul(rhs)
+                    .ok_or(Error::OutOfMemory)?;
+
+                let mut buf = String::new();
+                buf.try_reserve_exact(len)
+                    .map_err(|_| Error::OutOfMemory)?;
 
                 for _ in 0 .. rhs {
                     buf.push_str(&lhs);
@@ -269,6 +1143,15 @@ impl Mul for Value {
                 Ok((Str::from(buf)).into())
             },
 
+            (lhs @ Value::Int(_), rhs) | (lhs @ Value::Float(_), rhs) => {
+                Ok((promote(lhs)? * promote(rhs)?).into())
+            },
+
+            #[cfg(feature = "bigint")]
+            (lhs @ Value::BigInt(_), rhs) => {
+                Ok((promote(lhs)? * promote(rhs)?).into())
+            },
+
             _ => {
                 Err(Error::IllegalMultiply)
             },
@@ -276,13 +1159,177 @@ impl Mul for Value {
     }
 }
 
+impl Rem for Value {
+    type Output = Result<Self>;
+
+    /// The result takes the sign of the divisor, as in Perl.
+    fn rem(self, rhs: Self) -> Result<Self> {
+        #[cfg(feature = "bigint")]
+        {
+            if let Some((lhs, rhs)) = big_operands(&self, &rhs) {
+                return Arith::Rem.bigs(&lhs, &rhs);
+            }
+        }
+
+        let lhs = Int::extract(self)?;
+        let rhs = Int::extract(rhs)?;
+
+        if rhs == 0 {
+            return Err(Error::DividedByZero);
+        }
+
+        let rem = lhs.wrapping_rem(rhs);
+
+        if rem != 0 && (rem < 0) != (rhs < 0) {
+            Ok((rem + rhs).into())
+        } else {
+            Ok(rem.into())
+        }
+    }
+}
+
+/// Integer arithmetic. A result too wide for an Int fails with
+/// IntegerOverflow, or with the `bigint` feature becomes a BigInt.
+#[derive(Copy, Clone)]
+enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    #[cfg(feature = "bigint")]
+    Rem,
+}
+
+impl Arith {
+    fn ints(self, lhs: Int, rhs: Int) -> Result<Value> {
+        let result = match self {
+            Arith::Add => lhs.checked_add(rhs),
+            Arith::Sub => lhs.checked_sub(rhs),
+            Arith::Mul => lhs.checked_mul(rhs),
+            Arith::Div => lhs.checked_div(rhs),
+            #[cfg(feature = "bigint")]
+            Arith::Rem => lhs.checked_rem(rhs),
+        };
+
+        match result {
+            Some(n) => Ok(n.into()),
+            None => self.overflowed(lhs, rhs),
+        }
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    fn overflowed(self, _: Int, _: Int) -> Result<Value> {
+        Err(Error::IntegerOverflow)
+    }
+
+    #[cfg(feature = "bigint")]
+    fn overflowed(self, lhs: Int, rhs: Int) -> Result<Value> {
+        self.bigs(&lhs.into(), &rhs.into())
+    }
+
+    #[cfg(feature = "bigint")]
+    fn bigs(self, lhs: &num_bigint::BigInt, rhs: &num_bigint::BigInt)
+        -> Result<Value>
+    {
+        use num_traits::{Signed, Zero};
+
+        let result = match self {
+            Arith::Add => lhs + rhs,
+            Arith::Sub => lhs - rhs,
+            Arith::Mul => lhs * rhs,
+
+            Arith::Div | Arith::Rem if rhs.is_zero() => {
+                return Err(Error::DividedByZero);
+            },
+
+            Arith::Div => lhs / rhs,
+
+            // Takes the sign of the divisor, like Int remainders
+            Arith::Rem => {
+                let rem = lhs % rhs;
+
+                if !rem.is_zero() && rem.is_negative() != rhs.is_negative() {
+                    rem + rhs
+                } else {
+                    rem
+                }
+            },
+        };
+
+        Ok(result.into())
+    }
+}
+
+/// Both operands as BigInts, if they are integers and either one is a
+/// BigInt.
+#[cfg(feature = "bigint")]
+fn big_operands(lhs: &Value, rhs: &Value)
+    -> Option<(num_bigint::BigInt, num_bigint::BigInt)>
+{
+    let widen = |value: &Value| match *value {
+        Value::Int(i) => Some(num_bigint::BigInt::from(i)),
+        Value::BigInt(ref n) => Some((**n).clone()),
+        _ => None,
+    };
+
+    match (lhs, rhs) {
+        (&Value::BigInt(_), _) | (_, &Value::BigInt(_)) => {
+            Some((widen(lhs)?, widen(rhs)?))
+        },
+
+        _ => None,
+    }
+}
+
+/// Narrows to an Int when the value fits.
+#[cfg(feature = "bigint")]
+impl From<num_bigint::BigInt> for Value {
+    fn from(n: num_bigint::BigInt) -> Self {
+        use num_traits::ToPrimitive;
+
+        match n.to_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::BigInt(Arc::new(n)),
+        }
+    }
+}
+
+/// Accepts an Int or a BigInt.
+#[cfg(feature = "bigint")]
+impl Extract for num_bigint::BigInt {
+    const TYPE_NAME: &'static str = "Int|BigInt";
+
+    fn extract(value: Value) -> Result<Self> {
+        match value {
+            Value::Int(i) => Ok(i.into()),
+            Value::BigInt(n) => Ok((*n).clone()),
+
+            other => Err(Error::TypeMismatch {
+                expected: Self::TYPE_NAME,
+                found: other.type_name(),
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "bigint")]
+impl TryFrom<Value> for num_bigint::BigInt {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        Self::extract(value)
+    }
+}
+
 use std::fmt::{self, Display};
 
 impl Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Value::Nil(_) => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
             Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
             Value::Str(ref s) => write!(f, "{}", s),
             Value::Ident(ref id) => write!(f, "{}", id),
 
@@ -303,6 +1350,10 @@ impl Display for Value {
             },
 
             Value::Pattern(_) => write!(f, "re/.../"),
+            Value::Opaque(ref obj) => write!(f, "<{}>", obj.type_name()),
+
+            #[cfg(feature = "bigint")]
+            Value::BigInt(ref n) => write!(f, "{}", n),
         }
     }
 }
@@ -0,0 +1,157 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Run `canary` with `args` and `input` piped to its stdin.
+fn canary(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_canary"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Run `canary -e source` with `input` piped to its stdin, and return what
+/// it printed.
+fn run_with_stdin(source: &str, input: &str) -> String {
+    let output = canary(&["-e", source], input);
+    assert!(output.status.success(), "exited with {}", output.status);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Write `source` to a file of its own for one test.
+fn script(name: &str, source: &str) -> PathBuf {
+    let name = format!("canary_cli_{}_{}.cy", name, std::process::id());
+    let path = std::env::temp_dir().join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn text(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap()
+}
+
+#[test]
+fn readline_from_stdin() {
+    let source = r#"
+        print readline();
+        print readline();
+        print typeof(readline());
+    "#;
+
+    // Line endings are dropped, and the end of input reads as nil
+    assert_eq!(run_with_stdin(source, "first\r\nsecond"), "first\nsecond\nNil\n");
+    assert_eq!(run_with_stdin(source, ""), "nil\nnil\nNil\n");
+}
+
+#[test]
+fn read_all_of_stdin() {
+    let source = r#"
+        print readline();
+        print len(read_all_stdin());
+        print len(read_all_stdin());
+    "#;
+
+    assert_eq!(run_with_stdin(source, "head\nrest\nof it\n"), "head\n11\n0\n");
+}
+
+#[test]
+fn inline_source_and_exit_status() {
+    let output = canary(&["-e", "print %ARGV;", "a", "b"], "");
+    assert!(output.status.success());
+    assert_eq!(text(output.stdout), "[a, b]\n");
+
+    let output = canary(&["-e", "print 1; exit(3);"], "");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(text(output.stdout), "1\n");
+
+    // Uncaught errors go to stderr and exit with 1
+    let output = canary(&["-e", "my $x = 1 / 0;"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(text(output.stderr).starts_with("ERROR: divided by zero"));
+}
+
+#[test]
+fn check_reports_to_stderr() {
+    let good = script("good", "my $x = 1;\nprint $x;\n");
+    let output = canary(&["--check", good.to_str().unwrap()], "");
+    let _ = fs::remove_file(&good);
+
+    // Checking doesn't run anything
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    let bad = script("bad", "my $x = ;\nprint $y;\n");
+    let output = canary(&["--check", bad.to_str().unwrap()], "");
+    let _ = fs::remove_file(&bad);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    let stderr = text(output.stderr);
+    assert_eq!(stderr.matches("ERROR: ").count(), 2, "{}", stderr);
+    assert!(stderr.contains(":1:9: unexpected `;`"), "{}", stderr);
+}
+
+#[test]
+fn piped_script_with_shebang() {
+    let output = canary(&[], "#!/usr/bin/env canary\nprint \"piped\";\n");
+    assert!(output.status.success(), "{}", text(output.stderr));
+    assert_eq!(text(output.stdout), "piped\n");
+}
+
+#[test]
+fn dump_ast_without_running() {
+    let path = script("ast", "print twice(2);\n\nsub twice($x) {\n    return $x * 2;\n}\n");
+    let output = canary(&["--dump-ast", path.to_str().unwrap()], "");
+    let _ = fs::remove_file(&path);
+
+    assert!(output.status.success(), "{}", text(output.stderr));
+    assert_eq!(text(output.stdout), concat!(
+        "print(twice(2));\n",
+        "\n",
+        "sub twice($x) {\n",
+        "    return $x * 2;\n",
+        "}\n",
+    ));
+
+    // The flag needs a file
+    assert_eq!(canary(&["--dump-ast"], "").status.code(), Some(2));
+}
+
+#[test]
+fn dump_bytecode_without_running() {
+    let path = script("bytecode", "print twice(2);\n\nsub twice($x) {\n    return $x * 2;\n}\n");
+    let output = canary(&["--no-optimize", "--dump-bytecode", path.to_str().unwrap()], "");
+
+    assert!(output.status.success(), "{}", text(output.stderr));
+    assert_eq!(text(output.stdout), concat!(
+        "(top level):\n",
+        "    0000  PUSHI 2\n",
+        "    0001  CALL twice, 1\n",
+        "    0002  CALL print, 1\n",
+        "    0003  DROP\n",
+        "\n",
+        "sub twice:\n",
+        "    0000  LOAD 0\n",
+        "    0001  PUSHI 2\n",
+        "    0002  BINOP MUL\n",
+        "    0003  RET\n",
+        "    0004  NIL\n",
+        "    0005  RET\n",
+    ));
+
+    // The optimizer drops the unreachable implicit return
+    let output = canary(&["--dump-bytecode", path.to_str().unwrap()], "");
+    let _ = fs::remove_file(&path);
+
+    assert!(output.status.success());
+    assert!(text(output.stdout).ends_with("    0003  RET\n"));
+}
@@ -0,0 +1,1484 @@
+extern crate canary;
+#[cfg(feature = "bigint")]
+extern crate num_bigint;
+
+use canary::{Error, Result};
+use canary::ast::parse_module;
+use canary::opcode::{Argc, Module};
+use canary::token::Tokenizer;
+use canary::value::*;
+
+fn compile(src: &str) -> Result<Module> {
+    parse_module(Tokenizer::new(src).spanned())?.translate()
+}
+
+#[test]
+fn native_callbacks() {
+    let src = r#"
+        %greeting = "hello";
+        assert_eq apply_twice(:inc, 1), 3;
+        assert_eq greeting(), "hello";
+        assert host_match("bcd", re/(b)/);
+        assert_eq $1, "b";
+
+        sub inc($x) {
+            return $x + 1;
+        }
+    "#;
+
+    let mut module = compile(src).unwrap();
+
+    module.def_native_ctx("apply_twice", Argc::Exactly(2), |interp, mut args| {
+        let arg = args.pop().unwrap();
+        let func = args.pop().unwrap();
+        let once = interp.call_value(func.clone(), vec![arg])?;
+        interp.call_value(func, vec![once])
+    }).unwrap();
+
+    module.def_native_ctx("greeting", Argc::Exactly(0), |interp, _| {
+        Ok(interp.global("greeting")?.unwrap_or(Value::Nil(())))
+    }).unwrap();
+
+    module.def_native_ctx("host_match", Argc::Exactly(2), |interp, mut args| {
+        let pat = args.pop().unwrap();
+        let text = args.pop().unwrap();
+        interp.match_pattern(pat, text)
+    }).unwrap();
+
+    let mut interp = module.start().unwrap_or_else(|err| {
+        panic!("Error: {}", err);
+    });
+
+    let value = interp.exec("inc", &[Value::Int(41)]).unwrap();
+    assert_eq!(value, Value::Int(42));
+}
+
+#[test]
+fn script_args() {
+    let src = r#"
+        assert_eq argv(), ["one", "two"];
+        assert_eq %ARGV, ["one", "two"];
+    "#;
+
+    compile(src).unwrap().start_with_args(&["one", "two"]).unwrap();
+
+    // Only a host passing arguments sets %ARGV
+    let interp = compile("").unwrap().start().unwrap();
+    assert!(interp.globals().is_empty());
+}
+
+#[test]
+fn exit_skips_handlers() {
+    let src = r#"
+        try {
+            stop(7);
+        } catch $e {
+            die("exit was caught");
+        }
+
+        sub stop($code) {
+            exit($code);
+        }
+    "#;
+
+    match compile(src).unwrap().start() {
+        Err(Error::Exit { code }) => assert_eq!(code, 7),
+        Err(err) => panic!("Error: {}", err),
+        Ok(_) => panic!("exit() did not stop the script"),
+    }
+}
+
+#[test]
+fn processes_are_opt_in() {
+    let src = r#"
+        my $result = run("echo", "hello", 42);
+        assert_eq $result.stdout, "hello 42\n";
+        assert_eq $result.stderr, "";
+        assert_eq $result.status, 0;
+
+        assert_eq run("sh", "-c", "exit 3").status, 3;
+
+        try {
+            run("canary-no-such-command");
+            assert 0;
+        } catch $e {
+            assert_eq $e.error, :Io;
+        }
+    "#;
+
+    match compile(src).unwrap().start() {
+        Err(err) => assert_eq!(err.kind(), "NoSuchLabel"),
+        Ok(_) => panic!("run() was available without opting in"),
+    }
+
+    let mut module = compile(src).unwrap();
+    module.enable_processes().unwrap();
+    module.start().unwrap_or_else(|err| panic!("Error: {}", err));
+}
+
+#[test]
+fn defaults_must_come_last() {
+    let src = r#"
+        sub f($a = 1, $b) {
+        }
+    "#;
+
+    match compile(src) {
+        Err(err) => assert_eq!(err.kind(), "MisplacedDefault"),
+        Ok(_) => panic!("a required parameter followed a default"),
+    }
+}
+
+#[test]
+fn rest_must_come_last() {
+    let src = r#"
+        sub f(@rest, $a) {
+        }
+    "#;
+
+    match compile(src) {
+        Err(err) => assert_eq!(err.kind(), "MisplacedRest"),
+        Ok(_) => panic!("a parameter followed the rest parameter"),
+    }
+}
+
+#[test]
+fn packages_qualify_subs() {
+    let src = r#"
+        package util;
+
+        sub inc($x) {
+            return $x + 1;
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+    let value = interp.exec("util::inc", &[Value::Int(1)]).unwrap();
+    assert_eq!(value, Value::Int(2));
+    assert!(interp.exec("inc", &[Value::Int(1)]).is_err());
+}
+
+#[test]
+fn fuel_limits_loops() {
+    let src = r#"
+        sub spin() {
+            try {
+                while 1 { }
+            } catch $e {
+                return :caught;
+            }
+        }
+
+        sub quick($x) {
+            return $x * 2;
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+
+    match interp.exec_with_fuel("spin", &[], 1000).map_err(|e| e.root().kind()) {
+        Err("FuelExhausted") => (),
+        other => panic!("expected FuelExhausted, got {:?}", other),
+    }
+
+    // The interpreter is still usable, and the limit does not persist
+    let value = interp.exec_with_fuel("quick", &[Value::Int(2)], 1000).unwrap();
+    assert_eq!(value, Value::Int(4));
+    assert_eq!(interp.exec("quick", &[Value::Int(3)]).unwrap(), Value::Int(6));
+}
+
+#[test]
+fn interrupt_from_another_thread() {
+    use std::thread;
+    use std::time::Duration;
+
+    let src = r#"
+        sub spin() {
+            try {
+                while 1 { }
+            } catch $e {
+                return :caught;
+            }
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+    let handle = interp.interrupt_handle();
+
+    let timer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+
+    match interp.exec("spin", &[]).map_err(|e| e.root().kind()) {
+        Err("Interrupted") => (),
+        other => panic!("expected Interrupted, got {:?}", other),
+    }
+
+    timer.join().unwrap();
+
+    // The request is consumed, so later calls run normally
+    assert_eq!(interp.exec("is_int", &[Value::Int(1)]).unwrap(), Value::Bool(true));
+}
+
+#[test]
+fn recursion_depth_is_limited() {
+    let src = r#"
+        sub forever($n) {
+            return forever($n + 1);
+        }
+
+        sub guarded() {
+            try {
+                forever(0);
+            } catch $e {
+                return $e.error;
+            }
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+
+    let err = interp.exec("forever", &[Value::Int(0)]).unwrap_err();
+
+    match *err.root() {
+        Error::StackOverflow { ref func, depth } => {
+            assert_eq!(func.as_ref(), "forever");
+            assert_eq!(depth, canary::eval::DEFAULT_MAX_DEPTH);
+        },
+
+        ref other => panic!("expected StackOverflow, got {:?}", other),
+    }
+
+    interp.set_max_depth(50);
+
+    let kind = interp.exec("guarded", &[]).unwrap();
+    assert_eq!(format!("{}", kind), "StackOverflow");
+}
+
+#[test]
+fn uncaught_errors_carry_a_trace() {
+    let src = r#"
+        sub outer() {
+            inner();
+        }
+
+        sub inner() {
+            return 1 / 0;
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+    let err = interp.exec("outer", &[]).unwrap_err();
+
+    match *err.root() {
+        Error::DividedByZero => (),
+        ref other => panic!("expected DividedByZero, got {:?}", other),
+    }
+
+    let names: Vec<_> = err.trace().expect("no trace").0.iter()
+        .map(|frame| frame.func.as_ref().map(|f| f.as_ref().to_owned()))
+        .collect();
+
+    assert_eq!(names, vec![
+        Some("inner".to_owned()),
+        Some("outer".to_owned()),
+        None,
+    ]);
+}
+
+#[test]
+fn runtime_errors_report_locations() {
+    let src = "sub outer() {\n    inner();\n}\n\nsub inner() {\n    my $x = 1;\n    return [$x][2];\n}\n";
+
+    let mut module = compile(src).unwrap();
+    module.set_source("test.cy", src);
+
+    let mut interp = module.start().unwrap();
+    let err = interp.exec("outer", &[]).unwrap_err();
+
+    let location = err.location().expect("no location");
+    assert_eq!(format!("{}", location), "test.cy:7:5");
+    assert!(err.to_string().starts_with("index out of bounds at test.cy:7:5"));
+
+    let caller = err.trace().unwrap().0[1].location.clone().unwrap();
+    assert_eq!((caller.line, caller.column), (2, 5));
+}
+
+#[test]
+fn parse_errors_show_an_excerpt() {
+    use canary::opcode::Source;
+
+    let src = "sub f() {\n    my $y = [1, 2;\n}\n";
+    let source = Source::new("bad.cy", src);
+
+    let err = parse_module(Tokenizer::new(src).spanned()).unwrap_err();
+    let err = Error::from(err).diagnose(&source);
+
+    assert_eq!(err.to_string(), concat!(
+        "bad.cy:2:18: unexpected `;`, expected one of \",\", \"]\"\n",
+        "2 |     my $y = [1, 2;\n",
+        "  |                  ^",
+    ));
+
+    let location = err.location().unwrap();
+    assert_eq!((location.line, location.column), (2, 18));
+
+    // An offset partway through a character, as from corrupt bytecode,
+    // finds the character rather than panicking
+    let wide = Source::new("wide.cy", "my $s = \"é\";\nx;");
+    let location = wide.locate(10);
+    assert_eq!((location.line, location.column), (1, 10));
+}
+
+#[test]
+fn compile_all_reports_every_error() {
+    use std::fs;
+
+    let src = concat!(
+        "my $a = [1, 2;\n",
+        "sub f() {\n",
+        "    my $b = ;\n",
+        "    print $c;\n",
+        "    print $d;\n",
+        "}\n",
+        "sub g($x = 1, $y) { }\n",
+        "sub h() { my $e = 1 }\n",
+        "sub k() { print $f; }\n",
+    );
+
+    let name = format!("canary_compile_all_{}.cy", std::process::id());
+    let path = std::env::temp_dir().join(name);
+    fs::write(&path, src).unwrap();
+
+    let (module, diagnostics) = canary::compile_all(&path).unwrap();
+    let _ = fs::remove_file(&path);
+
+    assert!(module.is_none());
+
+    let lines: Vec<_> = diagnostics.iter()
+        .map(|diagnostic| diagnostic.location.as_ref().unwrap().line)
+        .collect();
+
+    // Translation carries on past a bad statement, and parsing picks up
+    // again at the end of a block as well as at a semicolon
+    assert_eq!(lines, vec![1, 3, 4, 5, 7, 8, 9]);
+    assert!(diagnostics[2].message.contains("variable not defined"));
+}
+
+#[test]
+fn disassembly_names_jump_targets() {
+    let src = "sub count($n) { while $n > 0 { $n -= 1; } return $n; }";
+    let module = compile(src).unwrap();
+
+    let listing = module.disassemble();
+    let count = listing.split("sub count:\n").nth(1).expect("no listing for count");
+
+    assert!(count.starts_with("    0000  LOAD 0\n"));
+    assert!(count.contains("JNZ L0\n"));
+    assert!(count.contains("L0:\n"));
+    assert!(count.contains("BINOP GT\n"));
+}
+
+#[test]
+fn compile_from_a_string() {
+    let module = canary::compile_str("inline.cy", "sub twice($x) { return $x * 2; }").unwrap();
+    let mut interp = module.start().unwrap();
+    assert_eq!(interp.exec("twice", &[Value::Int(4)]).unwrap(), Value::Int(8));
+
+    let err = canary::compile_str("inline.cy", "my $x = ;").err().unwrap();
+    assert!(err.to_string().starts_with("inline.cy:1:9: unexpected `;`"));
+}
+
+#[test]
+fn compile_without_optimizing() {
+    let src = "my $x = 1;\nif 1 { $x = 2; }\nassert_eq $x, 2;";
+
+    let plain = canary::Options { optimize: false, ..Default::default() };
+    let unoptimized = canary::compile_str_with("plain.cy", src, &plain).unwrap();
+    let optimized = canary::compile_str("tidy.cy", src).unwrap();
+
+    // Only the module asked for it keeps the compiler's own output
+    assert!(!unoptimized.optimize);
+    assert!(optimized.optimize);
+    assert!(unoptimized.begin.len() > optimized.begin.len());
+
+    unoptimized.start().unwrap();
+    optimized.start().unwrap();
+}
+
+#[test]
+fn newlines_are_opt_in() {
+    // Semicolons end statements unless asked otherwise, so a call can
+    // carry on to the next line
+    let split = "my $x = 2;\nassert_eq\n    $x, 2;";
+    canary::compile_str("split.cy", split).unwrap().start().unwrap();
+
+    let bare = "my $x = 2\nassert_eq $x, 2\n";
+    assert!(canary::compile_str("bare.cy", bare).is_err());
+
+    let newlines = canary::Options { newlines: true, ..Default::default() };
+    canary::compile_str_with("bare.cy", bare, &newlines).unwrap().start().unwrap();
+}
+
+#[test]
+fn imports_need_a_file() {
+    let err = canary::compile_str("inline.cy", "import nowhere;").err().unwrap();
+    assert!(err.to_string().starts_with("in import nowhere: "), "{}", err);
+
+    // Translating a parsed module has nowhere to look for them
+    let err = compile("import geometry;").err().unwrap();
+    assert_eq!(err.kind(), "UnresolvedImport");
+}
+
+#[test]
+fn compile_with_a_shebang() {
+    let src = "#!/usr/bin/env canary\nsub answer() { return 42; }\nsub oops() { throw :x; }";
+    let module = canary::compile_str("run.cy", src).unwrap();
+
+    let mut interp = module.start().unwrap();
+    assert_eq!(interp.exec("answer", &[]).unwrap(), Value::Int(42));
+
+    // Lines are still counted from the top of the file
+    let err = interp.exec("oops", &[]).unwrap_err();
+    assert_eq!(err.location().map(|loc| loc.line), Some(3));
+}
+
+#[test]
+fn compile_from_a_reader() {
+    use std::io::Cursor;
+
+    let src = Cursor::new(b"sub answer() { return 42; }".to_vec());
+    let module = canary::compile_reader("stream.cy", src).unwrap();
+
+    let mut interp = module.start().unwrap();
+    assert_eq!(interp.exec("answer", &[]).unwrap(), Value::Int(42));
+
+    let bad = Cursor::new(vec![0xff, 0xfe]);
+    match canary::compile_reader("stream.cy", bad) {
+        Err(Error::Io { .. }) => (),
+        other => panic!("expected an I/O error, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn modules_survive_serialization() {
+    let src = r#"
+        %greeting = "hi";
+
+        sub check($s, $scale = 1.5) {
+            if $s =~ re/b([0-9]\d*)/i {
+                return [$1, $scale * 2, "$1 %greeting"];
+            }
+
+            return [1][3];
+        }
+    "#;
+
+    let module = canary::compile_str("saved.cy", src).unwrap();
+
+    let mut bytes = vec![];
+    module.serialize(&mut bytes).unwrap();
+
+    let mut again = vec![];
+    Module::deserialize(&mut &bytes[..]).unwrap().serialize(&mut again).unwrap();
+    assert_eq!(bytes, again);
+
+    let mut interp = Module::deserialize(&mut &bytes[..]).unwrap().start().unwrap();
+
+    let value = interp.exec("check", &[Value::Str("AB12".into())]).unwrap();
+    assert_eq!(format!("{}", value), "[12, 3.0, 12 hi]");
+
+    let err = interp.exec("check", &[Value::Str("xyz".into())]).unwrap_err();
+    assert_eq!(err.location().unwrap().to_string(), "saved.cy:9:13");
+
+    match Module::deserialize(&mut &b"not bytecode"[..]) {
+        Err(Error::InvalidBytecode) => (),
+        other => panic!("expected InvalidBytecode, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn eval_keeps_state_between_snippets() {
+    let mut interp = Module::stdlib().unwrap().start().unwrap();
+
+    assert_eq!(interp.eval("<repl>", "my $x = 20; %g = 1;").unwrap(), None);
+    interp.eval("<repl>", "sub add($n) { return $n + %g; }").unwrap();
+    assert_eq!(interp.eval("<repl>", "add($x + 1);").unwrap(), Some(Value::Int(22)));
+
+    // A failed snippet forgets its own locals but keeps earlier ones
+    let err = interp.eval("<repl>", "my $y = 1; my $z = 1 / 0;").unwrap_err();
+    assert_eq!(err.root().kind(), "DividedByZero");
+    assert!(interp.eval("<repl>", "$y;").is_err());
+
+    // Redeclaring a name shadows the old slot
+    interp.eval("<repl>", "my $x = \"s\";").unwrap();
+    assert_eq!(interp.eval("<repl>", "[$x, %g];").unwrap().unwrap().to_string(), "[s, 1]");
+}
+
+#[test]
+fn list_variables_after_eval() {
+    let mut interp = Module::stdlib().unwrap().start().unwrap();
+    interp.eval("<repl>", "my $a = 1; my $b = 2; %z = 3; %y = 4;").unwrap();
+    interp.eval("<repl>", "my $a = 5;").unwrap();
+
+    let locals: Vec<_> = interp.locals().into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    assert_eq!(locals, vec!["b=2", "a=5"]);
+
+    let globals: Vec<_> = interp.globals().into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    assert_eq!(globals, vec!["y=4", "z=3"]);
+}
+
+#[test]
+fn configure_before_running() {
+    use std::sync::{Arc, Mutex};
+    use canary::logger::Level;
+
+    let src = r#"
+        assert_eq %mode, "test";
+    "#;
+
+    let mut interp = compile(src).unwrap().prepare(Vec::<String>::new()).unwrap();
+    interp.set_global("mode", Value::Str("test".into())).unwrap();
+
+    // Tracing goes to the logger, one line per opcode
+    let seen = Arc::new(Mutex::new(vec![]));
+    let log = seen.clone();
+    interp.set_logger(move |level: Level, message: &str, _: Option<_>| {
+        assert_eq!(level, Level::Debug);
+        log.lock().unwrap().push(message.to_owned());
+    });
+
+    interp.set_trace(true);
+    interp.run().unwrap();
+
+    let trace = seen.lock().unwrap().clone();
+    assert!(trace.len() > 1);
+    assert!(trace[0].starts_with("(top level):0000"), "{:?}", trace[0]);
+    assert!(trace.iter().any(|line| line.contains("assert_eq")));
+    assert!(trace.iter().any(|line| line.contains("top \"test\"")));
+
+    interp.set_trace(false);
+    seen.lock().unwrap().clear();
+    interp.exec("len", &["abc".into()]).unwrap();
+    assert!(seen.lock().unwrap().is_empty());
+}
+
+#[test]
+fn run_after_an_error() {
+    let src = r#"
+        fail();
+
+        sub fail() {
+            return 1 / 0;
+        }
+
+        sub ok() {
+            return 2;
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().prepare(Vec::<String>::new()).unwrap();
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.root().kind(), "DividedByZero");
+
+    // Back at the top level, with nothing left to run there
+    assert_eq!(interp.frames().len(), 1);
+    assert_eq!(interp.function(), None);
+    interp.run().unwrap();
+
+    assert_eq!(interp.call::<Int, _>("ok", ()).unwrap(), 2);
+}
+
+#[test]
+fn profile_counts_calls_and_ops() {
+    let src = r#"
+        twice(1);
+
+        sub twice($x) {
+            return double($x);
+        }
+
+        sub double($x) {
+            return $x * 2;
+        }
+    "#;
+
+    // Unoptimized, the top level runs PUSHI CALL DROP, twice runs LOAD
+    // CALL RET and double runs LOAD PUSHI BINOP RET
+    let plain = canary::Options { optimize: false, ..Default::default() };
+    let module = canary::compile_str_with("profile.cy", src, &plain).unwrap();
+
+    let mut quiet = module.clone().start().unwrap();
+    quiet.exec("twice", &[Value::Int(1)]).unwrap();
+    assert!(quiet.profile().is_empty());
+
+    let mut interp = module.prepare(Vec::<String>::new()).unwrap();
+    interp.set_profile(true);
+    interp.run().unwrap();
+    interp.exec("twice", &[Value::Int(2)]).unwrap();
+
+    let profile = interp.profile();
+    let stats = |name: Option<&str>| profile.iter()
+        .find(|entry| entry.0.as_ref().map(|name| name.as_ref()) == name)
+        .map(|entry| (entry.1.calls, entry.1.ops))
+        .unwrap();
+
+    assert_eq!(profile.len(), 3);
+    assert_eq!(stats(None), (0, 3));
+    assert_eq!(stats(Some("twice")), (2, 6));
+    assert_eq!(stats(Some("double")), (2, 8));
+}
+
+#[test]
+fn capture_printed_output() {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let src = r#"
+        sub greet($name) {
+            print "hello", $name;
+            print [1, 2];
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+    let captured = Captured::default();
+    interp.set_output(captured.clone());
+
+    interp.exec("greet", &["world".into()]).unwrap();
+    let text = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(text, "hello world\n[1, 2]\n");
+
+    // The other output natives write to the same place
+    let src = r#"
+        sub report($n) {
+            print_no_nl "count:";
+            printf " \%d of \%s", $n, "ten";
+            say "";
+            say "done";
+        }
+    "#;
+
+    let mut other = compile(src).unwrap().start().unwrap();
+    let captured = Captured::default();
+    other.set_output(captured.clone());
+
+    other.exec("report", &[3.into()]).unwrap();
+    let text = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(text, "count: 3 of ten\ndone\n");
+
+    // Output that can't be written is an error in the script
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    interp.set_output(Broken);
+    match interp.exec("greet", &["again".into()]).map_err(|e| e.root().kind()) {
+        Err("Io") => (),
+        other => panic!("expected Io, got {:?}", other),
+    }
+}
+
+#[test]
+fn logging_goes_to_the_host() {
+    use std::sync::{Arc, Mutex};
+    use canary::logger::Level;
+
+    let src = r#"
+        sub noisy($x) {
+            warn("careful with $x");
+            log(:info, [1, 2]);
+            log("debug", "quiet");
+            return $x;
+        }
+
+        sub bad() {
+            log(:loud, "?");
+        }
+    "#;
+
+    let mut module = compile(src).unwrap();
+    module.set_source("noisy.cy", src);
+    let mut interp = module.start().unwrap();
+
+    let seen = Arc::new(Mutex::new(vec![]));
+    let log = seen.clone();
+    interp.set_logger(move |level: Level, message: &str, at: Option<_>| {
+        let line = at.map(|at: canary::opcode::Location| at.line);
+        log.lock().unwrap().push((level, message.to_owned(), line));
+    });
+
+    assert_eq!(interp.exec("noisy", &[Value::Int(3)]).unwrap(), Value::Int(3));
+    assert_eq!(*seen.lock().unwrap(), vec![
+        (Level::Warn, "careful with 3".to_owned(), Some(3)),
+        (Level::Info, "[1, 2]".to_owned(), Some(4)),
+        (Level::Debug, "quiet".to_owned(), Some(5)),
+    ]);
+
+    match interp.exec("bad", &[]).map_err(|e| e.root().kind()) {
+        Err("NoSuchLevel") => (),
+        other => panic!("expected NoSuchLevel, got {:?}", other),
+    }
+}
+
+#[test]
+fn debugger_breakpoints_and_stepping() {
+    use std::sync::{Arc, Mutex};
+    use canary::debug::Resume;
+    use canary::eval::Interpreter;
+
+    let src = r#"
+        sub f($x) {
+            my $y = $x * 10;
+            return $y + 1;
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+
+    // Stop at the start of every call to f
+    let seen = Arc::new(Mutex::new(vec![]));
+    let log = seen.clone();
+    interp.set_breakpoint(Some("f"), 0).unwrap();
+    interp.set_debugger(move |interp: &Interpreter| {
+        let func = interp.function().map(|f| f.as_ref().to_owned());
+        log.lock().unwrap().push((func, interp.pc(), interp.local("x")));
+        Resume::Continue
+    }, false);
+
+    interp.exec("f", &[Value::Int(1)]).unwrap();
+    interp.exec("f", &[Value::Int(2)]).unwrap();
+
+    assert_eq!(*seen.lock().unwrap(), vec![
+        (Some("f".to_owned()), 0, Some(Value::Int(1))),
+        (Some("f".to_owned()), 0, Some(Value::Int(2))),
+    ]);
+
+    // Single-step until $y is visible
+    let found = Arc::new(Mutex::new(None));
+    let result = found.clone();
+    interp.set_debugger(move |interp: &Interpreter| {
+        match interp.local("y") {
+            Some(y) => {
+                *result.lock().unwrap() = Some(y);
+                Resume::Continue
+            },
+
+            None => Resume::Step,
+        }
+    }, true);
+
+    assert_eq!(interp.exec("f", &[Value::Int(3)]).unwrap(), Value::Int(31));
+    assert_eq!(*found.lock().unwrap(), Some(Value::Int(30)));
+
+    // Aborting stops the script
+    interp.set_debugger(|_: &Interpreter| Resume::Abort, true);
+    let err = interp.exec("f", &[Value::Int(4)]).unwrap_err();
+    assert_eq!(err.root().kind(), "Interrupted");
+
+    interp.clear_debugger();
+    assert_eq!(interp.exec("f", &[Value::Int(4)]).unwrap(), Value::Int(41));
+}
+
+#[test]
+fn inspect_frames_from_a_native() {
+    use std::sync::{Arc, Mutex};
+
+    let src = "sub outer($a) {\n    my $b = $a + 1;\n    inner($b * 2);\n}\n\nsub inner($c) {\n    snapshot();\n}\n";
+
+    let mut module = compile(src).unwrap();
+    module.set_source("frames.cy", src);
+
+    let seen = Arc::new(Mutex::new(vec![]));
+    let log = seen.clone();
+
+    module.def_native_ctx("snapshot", Argc::Exactly(0), move |interp, _| {
+        for frame in interp.frames() {
+            let func = frame.function_name().map_or("-".to_owned(), |f| f.to_string());
+
+            let locals: Vec<_> = frame.locals().into_iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+
+            let line = frame.location().map_or(0, |loc| loc.line);
+            log.lock().unwrap().push(format!("{}:{} {}", func, line, locals.join(" ")));
+        }
+
+        Ok(Value::Nil(()))
+    }).unwrap();
+
+    let mut interp = module.start().unwrap();
+    interp.exec("outer", &[Value::Int(1)]).unwrap();
+
+    assert_eq!(*seen.lock().unwrap(), vec![
+        "inner:7 c=4",
+        "outer:3 a=1 b=2",
+        "-:0 ",
+    ]);
+}
+
+#[test]
+fn constants_are_shared_across_subs() {
+    let src = r#"
+        sub a() { return ["x", :y, f()]; }
+        sub b() { return ["x", :y, f()]; }
+        sub f() { return "x"; }
+    "#;
+
+    let module = compile(src).unwrap();
+
+    let count = |s: &str| module.constants.strings().iter()
+        .filter(|string| string.as_ref() as &str == s)
+        .count();
+    assert_eq!(count("x"), 1);
+
+    let names: Vec<_> = module.constants.names().iter()
+        .map(|name| name.as_ref().to_owned())
+        .collect();
+    assert_eq!(names.iter().filter(|name| *name == "y").count(), 1);
+    assert_eq!(names.iter().filter(|name| *name == "f").count(), 1);
+}
+
+#[test]
+fn interpreters_move_between_threads() {
+    use std::sync::Arc;
+    use std::thread;
+
+    fn is_send<T: Send>() {}
+    fn is_sync<T: Sync>() {}
+    is_send::<canary::eval::Interpreter>();
+    is_sync::<Module>();
+    is_sync::<Value>();
+
+    let src = r#"
+        %items = [1, 2];
+
+        sub add($x) {
+            my $items = %items;
+            $items[0] = $x;
+            return len($items);
+        }
+    "#;
+
+    let module = compile(src).unwrap();
+    let mut interp = module.start().unwrap();
+
+    let shared = Arc::new(Value::from_slice(&[Value::Int(7)]));
+    let other = shared.clone();
+
+    let (mut interp, len) = thread::spawn(move || {
+        let len = interp.exec("add", &[(*other).clone()]).unwrap();
+        (interp, len)
+    }).join().unwrap();
+
+    assert_eq!(len, Value::Int(2));
+    assert_eq!(format!("{}", interp.global("items").unwrap().unwrap()), "[[7], 2]");
+
+    interp.exec("add", &[Value::Int(4)]).unwrap();
+    assert_eq!(format!("{}", interp.global("items").unwrap().unwrap()), "[4, 2]");
+}
+
+#[test]
+fn spawn_interpreters_over_one_module() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let src = r#"
+        %count = 0;
+
+        sub bump($by) {
+            %count = %count + $by;
+            return %count;
+        }
+    "#;
+
+    let module = Arc::new(compile(src).unwrap());
+
+    let workers: Vec<_> = (1 .. 4).map(|by| {
+        let module = module.clone();
+        thread::spawn(move || {
+            let mut interp = Module::spawn_interpreter(&module).unwrap();
+            interp.exec("bump", &[Value::Int(by)]).unwrap();
+            interp.exec("bump", &[Value::Int(by)]).unwrap()
+        })
+    }).collect();
+
+    let totals: Vec<_> = workers.into_iter()
+        .map(|worker| worker.join().unwrap())
+        .collect();
+    assert_eq!(totals, vec![Value::Int(2), Value::Int(4), Value::Int(6)]);
+
+    // Defining subs through `eval` leaves the shared module alone
+    let mut interp = Module::spawn_interpreter(&module).unwrap();
+    interp.eval("<eval>", "sub extra() { return 1; }").unwrap();
+    assert!(interp.module().functions.keys().any(|name| name.as_ref() == "extra"));
+    assert!(!module.functions.keys().any(|name| name.as_ref() == "extra"));
+}
+
+#[test]
+fn memory_limit_stops_scripts() {
+    let src = r#"
+        sub grow() {
+            my $items = [];
+            try {
+                while 1 {
+                    $items[len($items)] = "item " + len($items);
+                }
+            } catch $e {
+                return :caught;
+            }
+        }
+
+        sub churn() {
+            my $i = 0;
+            while $i < 2000 {
+                my $s = "garbage " + $i;
+                $i = $i + 1;
+            }
+            return $i;
+        }
+
+        sub repeat($n) {
+            return "x" * $n;
+        }
+
+        sub count($n) {
+            return range(1, $n);
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+    interp.set_memory_limit(Some(16 * 1024));
+
+    match interp.exec("grow", &[]).map_err(|e| e.root().kind()) {
+        Err("MemoryLimit") => (),
+        other => panic!("expected MemoryLimit, got {:?}", other),
+    }
+
+    // Values that are gone don't count against the limit
+    assert!(interp.memory_usage() < 16 * 1024);
+    assert_eq!(interp.exec("churn", &[]).unwrap(), Value::Int(2000));
+
+    assert!(interp.exec("repeat", &[Value::Int(1 << 30)]).is_err());
+    assert_eq!(interp.exec("repeat", &[Value::Int(3)]).unwrap(), Value::from(Str::from("xxx")));
+    assert!(interp.exec("count", &[Value::Int(1 << 40)]).is_err());
+
+    interp.set_memory_limit(None);
+    interp.exec("repeat", &[Value::Int(1 << 16)]).unwrap();
+}
+
+#[test]
+fn sandbox_profiles() {
+    use canary::opcode::Profile;
+
+    let has = |module: &Module, name: &str| {
+        module.functions.keys().any(|key| key.as_ref() == name)
+    };
+
+    let pure = Module::stdlib_with(&Profile::pure()).unwrap();
+    assert!(has(&pure, "len") && has(&pure, "sort"));
+    assert!(!has(&pure, "print") && !has(&pure, "clock_ms") && !has(&pure, "env"));
+
+    let timed = Module::stdlib_with(&Profile { time: true, ..Profile::pure() }).unwrap();
+    assert!(has(&timed, "clock_ms") && !has(&timed, "readline"));
+
+    let everything = Module::stdlib_with(&Profile { process: true, ..Profile::standard() }).unwrap();
+    assert!(has(&everything, "run") && has(&everything, "print"));
+
+    let src = r#"
+        sub sum($x, $y) {
+            return $x + $y;
+        }
+
+        sub time() {
+            return :mine;
+        }
+
+        sub shout() {
+            print("hello");
+        }
+    "#;
+
+    let mut module = compile(src).unwrap();
+    module.restrict(&Profile::pure());
+
+    let mut interp = module.clone().start().unwrap();
+    assert_eq!(interp.exec("sum", &[Value::Int(1), Value::Int(2)]).unwrap(), Value::Int(3));
+    assert_eq!(format!("{}", interp.exec("time", &[]).unwrap()), "mine");
+    assert_eq!(interp.exec("shout", &[]).unwrap_err().root().kind(), "NoSuchLabel");
+
+    // Saving and loading keeps the module in its sandbox
+    let mut bytes = vec![];
+    module.serialize(&mut bytes).unwrap();
+    let loaded = Module::deserialize(&mut &bytes[..]).unwrap();
+    assert_eq!(loaded.profile(), Profile::pure());
+    assert!(!has(&loaded, "print") && has(&loaded, "shout"));
+
+    let mut bytes = vec![];
+    everything.serialize(&mut bytes).unwrap();
+    let loaded = Module::deserialize(&mut &bytes[..]).unwrap();
+    assert_eq!(loaded.profile(), everything.profile());
+    assert!(has(&loaded, "run"));
+}
+
+#[test]
+fn opaque_host_values() {
+    use std::sync::Mutex;
+
+    struct Counter(Mutex<Int>);
+
+    let src = r#"
+        my $counter = counter_new();
+        assert_eq typeof($counter), :Opaque;
+        assert is_opaque($counter);
+
+        my $same = [$counter][0];
+        assert_eq $same, $counter;
+        assert_eq bump($counter), 1;
+        assert_eq bump($same), 2;
+        assert $counter ne counter_new();
+
+        try {
+            bump("not a counter");
+            assert 0;
+        } catch $e {
+            assert_eq $e.error, :TypeMismatch;
+        }
+
+        %counter = $counter;
+    "#;
+
+    let mut module = compile(src).unwrap();
+
+    module.def_native("counter_new", Argc::Exactly(0), |_| {
+        Ok(Value::opaque(Counter(Mutex::new(0))))
+    }).unwrap();
+
+    module.def_native("bump", Argc::Exactly(1), |mut args| {
+        let counter = match args.pop().unwrap() {
+            Value::Opaque(obj) => obj.downcast::<Counter>()?,
+            other => return Err(Error::TypeMismatch {
+                expected: "Opaque",
+                found: other.type_name(),
+            }),
+        };
+
+        let mut count = counter.0.lock().unwrap();
+        *count += 1;
+        Ok(Value::Int(*count))
+    }).unwrap();
+
+    let mut interp = module.start().unwrap_or_else(|err| panic!("Error: {}", err));
+
+    let counter = interp.global("counter").unwrap().unwrap();
+    assert_eq!(*counter.downcast_ref::<Counter>().unwrap().0.lock().unwrap(), 2);
+    assert!(counter.downcast_ref::<String>().is_none());
+
+    match counter {
+        Value::Opaque(ref obj) => assert!(obj.downcast::<String>().is_err()),
+        _ => panic!("expected an Opaque value"),
+    }
+}
+
+#[test]
+fn host_object_methods() {
+    use std::sync::Mutex;
+    use canary::ident::Ident;
+
+    struct Connection {
+        log: Mutex<Vec<String>>,
+    }
+
+    impl HostObject for Connection {
+        fn call_method(&self, name: &Ident, args: Vec<Value>) -> Result<Value> {
+            match name.as_ref() {
+                "query" => {
+                    let sql = format!("{}", args[0]);
+                    self.log.lock().unwrap().push(sql.clone());
+                    Ok(Value::from_slice(&[Value::from(Str::from(sql))]))
+                },
+
+                "count" => Ok(Value::Int(self.log.lock().unwrap().len() as Int)),
+
+                _ => Err(Error::NoSuchMethod {
+                    method: name.clone(),
+                    found: "Connection",
+                }),
+            }
+        }
+    }
+
+    let src = r#"
+        my $conn = connect();
+        assert_eq $conn.query("select 1"), ["select 1"];
+        assert_eq $conn.query("select " + 2)[0], "select 2";
+        assert_eq $conn.count(), 2;
+
+        try {
+            $conn.drop_tables();
+            assert 0;
+        } catch $e {
+            assert_eq $e.error, :NoSuchMethod;
+        }
+
+        try {
+            my $rec = { name: "x" };
+            $rec.name();
+            assert 0;
+        } catch $e {
+            assert_eq $e.error, :NoSuchMethod;
+        }
+    "#;
+
+    let mut module = compile(src).unwrap();
+
+    module.def_native("connect", Argc::Exactly(0), |_| {
+        Ok(Value::host(Connection { log: Mutex::new(vec![]) }))
+    }).unwrap();
+
+    module.start().unwrap_or_else(|err| panic!("Error: {}", err));
+}
+
+#[test]
+fn typed_natives() {
+    let src = r#"
+        assert_eq repeat("ab", 3, "-"), "ab-ab-ab";
+        assert_eq answer(), 42;
+        assert_eq describe([1, 2]), "List";
+
+        sub bad_type() {
+            return repeat("ab", "three", "-");
+        }
+
+        sub bad_count() {
+            return repeat("ab", 3);
+        }
+
+        sub caught() {
+            try {
+                bad_type();
+            } catch $e {
+                return $e.error;
+            }
+        }
+    "#;
+
+    let mut module = compile(src).unwrap();
+
+    module.def_fn("repeat", |text: Str, count: Int, sep: Str| {
+        let items = vec![text.as_ref(); count as usize];
+        Ok(Str::from(items.join(&sep)))
+    }).unwrap();
+
+    module.def_fn("answer", || Ok(42)).unwrap();
+
+    module.def_fn("describe", |value: Value| Ok(Str::from(value.type_name()))).unwrap();
+
+    let mut interp = module.start().unwrap_or_else(|err| panic!("Error: {}", err));
+
+    let err = interp.exec("bad_type", &[]).unwrap_err();
+    assert_eq!(err.root().kind(), "TypeMismatch");
+    assert!(err.to_string().contains("argument 2 of repeat: expected Int, found Str"), "{}", err);
+
+    let err = interp.exec("bad_count", &[]).unwrap_err();
+    assert_eq!(err.root().kind(), "WrongArgc");
+
+    assert_eq!(format!("{}", interp.exec("caught", &[]).unwrap()), "TypeMismatch");
+}
+
+#[test]
+fn rust_conversions() {
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    let src = r#"
+        assert_eq total([1, 2, 3]), 6;
+        assert_eq greet("sam"), "hello sam";
+        assert_eq greet(nothing()), "hello nobody";
+
+        sub pair($name, $n) {
+            return { name: $name, items: [$n, $n * 2] };
+        }
+
+        sub echo($x) {
+            return $x;
+        }
+
+        sub nothing() {
+        }
+    "#;
+
+    let mut module = compile(src).unwrap();
+
+    module.def_fn("total", |items: Vec<Int>| Ok(items.iter().sum::<Int>())).unwrap();
+
+    module.def_fn("greet", |name: Option<String>| {
+        Ok(format!("hello {}", name.unwrap_or_else(|| "nobody".into())))
+    }).unwrap();
+
+    let mut interp = module.start().unwrap_or_else(|err| panic!("Error: {}", err));
+
+    let result = interp.exec("pair", &["x".into(), 2.into()]).unwrap();
+    let mut rec = HashMap::<String, Value>::try_from(result).unwrap();
+    assert_eq!(String::try_from(rec.remove("name").unwrap()).unwrap(), "x");
+    assert_eq!(Vec::<Int>::try_from(rec.remove("items").unwrap()).unwrap(), vec![2, 4]);
+
+    let echo = |interp: &mut canary::eval::Interpreter, value: Value| {
+        interp.exec("echo", &[value]).unwrap()
+    };
+
+    assert!(bool::try_from(echo(&mut interp, true.into())).unwrap());
+    assert_eq!(f64::try_from(echo(&mut interp, 1.5.into())).unwrap(), 1.5);
+    assert_eq!(echo(&mut interp, String::from("s").into()), Value::from("s"));
+    assert_eq!(echo(&mut interp, None::<Int>.into()), Value::Nil(()));
+    assert_eq!(echo(&mut interp, Some(3).into()), Value::Int(3));
+    assert_eq!(format!("{}", echo(&mut interp, vec!["a", "b"].into())), "[a, b]");
+
+    let mut map = HashMap::new();
+    map.insert("k".to_owned(), vec![1.0]);
+    assert_eq!(format!("{}", echo(&mut interp, map.into())), "{ k: [1.0] }");
+
+    assert!(!bool::try_from(Value::Int(0)).unwrap());
+    assert!(bool::try_from(Value::from(vec!["x"])).unwrap());
+    assert!(String::try_from(Value::Int(1)).is_err());
+    assert!(Vec::<Int>::try_from(Value::from(vec!["x"])).is_err());
+}
+
+#[test]
+fn typed_calls() {
+    let src = r#"
+        sub score($player, $bonus) {
+            return len($player.name) * 10 + $bonus;
+        }
+
+        sub names() {
+            return ["a", "b"];
+        }
+
+        sub shout($text) {
+            return uc($text);
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+
+    let player = Record::default();
+    player.borrow_mut().insert(Str::from("name"), "sam".into());
+
+    let n: Int = interp.call("score", (player.clone(), 5)).unwrap();
+    assert_eq!(n, 35);
+
+    let n: Int = interp.call("score", Args::new().arg(player).arg(1)).unwrap();
+    assert_eq!(n, 31);
+
+    let names: Vec<String> = interp.call("names", ()).unwrap();
+    assert_eq!(names, vec!["a", "b"]);
+
+    let text: String = interp.call("shout", vec![Value::from("hi")]).unwrap();
+    assert_eq!(text, "HI");
+
+    let err = interp.call::<Int, _>("shout", ("hi",)).unwrap_err();
+    assert_eq!(err.root().kind(), "TypeMismatch");
+    assert!(err.to_string().contains("result of shout"), "{}", err);
+}
+
+#[test]
+fn read_globals_from_the_host() {
+    let src = r#"
+        %score = 12;
+        %name = "canary";
+        %tags = ["a", "b"];
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+
+    assert_eq!(interp.get_global::<Int>("score").unwrap(), Some(12));
+    assert_eq!(interp.get_global::<String>("name").unwrap(), Some("canary".to_owned()));
+    assert_eq!(interp.get_global::<Vec<String>>("tags").unwrap().unwrap(), vec!["a", "b"]);
+    assert_eq!(interp.get_global::<Int>("missing").unwrap(), None);
+
+    let err = interp.get_global::<Int>("name").unwrap_err();
+    assert_eq!(err.root().kind(), "TypeMismatch");
+    assert!(err.to_string().contains("global %name"), "{}", err);
+
+    let names: Vec<_> = interp.globals_iter()
+        .map(|(name, _)| name.to_string())
+        .collect();
+    assert_eq!(names, vec!["name", "score", "tags"]);
+}
+
+#[test]
+fn host_events() {
+    let mut interp = canary::compile("tests/events.cy").unwrap().start().unwrap();
+
+    let results = interp.emit("tick", (16,)).unwrap();
+    assert_eq!(results[0], Value::Int(1));
+    interp.emit("tick", (17,)).unwrap();
+
+    assert_eq!(interp.emit("unheard", ()).unwrap(), vec![]);
+
+    interp.emit("quit", ()).unwrap();
+    assert!(interp.emit("tick", (18,)).unwrap().is_empty());
+
+    assert_eq!(interp.get_global::<Int>("ticks").unwrap(), Some(2));
+    assert_eq!(interp.get_global::<Vec<String>>("log").unwrap().unwrap(),
+               vec!["tick 16", "tick 17", "quit"]);
+}
+
+#[test]
+fn run_in_slices() {
+    use canary::eval::Progress;
+
+    let src = r#"
+        %n = 0;
+        while %n < 100 {
+            %n = %n + 1;
+        }
+
+        sub count($to) {
+            my $i = 0;
+            while $i < $to {
+                $i = $i + 1;
+            }
+            return $i;
+        }
+
+        sub fail() {
+            my $i = 0;
+            while $i < 10 {
+                $i = $i + 1;
+            }
+            return 1 / 0;
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().prepare(Vec::<String>::new()).unwrap();
+
+    let mut slices = 1;
+    while interp.run_until(50).unwrap() == Progress::Paused {
+        slices += 1;
+    }
+
+    assert!(slices > 5, "took {} slices", slices);
+    assert_eq!(interp.get_global::<Int>("n").unwrap(), Some(100));
+    assert_eq!(interp.run_until(50).unwrap(), Progress::Finished(Value::Nil(())));
+
+    interp.begin_call("count", (30,)).unwrap();
+    assert_eq!(interp.run_until(20).unwrap(), Progress::Paused);
+
+    let result = loop {
+        match interp.run_until(20).unwrap() {
+            Progress::Paused => continue,
+            Progress::Finished(value) => break value,
+        }
+    };
+    assert_eq!(result, Value::Int(30));
+
+    // Natives finish straight away
+    interp.begin_call("len", ("abc",)).unwrap();
+    assert_eq!(interp.run_until(0).unwrap(), Progress::Finished(Value::Int(3)));
+
+    interp.begin_call("fail", ()).unwrap();
+    let err = interp.run_until(1000).unwrap_err();
+    assert_eq!(err.root().kind(), "DividedByZero");
+
+    // The interpreter is left ready for more
+    assert_eq!(interp.call::<Int, _>("count", (3,)).unwrap(), 3);
+    assert!(interp.frames().len() == 1);
+}
+
+#[test]
+fn run_in_slices_after_an_error() {
+    use canary::eval::Progress;
+
+    let src = r#"
+        %before = 1;
+        fail();
+        %after = 1;
+
+        sub fail() {
+            return 1 / 0;
+        }
+
+        sub ok() {
+            return 2;
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().prepare(Vec::<String>::new()).unwrap();
+
+    let err = loop {
+        match interp.run_until(5) {
+            Ok(Progress::Paused) => continue,
+            Ok(done) => panic!("finished with {:?}", done),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err.root().kind(), "DividedByZero");
+
+    // Back at the top level, with nothing left to run there
+    assert_eq!(interp.frames().len(), 1);
+    assert_eq!(interp.function(), None);
+    assert_eq!(interp.run_until(5).unwrap(), Progress::Finished(Value::Nil(())));
+    assert_eq!(interp.get_global::<Int>("before").unwrap(), Some(1));
+    assert_eq!(interp.get_global::<Int>("after").unwrap(), None);
+
+    assert_eq!(interp.call::<Int, _>("ok", ()).unwrap(), 2);
+}
+
+#[test]
+#[cfg(feature = "bigint")]
+fn bigint_values() {
+    use std::convert::TryFrom;
+    use num_bigint::BigInt;
+
+    let src = r#"
+        sub square($n) {
+            return $n * $n;
+        }
+    "#;
+
+    let mut interp = compile(src).unwrap().start().unwrap();
+
+    let big = interp.call::<BigInt, _>("square", (Int::MAX,)).unwrap();
+    assert_eq!(big, BigInt::from(Int::MAX) * Int::MAX);
+
+    let value = Value::from(big.clone());
+    assert_eq!(value.type_name(), "BigInt");
+    assert_eq!(value.to_string(), big.to_string());
+    assert_eq!(value, Value::from(BigInt::from(Int::MAX) * Int::MAX));
+
+    // Values that fit come back as Ints
+    assert_eq!(Value::from(BigInt::from(7)), Value::Int(7));
+    assert_eq!(interp.call::<BigInt, _>("square", (3,)).unwrap(), BigInt::from(9));
+    assert_eq!(BigInt::try_from(Value::Int(-2)).unwrap(), BigInt::from(-2));
+    assert!(Int::try_from(value).is_err());
+    assert!(BigInt::try_from(Value::from("1")).is_err());
+}
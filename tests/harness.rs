@@ -2,10 +2,14 @@ extern crate canary;
 
 macro_rules! generate {
     ( $name:ident ) => {
+        generate!($name, canary::Options::default());
+    };
+
+    ( $name:ident, $options:expr ) => {
         #[test]
         fn $name() {
             let path = format!("tests/{}.cy", stringify!($name));
-            canary::compile(path)
+            canary::compile_with(path, &$options)
                 .and_then(|env| env.start())
                 .unwrap_or_else(|err| {
                     println!("Error: {}", err);
@@ -24,3 +28,43 @@ generate!(variables);
 generate!(truthiness);
 generate!(scopes);
 generate!(strings);
+generate!(for_loops);
+generate!(foreach_loops);
+generate!(floats);
+generate!(comparisons);
+generate!(modulo);
+generate!(compound_assignment);
+generate!(exceptions);
+generate!(higher_order);
+generate!(split);
+generate!(string_natives);
+generate!(sprintf);
+generate!(sort);
+generate!(clone);
+generate!(types);
+generate!(time);
+generate!(process);
+generate!(nested_assignment);
+generate!(field_access);
+generate!(destructuring);
+generate!(multiple_returns);
+generate!(default_params);
+generate!(variadic);
+generate!(namespaces);
+generate!(imports);
+generate!(cycles);
+generate!(events);
+// Overflow widens to BigInt with the bigint feature, rather than failing
+#[cfg(not(feature = "bigint"))]
+generate!(integers);
+#[cfg(feature = "bigint")]
+generate!(bigint);
+generate!(newlines, canary::Options { newlines: true, ..Default::default() });
+generate!(unless_until);
+generate!(postfix);
+generate!(given);
+generate!(coalesce);
+generate!(get);
+generate!(slices);
+generate!(equality);
+generate!(short_circuit);